@@ -0,0 +1,124 @@
+//! Roles of callers, the permission each route needs and the admin API
+//! managing roles.
+//!
+//! `GET /admin/roles` maps public keys to the roles on their user entries;
+//! `POST /admin/roles` with e.g. `{"<public key>":"bot"}` changes them;
+//! the keys must belong to registered users, or nothing is changed. The
+//! configured admin is always an admin.
+
+use crate::auth::Caller;
+use crate::{require_admin, ApiError, AppState};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::Method;
+use axum::{Extension, Json};
+use entity::Role;
+use rustr_core::access::Permission;
+use std::collections::BTreeMap;
+
+pub(crate) async fn role_of(state: &AppState, public_key: &str) -> Role {
+    if state.config.auth.is_admin(public_key) {
+        return Role::Admin;
+    }
+    let users = state.user_repo.lock().await;
+    users
+        .read_user(public_key)
+        .map_or(state.config.access.default_role, |u| u.role)
+}
+
+/// The permission a request to `path` needs.
+pub(crate) fn permission(method: &Method, path: &str) -> Permission {
+    if path.starts_with("/admin/") {
+        Permission::Admin
+    } else if matches!(*method, Method::GET | Method::HEAD) {
+        Permission::Read
+    } else if path.starts_with("/notifications") {
+        // marking notifications as read publishes nothing
+        Permission::Read
+    } else if path == "/users" {
+        Permission::EditProfile
+    } else {
+        Permission::Publish
+    }
+}
+
+pub async fn read_roles(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+) -> Result<Json<BTreeMap<String, Role>>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let users = state.user_repo.lock().await;
+    let roles = users
+        .read_all_users()
+        .into_iter()
+        .map(|u| (u.public_key, u.role))
+        .collect();
+    Ok(Json(roles))
+}
+
+pub async fn set_roles(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<BTreeMap<String, Role>>, JsonRejection>,
+) -> Result<Json<BTreeMap<String, Role>>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Json(roles) = payload?;
+    let mut users = state.user_repo.lock().await;
+    if let Some(unknown) = roles.keys().find(|pk| users.read_user(pk).is_none()) {
+        return Err(ApiError::NotFound(format!("no user with key {}", unknown)));
+    }
+    for (public_key, role) in &roles {
+        let mut user = users.read_user(public_key).cloned().expect("checked");
+        user.role = *role;
+        users.add_user(user);
+    }
+    Ok(Json(roles))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use entity::User;
+
+    #[tokio::test]
+    async fn test_set_roles() {
+        let state = AppState::in_memory(Config::default());
+        let alice = "a".repeat(64);
+        let user = User::new("alice".to_string(), alice.clone());
+        state.user_repo.lock().await.add_user(user);
+        let admin = Extension(Caller {
+            public_key: "f".repeat(64),
+            session: None,
+            role: Role::Admin,
+        });
+        let set = |roles: &[(&String, Role)]| {
+            let roles = roles.iter().map(|(pk, r)| (pk.to_string(), *r)).collect();
+            set_roles(Some(admin.clone()), State(state.clone()), Ok(Json(roles)))
+        };
+        let unknown = "b".repeat(64);
+        let res = set(&[(&alice, Role::Bot), (&unknown, Role::Bot)]).await;
+        assert!(matches!(res, Err(ApiError::NotFound(_))));
+        assert_eq!(role_of(&state, &alice).await, Role::Member);
+        assert!(state.user_repo.lock().await.read_user(&unknown).is_none());
+        let Json(roles) = set(&[(&alice, Role::Bot)]).await.unwrap();
+        assert_eq!(roles.get(&alice), Some(&Role::Bot));
+        assert_eq!(role_of(&state, &alice).await, Role::Bot);
+    }
+
+    #[test]
+    fn test_permission() {
+        assert_eq!(permission(&Method::GET, "/events"), Permission::Read);
+        assert_eq!(permission(&Method::POST, "/events"), Permission::Publish);
+        assert_eq!(permission(&Method::POST, "/users"), Permission::EditProfile);
+        assert_eq!(
+            permission(&Method::POST, "/notifications/seen"),
+            Permission::Read
+        );
+        assert_eq!(permission(&Method::GET, "/admin/bans"), Permission::Admin);
+        assert_eq!(
+            permission(&Method::DELETE, "/admin/allow"),
+            Permission::Admin
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! `GET /articles/:pubkey/:d`: the latest version of an article (see
+//! [`entity::article`]) as an HTML page. The Markdown is rendered and then
+//! sanitized, so scripts, event handlers and other active content the
+//! author wrote never reach the reader.
+
+use crate::auth::Caller;
+use crate::circles::viewer;
+use crate::follows::key_forms;
+use crate::{ApiError, AppState};
+use axum::extract::rejection::PathRejection;
+use axum::extract::{Path, State};
+use axum::response::Html;
+use axum::Extension;
+use chrono::{TimeZone, Utc};
+use entity::article::Article;
+use entity::{kind, Filter};
+use pulldown_cmark::{html, Options, Parser};
+use rustr_core::circle::Audience;
+use rustr_core::repository::EventRepo;
+
+pub async fn read_article(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<(String, String)>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Html<String>, ApiError> {
+    let Path((public_key, d)) = path?;
+    let filter = Filter {
+        authors: Some(key_forms(&public_key)),
+        kinds: Some(vec![kind::LONG_FORM]),
+        limit: Some(1),
+        ..Filter::new().with_tag("d", vec![d.clone()])
+    };
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let latest = Audience::new(&mut **events, &**circles, viewer(&caller))
+        .query(&filter)
+        .pop();
+    let Some(e) = latest else {
+        return Err(ApiError::NotFound(format!(
+            "{} has no article {}",
+            public_key, d
+        )));
+    };
+    let article = Article::from_event(&e).map_err(ApiError::Invalid)?;
+    Ok(Html(page(&article, e.created_at)))
+}
+
+/// Renders Markdown to HTML without anything that could run in the reader's
+/// browser.
+fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    ammonia::clean(&unsafe_html)
+}
+
+/// A standalone page for `article`, last edited at `updated_at`.
+fn page(article: &Article, updated_at: u64) -> String {
+    let text = ammonia::clean_text;
+    let date = |t: u64| {
+        Utc.timestamp_opt(t as i64, 0)
+            .single()
+            .map_or_else(String::new, |t| t.format("%Y-%m-%d").to_string())
+    };
+    let published = article.published_at.unwrap_or(updated_at);
+    let mut head = format!("<title>{}</title>\n", text(&article.title));
+    if let Some(summary) = &article.summary {
+        head += &format!(
+            "<meta name=\"description\" content=\"{}\">\n",
+            text(summary)
+        );
+    }
+    let mut header = format!(
+        "<h1>{}</h1>\n<p><time datetime=\"{}\">{}</time>",
+        text(&article.title),
+        date(published),
+        date(published)
+    );
+    if date(updated_at) != date(published) {
+        header += &format!(", updated {}", date(updated_at));
+    }
+    header += "</p>\n";
+    if !article.hashtags.is_empty() {
+        let tags: Vec<_> = article
+            .hashtags
+            .iter()
+            .map(|t| format!("#{}", text(t)))
+            .collect();
+        header += &format!("<p>{}</p>\n", tags.join(" "));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n{}</head>\n\
+         <body>\n<article>\n<header>\n{}</header>\n{}</article>\n</body>\n</html>\n",
+        head,
+        header,
+        render(&article.content)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let html = render(
+            "# Title\n\n**bold** [link](https://example.com) \
+             [x](javascript:alert(1))\n\n<script>alert(1)</script>\n\n\
+             <img src=\"a.png\" onerror=\"alert(1)\">\n",
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(!html.contains("script"));
+        assert!(!html.contains("javascript"));
+        assert!(!html.contains("onerror"));
+
+        let article = Article {
+            title: "<b>Hi</b>".to_string(),
+            published_at: Some(0),
+            ..Article::default()
+        };
+        let page = page(&article, 86400);
+        assert!(page.contains("<title>&lt;b&gt;Hi&lt;&#47;b&gt;</title>"));
+        assert!(page.contains("1970-01-01</time>, updated 1970-01-02"));
+    }
+}
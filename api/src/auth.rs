@@ -0,0 +1,258 @@
+//! Who is calling: a session token or a signed request.
+//!
+//! Besides `Authorization: Bearer <session id>`, a request may carry
+//! `Authorization: Nostr <base64 event>` (NIP-98): an event of kind
+//! [`kind::HTTP_AUTH`] signed by the caller, whose `u` and `method` tags name
+//! this very request and whose `payload` tag, required for requests with a
+//! body, holds the SHA-256 of the body. It must have been created within
+//! `auth.window` seconds of now and is accepted only once.
+
+use crate::moderation::moderate;
+use crate::{access, ratelimit};
+use crate::{ApiError, AppState};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, Method, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::Engine;
+use entity::{kind, Event, Role};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// The authenticated caller, available to handlers as a request extension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub public_key: String,
+    /// The session the request was made with, if any.
+    pub session: Option<String>,
+    pub role: Role,
+}
+
+pub enum Credentials<'a> {
+    Bearer(&'a str),
+    Nostr(&'a str),
+}
+
+/// The credentials of the `Authorization` header, `None` without one.
+pub fn credentials(headers: &HeaderMap) -> Result<Option<Credentials<'_>>, ApiError> {
+    let Some(value) = headers.get(header::AUTHORIZATION) else {
+        return Ok(None);
+    };
+    let malformed = || ApiError::Unauthorized("malformed authorization header".to_string());
+    let (scheme, value) = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.split_once(' '))
+        .ok_or_else(malformed)?;
+    match scheme {
+        "Bearer" => Ok(Some(Credentials::Bearer(value.trim()))),
+        "Nostr" => Ok(Some(Credentials::Nostr(value.trim()))),
+        _ => Err(malformed()),
+    }
+}
+
+/// Ids of recently accepted authorization events with their `created_at`.
+#[derive(Default)]
+pub struct ReplayGuard {
+    seen: Mutex<HashMap<String, u64>>,
+}
+
+impl ReplayGuard {
+    /// Records `id`; false if it was seen before. Entries older than
+    /// `window` are forgotten, as their events are refused anyway.
+    pub fn first_use(&self, id: &str, created_at: u64, now: u64, window: u64) -> bool {
+        let mut seen = self.seen.lock().expect("replay guard poisoned");
+        seen.retain(|_, t| now.saturating_sub(*t) <= window);
+        seen.insert(id.to_string(), created_at).is_none()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Decodes the event of a `Nostr` authorization without checking it.
+pub fn decode(token: &str) -> Result<Event, ApiError> {
+    let json = base64::engine::general_purpose::STANDARD
+        .decode(token)
+        .map_err(|_| ApiError::Unauthorized("authorization is not base64".to_string()))?;
+    serde_json::from_slice(&json)
+        .map_err(|e| ApiError::Unauthorized(format!("invalid authorization event: {}", e)))
+}
+
+/// Checks a signed authorization against the request it came with and
+/// returns the signer.
+fn verify(
+    state: &AppState,
+    e: &Event,
+    method: &Method,
+    host: Option<&str>,
+    path: &str,
+    body: &[u8],
+) -> Result<String, ApiError> {
+    let deny = |msg: &str| Err(ApiError::Unauthorized(msg.to_string()));
+    if e.kind != kind::HTTP_AUTH {
+        return deny("authorization event has the wrong kind");
+    }
+    if !e.verify() {
+        return deny("invalid authorization signature");
+    }
+    let now = now();
+    let window = state.config.auth.window;
+    if e.created_at.abs_diff(now) > window {
+        return deny("authorization event is too old or in the future");
+    }
+    if !e
+        .tag_values("method")
+        .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+    {
+        return deny("authorization is for another method");
+    }
+    if !e.tag_values("u").any(|u| url_matches(u, host, path)) {
+        return deny("authorization is for another URL");
+    }
+    let payload = e.tag_values("payload").next();
+    if (!body.is_empty() || payload.is_some()) && payload != Some(crypto::hash_bytes(body).as_str())
+    {
+        return deny("authorization does not match the body");
+    }
+    if !state.replays.first_use(&e.id, e.created_at, now, window) {
+        return deny("authorization was already used");
+    }
+    Ok(e.public_key.clone())
+}
+
+/// Whether the absolute `url` is `path` on `host`; the scheme is ignored as
+/// TLS is often terminated in front of the relay. Without a host to compare
+/// with, nothing matches.
+fn url_matches(url: &str, host: Option<&str>, path: &str) -> bool {
+    let Some(rest) = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let (authority, url_path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    host.is_some_and(|h| h.eq_ignore_ascii_case(authority)) && url_path == path
+}
+
+/// Resolves the caller of every request behind it and applies moderation
+/// and the caller's role. Anonymous requests only pass if `auth.required` is
+/// off and then have the default role.
+pub async fn require_auth(
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, ApiError> {
+    let (mut parts, mut body) = request.into_parts();
+    let caller = match credentials(&parts.headers)? {
+        Some(Credentials::Bearer(token)) => match state.session_repo.lock().await.read(token) {
+            Some(session) => Some(Caller {
+                public_key: session.public_key().to_string(),
+                session: Some(token.to_string()),
+                role: Role::default(),
+            }),
+            None => {
+                return Err(ApiError::Unauthorized(
+                    "unknown or expired session".to_string(),
+                ))
+            }
+        },
+        Some(Credentials::Nostr(token)) => {
+            let e = decode(token)?;
+            let limit = state.config.limits.max_body_bytes;
+            let limited = http_body::Limited::new(std::mem::take(&mut body), limit);
+            let bytes = hyper::body::to_bytes(limited)
+                .await
+                .map_err(|_| ApiError::TooLarge("request body is too large".to_string()))?;
+            // HTTP/2 requests name the host in the URI instead
+            let host = parts
+                .headers
+                .get(header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .or_else(|| parts.uri.authority().map(|a| a.as_str()));
+            let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+            let public_key = verify(&state, &e, &parts.method, host, path, &bytes)?;
+            body = Body::from(bytes);
+            Some(Caller {
+                public_key,
+                session: None,
+                role: Role::default(),
+            })
+        }
+        None if state.config.auth.required => {
+            return Err(ApiError::Unauthorized(
+                "missing authorization header".to_string(),
+            ))
+        }
+        None => None,
+    };
+    if let Some(caller) = &caller {
+        ratelimit::charge_caller(&state, &parts.method, parts.uri.path(), caller)?;
+    }
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let write = !matches!(parts.method, Method::GET | Method::HEAD);
+    let pubkey = caller.as_ref().map(|c| c.public_key.as_str());
+    moderate(&state, pubkey, ip, write).await?;
+    let role = match &caller {
+        Some(caller) => access::role_of(&state, &caller.public_key).await,
+        None => state.config.access.default_role,
+    };
+    state
+        .config
+        .access
+        .check(role, access::permission(&parts.method, parts.uri.path()))
+        .map_err(ApiError::Forbidden)?;
+    if let Some(caller) = caller {
+        parts.extensions.insert(Caller { role, ..caller });
+    }
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_matches() {
+        let url = "https://relay.example.com/events?kinds=1";
+        assert!(url_matches(
+            url,
+            Some("relay.example.com"),
+            "/events?kinds=1"
+        ));
+        assert!(!url_matches(url, None, "/events?kinds=1"));
+        assert!(!url_matches(
+            url,
+            Some("evil.example.com"),
+            "/events?kinds=1"
+        ));
+        assert!(!url_matches(url, Some("relay.example.com"), "/events"));
+        assert!(url_matches(
+            "http://localhost:3000",
+            Some("localhost:3000"),
+            "/"
+        ));
+        assert!(!url_matches("ftp://localhost/", Some("localhost"), "/"));
+    }
+
+    #[test]
+    fn test_replay_guard() {
+        let guard = ReplayGuard::default();
+        assert!(guard.first_use("a", 100, 100, 60));
+        assert!(!guard.first_use("a", 100, 150, 60));
+        assert!(guard.first_use("b", 200, 200, 60));
+        // "a" has left the window and was forgotten
+        assert!(guard.first_use("a", 100, 200, 60));
+    }
+}
@@ -0,0 +1,217 @@
+//! Circles (see [`entity::circle`]) and the events addressed to them.
+//!
+//! `GET /circles` lists the circles the caller owns or belongs to, and
+//! `POST /circles` with `{"name": ..., "members": [...]}` creates one owned
+//! by the caller. `GET /circles/:id` and `GET /circles/:id/events` (taking
+//! the filter parameters of `/events`) answer members only; to anyone else
+//! the circle does not exist. The owner deletes the circle with
+//! `DELETE /circles/:id` and manages members by posting or deleting
+//! `{"members": [...]}` at `/circles/:id/members`, where a member may
+//! also remove themselves.
+
+use crate::auth::Caller;
+use crate::config::is_public_key;
+use crate::{filter_from_params, ApiError, AppState};
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::{Extension, Json};
+use entity::circle::Circle;
+use entity::Event;
+use rustr_core::circle::{Audience, CircleRepo};
+use rustr_core::repository::EventRepo;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::{event, Level};
+
+#[derive(Debug, Deserialize)]
+pub struct NewCircle {
+    name: String,
+    #[serde(default)]
+    members: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Members {
+    members: Vec<String>,
+}
+
+/// The public key of the caller, who has to be authenticated to see or
+/// manage circles.
+pub(crate) fn viewer(caller: &Option<Extension<Caller>>) -> Option<&str> {
+    caller.as_ref().map(|Extension(c)| c.public_key.as_str())
+}
+
+fn caller(caller: Option<Extension<Caller>>) -> Result<Caller, ApiError> {
+    match caller {
+        Some(Extension(caller)) => Ok(caller),
+        None => Err(ApiError::Unauthorized("authorization required".to_string())),
+    }
+}
+
+fn check_keys(members: &[String]) -> Result<(), ApiError> {
+    match members.iter().find(|pk| !is_public_key(pk)) {
+        Some(pk) => Err(ApiError::BadRequest(format!("invalid public key {}", pk))),
+        None => Ok(()),
+    }
+}
+
+/// The circle `id` if `public_key` may see it.
+fn visible_circle(
+    circles: &dyn CircleRepo,
+    id: &str,
+    public_key: &str,
+) -> Result<Circle, ApiError> {
+    circles
+        .read(id)
+        .filter(|c| c.includes(public_key))
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(format!("circle {} not found", id)))
+}
+
+/// The circle `id` if `public_key` owns it.
+fn owned_circle(circles: &dyn CircleRepo, id: &str, public_key: &str) -> Result<Circle, ApiError> {
+    let circle = visible_circle(circles, id, public_key)?;
+    match circle.is_owner(public_key) {
+        true => Ok(circle),
+        false => Err(ApiError::Forbidden(format!(
+            "only the owner may change circle {}",
+            id
+        ))),
+    }
+}
+
+/// Rejects `e` if it is addressed to a circle its author is not part of.
+pub(crate) async fn check_audience(state: &AppState, e: &Event) -> Result<(), ApiError> {
+    let Some(id) = entity::circle::audience(e) else {
+        return Ok(());
+    };
+    let circles = state.circles.lock().await;
+    match circles.read(id) {
+        Some(circle) if circle.includes(&e.public_key) => Ok(()),
+        Some(_) => Err(ApiError::Forbidden(format!(
+            "{} is not a member of circle {}",
+            e.public_key, id
+        ))),
+        None => Err(ApiError::Invalid(format!("circle {} does not exist", id))),
+    }
+}
+
+pub async fn read_circles(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Circle>>, ApiError> {
+    let caller = self::caller(caller)?;
+    let circles = state.circles.lock().await;
+    Ok(Json(circles.read_for(&caller.public_key)))
+}
+
+pub async fn create_circle(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<NewCircle>, JsonRejection>,
+) -> Result<(StatusCode, Json<Circle>), ApiError> {
+    let caller = self::caller(caller)?;
+    let Json(new) = payload?;
+    check_keys(&new.members)?;
+    let mut circle = Circle::new(&caller.public_key, &new.name).map_err(ApiError::BadRequest)?;
+    circle.add(&new.members);
+    let mut circles = state.circles.lock().await;
+    if circles.read(&circle.id()).is_some() {
+        return Err(ApiError::Duplicate(format!(
+            "circle {} already exists",
+            circle.id()
+        )));
+    }
+    circles.save(circle.clone())?;
+    event!(
+        Level::INFO,
+        "create circle {} with {} members",
+        circle.id(),
+        circle.members.len()
+    );
+    Ok((StatusCode::CREATED, Json(circle)))
+}
+
+pub async fn read_circle(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Circle>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Path(id) = path?;
+    let circles = state.circles.lock().await;
+    Ok(Json(visible_circle(&**circles, &id, &caller.public_key)?))
+}
+
+pub async fn delete_circle(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let caller = self::caller(caller)?;
+    let Path(id) = path?;
+    let mut circles = state.circles.lock().await;
+    owned_circle(&**circles, &id, &caller.public_key)?;
+    circles.delete(&id)?;
+    event!(Level::INFO, "delete circle {}", id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn add_members(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    State(state): State<AppState>,
+    payload: Result<Json<Members>, JsonRejection>,
+) -> Result<Json<Circle>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Path(id) = path?;
+    let Json(Members { members }) = payload?;
+    check_keys(&members)?;
+    let mut circles = state.circles.lock().await;
+    let mut circle = owned_circle(&**circles, &id, &caller.public_key)?;
+    if circle.add(&members) {
+        circles.save(circle.clone())?;
+    }
+    Ok(Json(circle))
+}
+
+pub async fn remove_members(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    State(state): State<AppState>,
+    payload: Result<Json<Members>, JsonRejection>,
+) -> Result<Json<Circle>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Path(id) = path?;
+    let Json(Members { members }) = payload?;
+    let mut circles = state.circles.lock().await;
+    let leaving = members
+        .iter()
+        .all(|pk| crypto::x_only(pk) == crypto::x_only(&caller.public_key));
+    let mut circle = match leaving {
+        true => visible_circle(&**circles, &id, &caller.public_key)?,
+        false => owned_circle(&**circles, &id, &caller.public_key)?,
+    };
+    if circle.remove(&members) {
+        circles.save(circle.clone())?;
+    }
+    Ok(Json(circle))
+}
+
+pub async fn read_circle_events(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Event>>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Path(id) = path?;
+    let Query(params) = query?;
+    let filter = filter_from_params(&params)?.with_tag("circle", vec![id.clone()]);
+    let circles = state.circles.lock().await;
+    visible_circle(&**circles, &id, &caller.public_key)?;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, Some(&caller.public_key));
+    Ok(Json(events.query(&filter)))
+}
@@ -0,0 +1,580 @@
+//! Server configuration.
+//!
+//! Settings are layered: built-in defaults, then the TOML file, then
+//! `RUSTR_*` environment variables, then command-line flags. Every layer
+//! above the file goes through [`Config::set`], so a value is parsed the same
+//! way wherever it comes from. [`Config::validate`] runs once all layers are
+//! applied.
+//!
+//! ```toml
+//! bind = "127.0.0.1:7000"
+//!
+//! [storage]
+//! backend = "redb"
+//! path = "/var/lib/rustr/events.redb"
+//!
+//! [session]
+//! ttl = 86400
+//!
+//! [retention.max_age]
+//! 1 = 2592000
+//!
+//! [access.kinds]
+//! 30023 = ["member", "bot"]
+//! ```
+
+use rustr_core::access::AccessPolicy;
+use rustr_core::retention::RetentionPolicy;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variables and the settings they override.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("RUSTR_BIND", "bind"),
+    ("RUSTR_STORAGE_BACKEND", "storage.backend"),
+    ("RUSTR_STORAGE_PATH", "storage.path"),
+    ("RUSTR_SESSION_TTL", "session.ttl"),
+    ("RUSTR_MAX_BODY_BYTES", "limits.max_body_bytes"),
+    ("RUSTR_MAX_EVENT_BYTES", "limits.max_event_bytes"),
+    ("RUSTR_AUTH_REQUIRED", "auth.required"),
+    ("RUSTR_ADMIN_PUBKEY", "auth.admin"),
+    ("RUSTR_AUTH_WINDOW", "auth.window"),
+    ("RUSTR_DEFAULT_ROLE", "access.default_role"),
+    ("RUSTR_LOG_FORMAT", "log.format"),
+    ("RUSTR_LOG_LEVEL", "log.level"),
+    ("RUSTR_MIN_POW_DIFFICULTY", "limits.min_pow_difficulty"),
+    ("RUSTR_MAX_SUBSCRIPTIONS", "limits.max_subscriptions"),
+    ("RUSTR_RELAY_NAME", "relay.name"),
+    ("RUSTR_RELAY_DESCRIPTION", "relay.description"),
+    ("RUSTR_RELAY_CONTACT", "relay.contact"),
+    ("RUSTR_RELAY_PUBKEY", "relay.pubkey"),
+    ("RUSTR_RELAY_URL", "relay.url"),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub storage: StorageConfig,
+    pub session: SessionConfig,
+    pub limits: Limits,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub retention: RetentionPolicy,
+    pub relay: RelayConfig,
+    pub rate_limits: RateLimits,
+    pub access: AccessPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Memory,
+    Redb,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    /// Database file, required by the `redb` backend.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Lifetime of new sessions in seconds.
+    pub ttl: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Largest accepted request body; admin imports are exempt.
+    pub max_body_bytes: usize,
+    /// Largest accepted event, measured as serialized JSON.
+    pub max_event_bytes: usize,
+    /// Leading zero bits new event ids need, see NIP-13.
+    pub min_pow_difficulty: u32,
+    /// Open subscriptions allowed per client; unlimited if unset.
+    pub max_subscriptions: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Whether requests without a session are turned away. If not, they are
+    /// served anonymously; invalid sessions are rejected either way.
+    pub required: bool,
+    /// Public key allowed to use the `/admin` routes.
+    pub admin: Option<String>,
+    /// How far in seconds the time of a signed request may be off.
+    pub window: u64,
+}
+
+/// What the relay information document says about the operator.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    pub name: String,
+    pub description: String,
+    /// Where to reach the operator, e.g. a `mailto:` URI.
+    pub contact: Option<String>,
+    /// The operator's public key; defaults to `auth.admin`.
+    pub pubkey: Option<String>,
+    /// Where clients reach the relay, e.g. `wss://relay.example.com`;
+    /// advertised for registered users in `/.well-known/nostr.json`.
+    pub url: Option<String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> RelayConfig {
+        RelayConfig {
+            name: "rustr".to_string(),
+            description: "A nostr relay written in Rust".to_string(),
+            contact: None,
+            pubkey: None,
+            url: None,
+        }
+    }
+}
+
+/// A token bucket: `burst` requests at once, refilled at `per_minute`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl Rate {
+    pub fn per_second(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+/// Limits per client for each kind of request; a missing rate means
+/// unlimited.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub reads: Option<Rate>,
+    pub writes: Option<Rate>,
+    /// `POST /authenticate`.
+    pub auth: Option<Rate>,
+    /// Opening live streams of events.
+    pub subscriptions: Option<Rate>,
+    /// Buckets kept in memory before idle ones are dropped.
+    pub max_clients: usize,
+}
+
+impl RateLimits {
+    pub fn rate(&self, class: crate::ratelimit::Class) -> Option<&Rate> {
+        use crate::ratelimit::Class;
+        match class {
+            Class::Read => self.reads.as_ref(),
+            Class::Write => self.writes.as_ref(),
+            Class::Auth => self.auth.as_ref(),
+            Class::Subscription => self.subscriptions.as_ref(),
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> RateLimits {
+        RateLimits {
+            reads: Some(Rate {
+                per_minute: 600,
+                burst: 100,
+            }),
+            writes: Some(Rate {
+                per_minute: 60,
+                burst: 20,
+            }),
+            auth: Some(Rate {
+                per_minute: 10,
+                burst: 5,
+            }),
+            subscriptions: Some(Rate {
+                per_minute: 10,
+                burst: 5,
+            }),
+            max_clients: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// One of `trace`, `debug`, `info`, `warn` or `error`.
+    pub level: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            storage: StorageConfig::default(),
+            session: SessionConfig::default(),
+            limits: Limits::default(),
+            auth: AuthConfig::default(),
+            log: LogConfig::default(),
+            retention: RetentionPolicy::default(),
+            relay: RelayConfig::default(),
+            rate_limits: RateLimits::default(),
+            access: AccessPolicy::default(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> StorageConfig {
+        StorageConfig {
+            backend: Backend::Memory,
+            path: None,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> SessionConfig {
+        SessionConfig { ttl: 60 * 60 }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_body_bytes: 1024 * 1024,
+            max_event_bytes: 64 * 1024,
+            min_pow_difficulty: 0,
+            max_subscriptions: None,
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Whether `public_key`, in either key form, is the admin's.
+    pub fn is_admin(&self, public_key: &str) -> bool {
+        self.admin
+            .as_deref()
+            .is_some_and(|admin| crypto::x_only(admin) == crypto::x_only(public_key))
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig {
+            required: true,
+            admin: None,
+            window: 60,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig {
+            format: LogFormat::Text,
+            level: "info".to_string(),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s {
+            "memory" => Ok(Backend::Memory),
+            "redb" => Ok(Backend::Redb),
+            _ => Err("expected memory or redb".to_string()),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io(PathBuf, std::io::Error),
+    /// The configuration file is not valid TOML or has unknown keys.
+    Parse(PathBuf, String),
+    /// A setting got a value it cannot take; `source` names where it came
+    /// from, e.g. an environment variable.
+    Value {
+        source: String,
+        key: String,
+        message: String,
+    },
+    /// The settings are well-formed but do not fit together.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {}", path.display(), e),
+            ConfigError::Value {
+                source,
+                key,
+                message,
+            } => write!(
+                f,
+                "invalid value for {} (from {}): {}",
+                key, source, message
+            ),
+            ConfigError::Invalid(e) => write!(f, "invalid configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Defaults overlaid with the file at `path`, if any, and the `RUSTR_*`
+    /// variables in `env`.
+    pub fn load(
+        path: Option<&Path>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        for (var, value) in env {
+            if let Some((_, key)) = ENV_VARS.iter().find(|(name, _)| *name == var) {
+                config.set(&var, key, &value)?;
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e.to_string()))
+    }
+
+    /// Overrides the setting `key`, written as in the file, e.g.
+    /// `storage.path`; `source` is only used in error messages.
+    pub fn set(&mut self, source: &str, key: &str, value: &str) -> Result<(), ConfigError> {
+        fn parse<T: FromStr>(value: &str) -> Result<T, String>
+        where
+            T::Err: fmt::Display,
+        {
+            value.parse().map_err(|e: T::Err| e.to_string())
+        }
+        let result = match key {
+            "bind" => parse(value).map(|v| self.bind = v),
+            "storage.backend" => parse(value).map(|v| self.storage.backend = v),
+            "storage.path" => {
+                self.storage.path = Some(PathBuf::from(value));
+                Ok(())
+            }
+            "session.ttl" => parse(value).map(|v| self.session.ttl = v),
+            "limits.max_body_bytes" => parse(value).map(|v| self.limits.max_body_bytes = v),
+            "limits.max_event_bytes" => parse(value).map(|v| self.limits.max_event_bytes = v),
+            "limits.min_pow_difficulty" => parse(value).map(|v| self.limits.min_pow_difficulty = v),
+            "limits.max_subscriptions" => {
+                parse(value).map(|v| self.limits.max_subscriptions = Some(v))
+            }
+            "auth.required" => parse(value).map(|v| self.auth.required = v),
+            "auth.window" => parse(value).map(|v| self.auth.window = v),
+            "auth.admin" => {
+                self.auth.admin = Some(value.to_string());
+                Ok(())
+            }
+            "access.default_role" => parse(value).map(|v| self.access.default_role = v),
+            "log.format" => parse(value).map(|v| self.log.format = v),
+            "relay.name" => {
+                self.relay.name = value.to_string();
+                Ok(())
+            }
+            "relay.description" => {
+                self.relay.description = value.to_string();
+                Ok(())
+            }
+            "relay.contact" => {
+                self.relay.contact = Some(value.to_string());
+                Ok(())
+            }
+            "relay.pubkey" => {
+                self.relay.pubkey = Some(value.to_string());
+                Ok(())
+            }
+            "relay.url" => {
+                self.relay.url = Some(value.to_string());
+                Ok(())
+            }
+            "log.level" => {
+                self.log.level = value.to_string();
+                Ok(())
+            }
+            _ => Err("unknown setting".to_string()),
+        };
+        result.map_err(|message| ConfigError::Value {
+            source: source.to_string(),
+            key: key.to_string(),
+            message,
+        })
+    }
+
+    /// Checks what the types alone cannot.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: &str| Err(ConfigError::Invalid(msg.to_string()));
+        if self.storage.backend == Backend::Redb && self.storage.path.is_none() {
+            return invalid("storage.path is required for the redb backend");
+        }
+        if self.session.ttl == 0 {
+            return invalid("session.ttl must be at least one second");
+        }
+        if self.auth.window == 0 {
+            return invalid("auth.window must be at least one second");
+        }
+        if self.limits.max_body_bytes == 0 || self.limits.max_event_bytes == 0 {
+            return invalid("limits must be greater than zero");
+        }
+        if self.limits.max_event_bytes > self.limits.max_body_bytes {
+            return invalid("limits.max_event_bytes cannot exceed limits.max_body_bytes");
+        }
+        if self
+            .auth
+            .admin
+            .as_deref()
+            .is_some_and(|k| !is_public_key(k))
+        {
+            return invalid("auth.admin must be a hex public key");
+        }
+        if self
+            .relay
+            .pubkey
+            .as_deref()
+            .is_some_and(|k| !is_public_key(k))
+        {
+            return invalid("relay.pubkey must be a hex public key");
+        }
+        if self.relay.url.as_deref().is_some_and(|url| {
+            !["ws://", "wss://", "http://", "https://"]
+                .iter()
+                .any(|scheme| url.starts_with(scheme))
+        }) {
+            return invalid("relay.url must be a ws(s) or http(s) URL");
+        }
+        let rates = &self.rate_limits;
+        let all = [
+            &rates.reads,
+            &rates.writes,
+            &rates.auth,
+            &rates.subscriptions,
+        ];
+        if all
+            .iter()
+            .any(|r| r.is_some_and(|r| r.per_minute == 0 || r.burst == 0))
+        {
+            return invalid("rate limits need per_minute and burst greater than zero");
+        }
+        if rates.max_clients < 4 {
+            return invalid("rate_limits.max_clients must be at least 4");
+        }
+        if self.limits.min_pow_difficulty > 256 {
+            return invalid("limits.min_pow_difficulty cannot exceed 256");
+        }
+        if self.log_level().is_none() {
+            return invalid("log.level must be one of trace, debug, info, warn, error");
+        }
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> Option<tracing::Level> {
+        self.log.level.parse().ok()
+    }
+}
+
+/// x-only (64 characters) or compressed (66 characters) hex key.
+pub(crate) fn is_public_key(key: &str) -> bool {
+    matches!(key.len(), 64 | 66) && key.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_layers() {
+        let path = std::env::temp_dir().join(format!("rustr-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "bind = \"127.0.0.1:7000\"\n[storage]\nbackend = \"redb\"\npath = \"a.redb\"\n[retention]\nmax_events_per_author = 5\n[access.kinds]\n1 = [\"bot\"]\n",
+        )
+        .unwrap();
+        let config = Config::load(
+            Some(&path),
+            env(&[
+                ("RUSTR_STORAGE_PATH", "b.redb"),
+                ("RUSTR_DEFAULT_ROLE", "read-only"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.bind, SocketAddr::from(([127, 0, 0, 1], 7000)));
+        assert_eq!(config.storage.backend, Backend::Redb);
+        assert_eq!(config.storage.path, Some(PathBuf::from("b.redb")));
+        assert_eq!(config.retention.max_events_per_author, Some(5));
+        assert_eq!(config.session.ttl, 3600);
+        assert_eq!(config.access.default_role, entity::Role::ReadOnly);
+        assert_eq!(config.access.kinds.len(), 1);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = Config::load(None, env(&[("RUSTR_SESSION_TTL", "soon")])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid value for session.ttl (from RUSTR_SESSION_TTL): invalid digit found in string"
+        );
+        let err = Config::load(Some(Path::new("/nonexistent/rustr.toml")), env(&[])).unwrap_err();
+        assert!(matches!(err, ConfigError::Io(..)));
+
+        let mut config = Config::default();
+        config
+            .set("--storage-backend", "storage.backend", "redb")
+            .unwrap();
+        assert!(config.validate().is_err());
+        let mut config = Config::default();
+        config.set("test", "log.level", "loud").unwrap();
+        assert!(config.validate().is_err());
+        assert!(toml::from_str::<Config>("port = 1").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! The error every handler returns, rendered as an [`ErrorBody`] with a
+//! stable code and the matching status.
+
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use crypto::CryptoError;
+use entity::ErrorBody;
+use rustr_core::repository::RepoError;
+use tracing::{event, Level};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiError {
+    /// Malformed request, e.g. an unknown filter parameter.
+    BadRequest(String),
+    /// No valid session.
+    Unauthorized(String),
+    /// Authenticated, but not allowed to do this.
+    Forbidden(String),
+    NotFound(String),
+    /// An event with this id is already stored.
+    Duplicate(String),
+    /// A newer event already holds the address of this one.
+    Outdated(String),
+    TooLarge(String),
+    /// Over a rate limit; may retry after `retry_after` seconds.
+    RateLimited {
+        message: String,
+        retry_after: u64,
+    },
+    /// Well-formed, but rejected, e.g. for a bad signature.
+    Invalid(String),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Duplicate(_) | ApiError::Outdated(_) => StatusCode::CONFLICT,
+            ApiError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Duplicate(_) => "duplicate",
+            ApiError::Outdated(_) => "outdated",
+            ApiError::TooLarge(_) => "too_large",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Invalid(_) => "invalid",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+    pub fn message(&self) -> &str {
+        match self {
+            ApiError::BadRequest(m)
+            | ApiError::Unauthorized(m)
+            | ApiError::Forbidden(m)
+            | ApiError::NotFound(m)
+            | ApiError::Duplicate(m)
+            | ApiError::Outdated(m)
+            | ApiError::TooLarge(m)
+            | ApiError::Invalid(m)
+            | ApiError::Internal(m)
+            | ApiError::RateLimited { message: m, .. } => m,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(msg) = &self {
+            event!(Level::ERROR, "internal error: {}", msg);
+        }
+        let body = ErrorBody {
+            code: self.code().to_string(),
+            message: self.message().to_string(),
+        };
+        let mut response = (self.status(), Json(body)).into_response();
+        if let ApiError::RateLimited { retry_after, .. } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response
+    }
+}
+
+impl From<RepoError> for ApiError {
+    fn from(e: RepoError) -> ApiError {
+        match e {
+            RepoError::Duplicate(id) => ApiError::Duplicate(format!("event {} already exists", id)),
+            RepoError::Outdated(id) => {
+                ApiError::Outdated(format!("a newer event replaces event {}", id))
+            }
+            RepoError::Storage(msg) => ApiError::Internal(msg),
+        }
+    }
+}
+
+impl From<CryptoError> for ApiError {
+    fn from(e: CryptoError) -> ApiError {
+        ApiError::Invalid(e.to_string())
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(e: JsonRejection) -> ApiError {
+        match e {
+            JsonRejection::JsonDataError(_) => ApiError::Invalid(e.body_text()),
+            JsonRejection::BytesRejection(_) => ApiError::TooLarge(e.body_text()),
+            _ => ApiError::BadRequest(e.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(e: QueryRejection) -> ApiError {
+        ApiError::BadRequest(e.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(e: PathRejection) -> ApiError {
+        ApiError::BadRequest(e.body_text())
+    }
+}
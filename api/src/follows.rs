@@ -0,0 +1,162 @@
+//! `GET /feed`: what the caller's follows posted, newest first.
+//!
+//! The follows come from the caller's latest contact list. A page holds up
+//! to `limit` events (default 50) of the given `kinds` (default text notes)
+//! and, if there are more, the cursor for the next one, to be passed back
+//! as `before`.
+
+use crate::auth::Caller;
+use crate::mutes::mutes_for;
+use crate::sse::{cursor, parse_cursor};
+use crate::{ApiError, AppState};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use entity::contacts::ContactList;
+use entity::{kind, Event, Filter};
+use rustr_core::circle::Audience;
+use rustr_core::repository::EventRepo;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    limit: Option<usize>,
+    /// `<created_at>:<id>` of the last event of the previous page.
+    before: Option<String>,
+    /// Comma separated.
+    kinds: Option<String>,
+    /// Leave out what the caller muted, see [`crate::mutes`].
+    apply_mutes: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page {
+    pub events: Vec<Event>,
+    pub next: Option<String>,
+}
+
+/// `public_key` in both the x-only and the compressed forms, as follows and
+/// authors may use either.
+pub(crate) fn key_forms(public_key: &str) -> Vec<String> {
+    match public_key.len() {
+        64 => vec![
+            public_key.to_string(),
+            format!("02{}", public_key),
+            format!("03{}", public_key),
+        ],
+        _ => vec![
+            public_key.to_string(),
+            crypto::x_only(public_key).to_string(),
+        ],
+    }
+}
+
+/// Parses a comma separated `kinds` parameter.
+pub(crate) fn parse_kinds(kinds: Option<&str>) -> Result<Option<Vec<u32>>, ApiError> {
+    let Some(v) = kinds else {
+        return Ok(None);
+    };
+    v.split(',')
+        .map(|k| k.parse())
+        .collect::<Result<_, _>>()
+        .map(Some)
+        .map_err(|_| ApiError::BadRequest(format!("invalid kinds {}", v)))
+}
+
+/// Parses a `before` cursor as produced by [`cursor`].
+pub(crate) fn parse_before(before: Option<&str>) -> Result<Option<(u64, String)>, ApiError> {
+    before
+        .map(|v| parse_cursor(v).ok_or_else(|| ApiError::BadRequest("invalid before".to_string())))
+        .transpose()
+}
+
+/// The latest list event of `kind` that `public_key` published.
+pub(crate) fn latest_list(
+    events: &mut dyn EventRepo,
+    public_key: &str,
+    kind: u32,
+) -> Option<Event> {
+    let filter = Filter {
+        authors: Some(key_forms(public_key)),
+        kinds: Some(vec![kind]),
+        limit: Some(1),
+        ..Filter::default()
+    };
+    events.query(&filter).into_iter().next()
+}
+
+/// The latest contact list of `public_key`, empty if there is none.
+fn contact_list(events: &mut dyn EventRepo, public_key: &str) -> ContactList {
+    latest_list(events, public_key, kind::CONTACTS)
+        .and_then(|e| ContactList::from_event(&e).ok())
+        .unwrap_or_default()
+}
+
+pub async fn read_feed(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<Params>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Page>, ApiError> {
+    let Some(Extension(caller)) = caller else {
+        return Err(ApiError::Unauthorized("authorization required".to_string()));
+    };
+    let Query(params) = query?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let before = parse_before(params.before.as_deref())?;
+    let kinds = parse_kinds(params.kinds.as_deref())?.unwrap_or(vec![kind::TEXT_NOTE]);
+
+    let mutes = mutes_for(&state, Some(&caller), params.apply_mutes).await?;
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, Some(&caller.public_key));
+    let follows = contact_list(&mut events, &caller.public_key);
+    if follows.contacts.is_empty() {
+        return Ok(Json(Page {
+            events: Vec::new(),
+            next: None,
+        }));
+    }
+    let authors = follows
+        .contacts
+        .iter()
+        .flat_map(|c| key_forms(&c.public_key))
+        .collect();
+    let filter = Filter {
+        authors: Some(authors),
+        kinds: Some(kinds),
+        until: before.as_ref().map(|(created_at, _)| *created_at),
+        ..Filter::default()
+    };
+    let mut page = events.query(&filter);
+    if let Some((created_at, id)) = before {
+        page.retain(|e| (e.created_at, &e.id) < (created_at, &id));
+    }
+    if let Some(mutes) = mutes {
+        page.retain(|e| !mutes.mutes(e));
+    }
+    let next = (page.len() > limit).then(|| cursor(&page[limit - 1]));
+    page.truncate(limit);
+    Ok(Json(Page { events: page, next }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_forms() {
+        let x_only = "ab".repeat(32);
+        let compressed = format!("03{}", x_only);
+        assert!(key_forms(&x_only).contains(&compressed));
+        assert!(key_forms(&compressed).contains(&x_only));
+    }
+}
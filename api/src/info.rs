@@ -0,0 +1,95 @@
+//! Relay information document (NIP-11), served at `/` to requests that
+//! accept `application/nostr+json`, to any origin.
+
+use crate::AppState;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+/// NIPs a client can rely on at the relay URL. The relay messages of
+/// NIP-01, with COUNT (NIP-45) and search (NIP-50), are only spoken by the
+/// read-only socket at `/events/ws`, so they are not listed.
+pub const SUPPORTED_NIPS: &[u32] = &[5, 9, 11, 13, 18, 23, 25, 40, 51, 98];
+
+#[derive(Debug, Serialize)]
+pub struct RelayInfo {
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    pub supported_nips: Vec<u32>,
+    pub software: String,
+    pub version: String,
+    pub limitation: Limitation,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Limitation {
+    pub max_message_length: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_subscriptions: Option<usize>,
+    pub min_pow_difficulty: u32,
+    pub auth_required: bool,
+}
+
+impl RelayInfo {
+    pub fn from_config(config: &crate::Config) -> RelayInfo {
+        RelayInfo {
+            name: config.relay.name.clone(),
+            description: config.relay.description.clone(),
+            pubkey: config.relay.pubkey.clone().or(config.auth.admin.clone()),
+            contact: config.relay.contact.clone(),
+            supported_nips: SUPPORTED_NIPS.to_vec(),
+            software: env!("CARGO_PKG_REPOSITORY").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            limitation: Limitation {
+                max_message_length: config.limits.max_body_bytes,
+                max_subscriptions: config.limits.max_subscriptions,
+                min_pow_difficulty: config.limits.min_pow_difficulty,
+                auth_required: config.auth.required,
+            },
+        }
+    }
+}
+
+pub async fn relay_info(headers: HeaderMap, State(state): State<AppState>) -> Response {
+    let wants_info = headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.contains("application/nostr+json"));
+    if !wants_info {
+        return format!(
+            "{} - {}",
+            state.config.relay.name, state.config.relay.description
+        )
+        .into_response();
+    }
+    let mut response = Json(RelayInfo::from_config(&state.config)).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/nostr+json"),
+    );
+    allow_any_origin(response)
+}
+
+/// Answers the CORS preflight browsers send before fetching the document.
+pub async fn preflight() -> Response {
+    allow_any_origin(StatusCode::NO_CONTENT.into_response())
+}
+
+fn allow_any_origin(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    let any = HeaderValue::from_static("*");
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, any.clone());
+    headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, any);
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("GET, OPTIONS"),
+    );
+    response
+}
@@ -1,101 +1,349 @@
-use axum::extract::{Path, State};
+use axum::body::StreamBody;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, State};
+use axum::http::header;
+use axum::http::HeaderMap;
 use axum::http::StatusCode;
+use axum::middleware;
 use axum::response::IntoResponse;
+use axum::response::Response;
 use axum::{
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
-use axum::http::Request;
-use axum::middleware::{self, Next};
-use axum::response::Response;
-use core::repository::{SessionRepo,EventRepo,UserRepo};
-use core::repository::{SessionRepoInMemory,EventRepoInMemory,UserRepoInMemory};
+use rustr_core::access::Permission;
+use rustr_core::archive::{self, ExportReport, ImportReport, Record, Store};
+use rustr_core::circle::{Audience, CircleRepo, CircleRepoInMemory};
+use rustr_core::feed::{Change, ChangeFeed, FeedError, Publishing};
+use rustr_core::kv::EventRepoKv;
+use rustr_core::moderation::{ModerationRepo, ModerationRepoInMemory};
+use rustr_core::repository::{EventRepo, Pages, SessionRepo, UserRepo};
+use rustr_core::repository::{EventRepoInMemory, SessionRepoInMemory, UserRepoInMemory};
+use rustr_core::retention::RetentionPolicy;
+use rustr_core::thread;
 
-use entity::{User,Event, Session};
+use entity::reaction::Stats;
+use entity::thread::Thread;
+use entity::{kind, Count, Event, Filter, Role, Session, User};
+use futures_util::stream;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, Mutex};
 use tower_http::trace::TraceLayer;
-use tracing::{Level,event};
+use tracing::{event, Level};
+
+mod access;
+mod articles;
+pub mod auth;
+mod circles;
+pub mod config;
+pub mod error;
+mod follows;
+mod info;
+mod lists;
+mod moderation;
+mod mutes;
+mod nip05;
+mod notifications;
+pub mod ratelimit;
+mod sse;
+mod ws;
+
+use auth::{Caller, Credentials, ReplayGuard};
+pub use config::Config;
+use config::{Backend, LogFormat};
+pub use error::ApiError;
+use ratelimit::RateLimiter;
 #[derive(Clone)]
 pub struct AppState {
     user_repo: Arc<Mutex<Box<dyn UserRepo + Send + Sync>>>,
     event_repo: Arc<Mutex<Box<dyn EventRepo + Send + Sync>>>,
     session_repo: Arc<Mutex<Box<dyn SessionRepo + Send + Sync>>>,
+    moderation: Arc<Mutex<Box<dyn ModerationRepo + Send + Sync>>>,
+    /// Lock before `event_repo` when both are needed.
+    circles: Arc<Mutex<Box<dyn CircleRepo + Send + Sync>>>,
+    /// Changes to `event_repo`, for live subscribers.
+    feed: ChangeFeed,
+    config: Arc<Config>,
+    limiter: Arc<RateLimiter>,
+    replays: Arc<ReplayGuard>,
     //context : Arc<Mutex<Context>>,
 }
-#[derive(Clone)]
-struct Context {
-    pub private_key : Option<String>
+
+#[cfg(test)]
+impl AppState {
+    /// State over empty in-memory repositories, for tests.
+    pub(crate) fn in_memory(config: Config) -> AppState {
+        let feed = ChangeFeed::default();
+        AppState {
+            user_repo: Arc::new(Mutex::new(Box::new(UserRepoInMemory::new()))),
+            event_repo: Arc::new(Mutex::new(Box::new(Publishing::new(
+                EventRepoInMemory::new(),
+                feed.clone(),
+            )))),
+            session_repo: Arc::new(Mutex::new(Box::new(SessionRepoInMemory::new()))),
+            moderation: Arc::new(Mutex::new(Box::new(ModerationRepoInMemory::new()))),
+            circles: Arc::new(Mutex::new(Box::new(CircleRepoInMemory::new()))),
+            feed,
+            limiter: Arc::new(RateLimiter::new(config.rate_limits.clone())),
+            config: Arc::new(config),
+            replays: Arc::new(ReplayGuard::default()),
+        }
+    }
 }
 
-#[tokio::main]
-pub async fn main() {
-    let ur = UserRepoInMemory::new();
-    let er = EventRepoInMemory::new();
+pub fn main(config: Config) -> Result<(), String> {
+    tokio::runtime::Runtime::new()
+        .map_err(|e| e.to_string())?
+        .block_on(serve(config))
+}
+
+pub async fn serve(config: Config) -> Result<(), String> {
+    config.validate().map_err(|e| e.to_string())?;
+    init_tracing(&config);
+    let feed = ChangeFeed::default();
+    type Repos = (
+        Box<dyn EventRepo + Send + Sync>,
+        Box<dyn UserRepo + Send + Sync>,
+        Box<dyn ModerationRepo + Send + Sync>,
+        Box<dyn CircleRepo + Send + Sync>,
+    );
+    let (er, ur, mr, cr): Repos = match config.storage.backend {
+        Backend::Memory => (
+            Box::new(Publishing::new(EventRepoInMemory::new(), feed.clone())),
+            Box::new(UserRepoInMemory::new()),
+            Box::new(ModerationRepoInMemory::new()),
+            Box::new(CircleRepoInMemory::new()),
+        ),
+        Backend::Redb => {
+            let path = config.storage.path.as_ref().expect("validated");
+            let open_error = |e| format!("cannot open {}: {}", path.display(), e);
+            let repo = EventRepoKv::open(path).map_err(open_error)?;
+            let moderation = repo.moderation().map_err(open_error)?;
+            let circles = repo.circles().map_err(open_error)?;
+            let users = repo.users().map_err(open_error)?;
+            (
+                Box::new(Publishing::new(repo, feed.clone())),
+                Box::new(users),
+                Box::new(moderation),
+                Box::new(circles),
+            )
+        }
+    };
     let sr = SessionRepoInMemory::new();
-    let context = Context{private_key: None};
-    
+
     let app_state = AppState {
-        user_repo: Arc::new(Mutex::new(Box::new(ur))),
-        event_repo: Arc::new(Mutex::new(Box::new(er))),
+        user_repo: Arc::new(Mutex::new(ur)),
+        event_repo: Arc::new(Mutex::new(er)),
         session_repo: Arc::new(Mutex::new(Box::new(sr))),
-       //context: Arc::new(Mutex::new(context)),
+        moderation: Arc::new(Mutex::new(mr)),
+        circles: Arc::new(Mutex::new(cr)),
+        feed,
+        config: Arc::new(config.clone()),
+        limiter: Arc::new(RateLimiter::new(config.rate_limits.clone())),
+        replays: Arc::new(ReplayGuard::default()),
+        //context: Arc::new(Mutex::new(context)),
     };
-    // tracing_subscriber::fmt()
-    // .with_max_level(tracing::Level::DEBUG)
-    // .init();
-tracing_subscriber::fmt::init();
+    tokio::spawn(cleanup(
+        app_state.clone(),
+        config.retention.clone(),
+        Duration::from_secs(60),
+    ));
+    tokio::spawn(log_changes(app_state.feed.clone()));
     let app = Router::new()
         .route("/ping", get(|| async { "pong" }))
         .route("/users/:id", get(read_user))
+        .route("/users/:id/lists/:kind", get(lists::read_lists))
+        .route("/users/:id/lists/:kind/:d", get(lists::read_list))
         .route("/users", get(read_users))
         .route("/users", post(save_user))
+        .route("/events/stream", get(sse::stream_events))
+        .route("/events/ws", get(ws::socket))
+        .route("/events/count", get(count_events))
         .route("/events/:id", get(read_event))
+        .route("/events/:id/thread", get(read_thread))
+        .route("/events/:id/stats", get(read_stats))
         .route("/events", get(read_events))
         .route("/events", post(save_event))
-        .layer(middleware::from_fn_with_state(app_state.clone(),test_middleware_mutex))
+        .route("/feed", get(follows::read_feed))
+        .route("/articles/:id/:d", get(articles::read_article))
+        .route(
+            "/circles",
+            get(circles::read_circles).post(circles::create_circle),
+        )
+        .route(
+            "/circles/:id",
+            get(circles::read_circle).delete(circles::delete_circle),
+        )
+        .route(
+            "/circles/:id/members",
+            post(circles::add_members).delete(circles::remove_members),
+        )
+        .route("/circles/:id/events", get(circles::read_circle_events))
+        .route("/notifications", get(notifications::read_notifications))
+        .route("/notifications/seen", post(notifications::mark_seen))
+        .route("/admin/export", get(export_store))
+        .route(
+            "/admin/roles",
+            get(access::read_roles).post(access::set_roles),
+        )
+        .route(
+            "/admin/bans",
+            get(moderation::read_bans)
+                .post(moderation::add_bans)
+                .delete(moderation::remove_bans),
+        )
+        .route(
+            "/admin/allow",
+            get(moderation::read_allow)
+                .post(moderation::add_allow)
+                .delete(moderation::remove_allow),
+        )
+        .route(
+            "/admin/import",
+            post(import_store).layer(DefaultBodyLimit::disable()),
+        )
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::require_auth,
+        ))
+        .route("/", get(info::relay_info).options(info::preflight))
+        .route("/.well-known/nostr.json", get(nip05::nostr_json))
         .route("/authenticate", post(save_session))
         //authenticate
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            ratelimit::rate_limit,
+        ))
+        .layer(DefaultBodyLimit::max(config.limits.max_body_bytes))
         .layer(TraceLayer::new_for_http())
         .with_state(app_state)
         .fallback(handler_404);
-    let listening_string ="0.0.0.0:3000";
-    let msg = format!("start listening on {}", listening_string);
-    event!(Level::INFO,msg);
-    // run it with hyper on localhost:3000
-    axum::Server::bind(&listening_string.parse().unwrap())
-        .serve(app.into_make_service())
+    let msg = format!("start listening on {}", config.bind);
+    event!(Level::INFO, msg);
+    axum::Server::try_bind(&config.bind)
+        .map_err(|e| format!("cannot listen on {}: {}", config.bind, e))?
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
-        .unwrap();
+        .map_err(|e| e.to_string())
 }
 
-async fn handler_404() -> impl IntoResponse {
-    (StatusCode::NOT_FOUND, "nothing to see here")
+fn init_tracing(config: &Config) {
+    let builder = tracing_subscriber::fmt().with_max_level(config.log_level().expect("validated"));
+    match config.log.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Removes expired events and applies the retention policy every `period`.
+async fn cleanup(state: AppState, policy: RetentionPolicy, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        let mut events = state.event_repo.lock().await;
+        match policy.apply(&mut **events) {
+            Ok(report) if report.total() > 0 => {
+                event!(
+                    Level::INFO,
+                    "retention removed {} events: {} expired, {} too old, {} over author quota, {} over store quota",
+                    report.total(),
+                    report.expired.len(),
+                    report.too_old.len(),
+                    report.over_author_quota.len(),
+                    report.over_store_quota.len()
+                );
+                event!(Level::DEBUG, "retention report {:?}", report);
+            }
+            Ok(_) => {}
+            Err(e) => event!(Level::ERROR, "retention failed: {}", e),
+        }
+    }
+}
+
+/// Traces every change to the event store.
+async fn log_changes(feed: ChangeFeed) {
+    let mut changes = feed.subscribe();
+    loop {
+        match changes.recv().await {
+            Ok(Change::Added(e)) => event!(Level::DEBUG, "event {} added", e.id),
+            Ok(Change::Replaced { old, new }) => {
+                event!(Level::DEBUG, "event {} replaced by {}", old, new.id)
+            }
+            Ok(Change::Deleted(id)) => event!(Level::DEBUG, "event {} deleted", id),
+            Ok(Change::Expired(id)) => event!(Level::DEBUG, "event {} expired", id),
+            Err(FeedError::Lagged(n)) => event!(Level::WARN, "change log skipped {} changes", n),
+            Err(FeedError::Closed) => return,
+        }
+    }
+}
+
+async fn handler_404() -> ApiError {
+    ApiError::NotFound("nothing to see here".to_string())
 }
 
 //#[axum_macros::debug_handler]
 async fn read_user(
-    Path(user_id): Path<String>,
+    path: Result<Path<String>, PathRejection>,
     State(state): State<AppState>,
-) -> Result<Json<User>, (StatusCode, String)> {
-    let r = state.user_repo.lock().await;//.expect("mutex was poisoned");
-    let user = r.read_user(&user_id);
-    if user.is_some() {
-        Ok(Json(user.unwrap().to_owned()))
-    } else {
-        Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Unknown error".to_string(),
-        ))
+) -> Result<Json<User>, ApiError> {
+    let Path(user_id) = path?;
+    let r = state.user_repo.lock().await; //.expect("mutex was poisoned");
+    match r.read_user(&user_id) {
+        Some(user) => Ok(Json(user.to_owned())),
+        None => Err(ApiError::NotFound(format!("user {} not found", user_id))),
     }
 }
 
-async fn save_user(State(state): State<AppState>, payload: axum::extract::Json<User>) -> StatusCode {
-    println!("->{:?}", &payload);
-    let payload: User = payload.0;
-    let mut r = state.user_repo.lock().await;//.expect("mutex was poisoned");
-    r.add_user(payload);
-    return StatusCode::CREATED;
+/// Registers or updates the author of a signed profile event (kind 0), who
+/// must be the caller unless an admin relays it. Names are unique; roles are
+/// kept and change through `/admin/roles` only.
+async fn save_user(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<Event>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Some(Extension(caller)) = caller else {
+        return Err(ApiError::Unauthorized("authorization required".to_string()));
+    };
+    let Json(payload) = payload?;
+    let mut user = User::from_profile(&payload).map_err(ApiError::Invalid)?;
+    let same_key = |a: &str, b: &str| crypto::x_only(a) == crypto::x_only(b);
+    if !same_key(&user.public_key, &caller.public_key) && caller.role != Role::Admin {
+        return Err(ApiError::Forbidden(
+            "users may only modify their own entry".to_string(),
+        ));
+    }
+    let mut r = state.user_repo.lock().await; //.expect("mutex was poisoned");
+    if let Some(other) = r.find_user_by_name(&user.name) {
+        if !same_key(&other.public_key, &user.public_key) {
+            return Err(ApiError::Duplicate(format!(
+                "name {} is already taken",
+                user.name
+            )));
+        }
+    }
+    user.role = match r.read_user(&user.public_key) {
+        Some(old) if old.updated_at > user.updated_at => {
+            return Err(ApiError::Outdated(format!(
+                "a newer profile of {} is registered",
+                user.public_key
+            )))
+        }
+        Some(old) => old.role,
+        None => state.config.access.default_role,
+    };
+    event!(
+        Level::INFO,
+        "save user {} for {}",
+        user.name,
+        user.public_key
+    );
+    r.add_user(user);
+    Ok(StatusCode::CREATED)
 }
 
 
@@ -107,102 +355,489 @@ async fn read_users(State(state): State<AppState>) -> Json<Vec<User>> {
 
 
 async fn read_event(
-    Path(id): Path<String>,
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
     State(state): State<AppState>,
-) -> Result<Json<Event>, (StatusCode, String)> {
-    let mut r = state.event_repo.lock().await;//.expect("mutex was poisoned");
-    match r.read(&id){
-        Some(event) => { 
-        return Ok(Json(event.to_owned()))},
-        None => return Err((
-            StatusCode::NOT_FOUND,
-            "event not found".to_string(),
-        ))
+) -> Result<Json<Event>, ApiError> {
+    let Path(id) = path?;
+    let circles = state.circles.lock().await;
+    let mut r = state.event_repo.lock().await; //.expect("mutex was poisoned");
+    let mut r = Audience::new(&mut **r, &**circles, circles::viewer(&caller));
+    match r.read(&id) {
+        Some(event) => Ok(Json(event)),
+        None => Err(ApiError::NotFound(format!("event {} not found", id))),
     }
 }
 
-async fn save_event(State(state): State<AppState>, payload: axum::extract::Json<Event>) -> StatusCode {
-    let payload: Event = payload.0;
-    let msg = format!("save event {} content '{}'",payload.id,payload.content);
-    let mut r = state.event_repo.lock().await;//.expect("mutex was poisoned");
-    r.add(payload);
-    event!(Level::INFO,msg);
-    return StatusCode::CREATED;
+#[derive(Debug, Deserialize)]
+struct ThreadParams {
+    depth: Option<usize>,
 }
 
+/// Levels of ancestors and replies returned by default and at most.
+const THREAD_DEPTH: usize = 16;
+const MAX_THREAD_DEPTH: usize = 64;
+/// Replies returned at most, however deep.
+const MAX_THREAD_REPLIES: usize = 1000;
 
+/// The thread around event `id`, see [`rustr_core::thread`]; `?depth=`
+/// limits how far up and down it reaches.
+async fn read_thread(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    query: Result<Query<ThreadParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Thread>, ApiError> {
+    let Path(id) = path?;
+    let Query(params) = query?;
+    let depth = params.depth.unwrap_or(THREAD_DEPTH);
+    if depth > MAX_THREAD_DEPTH {
+        return Err(ApiError::BadRequest(format!(
+            "depth cannot exceed {}",
+            MAX_THREAD_DEPTH
+        )));
+    }
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, circles::viewer(&caller));
+    match thread::thread(&mut events, &id, depth, MAX_THREAD_REPLIES) {
+        Some(thread) => Ok(Json(thread)),
+        None => Err(ApiError::NotFound(format!("event {} not found", id))),
+    }
+}
 
-async fn read_events(State(state): State<AppState>) -> Json<Vec<Event>> {
-    let mut events = state.event_repo.lock().await;//.expect("mutex was poisoned");
-    Json(events.read_all())
+/// Reactions, reposts and replies counted for event `id`.
+async fn read_stats(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<String>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Stats>, ApiError> {
+    let Path(id) = path?;
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, circles::viewer(&caller));
+    if events.read(&id).is_none() {
+        return Err(ApiError::NotFound(format!("event {} not found", id)));
+    }
+    Ok(Json(events.stats(&id)))
 }
 
-async fn save_session(headers: HeaderMap,State(state): State<AppState>) -> StatusCode {
-    let auth_header = headers.get("authorization");
-    let auth_parts = if auth_header.is_some(){
-        auth_header.unwrap().to_str()
-    } else {
-        return StatusCode::BAD_REQUEST
+/// Deletes the events a deletion request (NIP-09) names, as far as they
+/// were published by its author, and returns how many there were.
+fn apply_deletion(events: &mut dyn EventRepo, request: &Event) -> Result<usize, ApiError> {
+    let mut deleted = 0;
+    for id in request.tag_values("e") {
+        let Some(e) = events.read(id) else {
+            continue;
+        };
+        // deleting a deletion request has no effect
+        if e.kind == kind::DELETION
+            || crypto::x_only(&e.public_key) != crypto::x_only(&request.public_key)
+        {
+            continue;
+        }
+        events.delete(id)?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+async fn save_event(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    payload: Result<Json<Event>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Json(payload) = payload?;
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    moderation::moderate(&state, Some(&payload.public_key), ip, true).await?;
+    // anonymous events are judged by the role of their author
+    let role = match caller {
+        Some(Extension(caller)) => caller.role,
+        None => access::role_of(&state, &payload.public_key).await,
+    };
+    state
+        .config
+        .access
+        .check_kind(role, payload.kind)
+        .map_err(ApiError::Forbidden)?;
+    moderation::moderate_event(&state, &payload).await?;
+    let size = serde_json::to_vec(&payload).map_or(0, |b| b.len());
+    let limits = &state.config.limits;
+    if size > limits.max_event_bytes {
+        return Err(ApiError::TooLarge(format!(
+            "event has {} bytes, at most {} are allowed",
+            size, limits.max_event_bytes
+        )));
+    }
+    if payload.difficulty() < limits.min_pow_difficulty {
+        return Err(ApiError::Forbidden(format!(
+            "proof of work of {} bits required",
+            limits.min_pow_difficulty
+        )));
+    }
+    if !payload.verify() {
+        return Err(ApiError::Invalid(format!(
+            "invalid id or signature for event {}",
+            payload.id
+        )));
+    }
+    circles::check_audience(&state, &payload).await?;
+    let msg = format!("save event {} content '{}'", payload.id, payload.content);
+    let mut r = state.event_repo.lock().await; //.expect("mutex was poisoned");
+    r.add(payload.clone())?;
+    event!(Level::INFO, msg);
+    if payload.kind == kind::DELETION {
+        let deleted = apply_deletion(&mut **r, &payload)?;
+        event!(
+            Level::INFO,
+            "deletion {} removed {} events",
+            payload.id,
+            deleted
+        );
+    }
+    Ok(StatusCode::CREATED)
+}
+
+/// An event in a listing, with its counters if they were asked for.
+#[derive(Debug, Serialize)]
+struct Listed {
+    #[serde(flatten)]
+    event: Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<Stats>,
+}
+
+/// Events matching the filter parameters; `?stats=true` adds the counters
+/// of each event and `?apply_mutes=true` hides what the caller muted.
+async fn read_events(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Listed>>, ApiError> {
+    let Query(mut params) = query?;
+    let apply_mutes = mutes::parse_flag(params.remove("apply_mutes").as_deref())?;
+    let with_stats = match params.remove("stats").as_deref() {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(v) => {
+            return Err(ApiError::BadRequest(format!(
+                "invalid value for stats: {}",
+                v
+            )))
+        }
     };
+    let mut filter = filter_from_params(&params)?;
+    let mutes = mutes::mutes_for(&state, caller.as_deref(), apply_mutes).await?;
+    // muted events must not use up the limit
+    let limit = match mutes {
+        Some(_) => filter.limit.take(),
+        None => None,
+    };
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await; //.expect("mutex was poisoned");
+    let mut events = Audience::new(&mut **events, &**circles, circles::viewer(&caller));
+    let mut found = events.query(&filter);
+    if let Some(mutes) = mutes {
+        found.retain(|e| !mutes.mutes(e));
+        found.truncate(limit.unwrap_or(usize::MAX));
+    }
+    let listed = found
+        .into_iter()
+        .map(|event| Listed {
+            stats: with_stats.then(|| events.stats(&event.id)),
+            event,
+        })
+        .collect();
+    Ok(Json(listed))
+}
+
+/// How many events match the filter parameters, as for `GET /events`.
+async fn count_events(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Count>, ApiError> {
+    let Query(params) = query?;
+    let filter = filter_from_params(&params)?;
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, circles::viewer(&caller));
+    Ok(Json(events.count(&[filter])))
+}
 
-    let auth_part = match  auth_parts {
-        Ok(v) => v.split(" ").nth(1),
-        _ => return StatusCode::BAD_REQUEST,
+/// Builds a filter from query parameters such as
+/// `?authors=a,b&kinds=1&since=0&limit=10&e=<id>&search=rust+relay`. List
+/// values are comma separated and single-letter keys are tag conditions.
+fn filter_from_params(params: &HashMap<String, String>) -> Result<Filter, ApiError> {
+    fn list(v: &str) -> Vec<String> {
+        v.split(',').map(|s| s.to_string()).collect()
+    }
+    fn number<T: std::str::FromStr>(key: &str, v: &str) -> Result<T, ApiError> {
+        v.parse()
+            .map_err(|_| ApiError::BadRequest(format!("invalid value for {}: {}", key, v)))
+    }
+    let mut filter = Filter::new();
+    for (key, value) in params {
+        match key.as_str() {
+            "ids" => filter.ids = Some(list(value)),
+            "authors" => filter.authors = Some(list(value)),
+            "kinds" => {
+                let kinds = value
+                    .split(',')
+                    .map(|k| number(key, k))
+                    .collect::<Result<_, _>>()?;
+                filter.kinds = Some(kinds);
+            }
+            "since" => filter.since = Some(number(key, value)?),
+            "until" => filter.until = Some(number(key, value)?),
+            "limit" => filter.limit = Some(number(key, value)?),
+            "search" => filter.search = Some(value.clone()),
+            tag if tag.chars().count() == 1 => {
+                filter = filter.with_tag(tag, list(value));
+            }
+            _ => {
+                return Err(ApiError::BadRequest(format!(
+                    "unknown filter parameter {}",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// Only admins may pass.
+fn require_admin(state: &AppState, caller: Option<&Extension<Caller>>) -> Result<(), ApiError> {
+    let Some(Extension(caller)) = caller else {
+        return Err(ApiError::Unauthorized("authorization required".to_string()));
     };
+    state
+        .config
+        .access
+        .check(caller.role, Permission::Admin)
+        .map_err(|_| ApiError::Forbidden("admin only".to_string()))
+}
 
-    let token = match  auth_part {
-        Some(v) => v,
-        _ => return StatusCode::BAD_REQUEST,
+/// Streams the store as JSONL; filter parameters as for `GET /events`
+/// restrict the exported events.
+async fn export_store(
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Query(params) = query?;
+    let filter = filter_from_params(&params)?;
+    let filter = if params.is_empty() {
+        Filter::new()
+    } else {
+        filter
     };
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(export_pages(state, filter, tx));
+    let chunks = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        StreamBody::new(chunks),
+    )
+        .into_response())
+}
 
-    println!("{:?}",token);
-    // let ctx = state.context.lock().await;//.expect("mutex was poisoned");
-    // println!("session {}",ctx.private_key.as_ref().unwrap());
+/// Sends the records of an export to `tx` a page at a time, locking the
+/// event store for one page only. Ends early once the client is gone.
+async fn export_pages(
+    state: AppState,
+    filter: Filter,
+    tx: mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+) {
+    let mut pages = Pages::new(&filter, archive::PAGE);
+    let mut report = ExportReport::default();
+    loop {
+        let events = {
+            let mut events = state.event_repo.lock().await;
+            pages.next(&mut **events)
+        };
+        let Some(events) = events else {
+            break;
+        };
+        report.events += events.len();
+        let records = events.into_iter().map(Record::Event);
+        if !send_records(&tx, records).await {
+            return;
+        }
+    }
+    let users = state.user_repo.lock().await.read_all_users();
+    report.users = users.len();
+    for page in users.chunks(archive::PAGE) {
+        let records = page.iter().cloned().map(Record::User);
+        if !send_records(&tx, records).await {
+            return;
+        }
+    }
+    event!(Level::INFO, "exported {:?}", report);
+}
 
-    let mut session_repo = state.session_repo.lock().await;//.expect("mutex was poisoned");
+/// Sends `records` as one chunk of lines; false if the export is over,
+/// because the client is gone or a record could not be written.
+async fn send_records(
+    tx: &mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+    mut records: impl Iterator<Item = Record>,
+) -> bool {
+    let mut chunk = Vec::new();
+    match records.try_for_each(|record| archive::write_record(&mut chunk, &record)) {
+        Ok(()) => tx.send(Ok(chunk)).await.is_ok(),
+        Err(e) => {
+            event!(Level::ERROR, "export failed: {}", e);
+            // the client sees the body end with an error, not a short dump
+            let _ = tx.send(Err(e)).await;
+            false
+        }
+    }
+}
 
-    session_repo.add(Session::new(token.to_string(), 0));
-    let msg = format!("new session for  {}", token.to_string());
-    event!(Level::INFO,msg);
-    return StatusCode::CREATED;
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    /// Keep the roles of imported users rather than resetting them.
+    #[serde(default)]
+    keep_roles: bool,
 }
 
+async fn import_store(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<ImportParams>, QueryRejection>,
+    State(state): State<AppState>,
+    body: String,
+) -> Result<Json<ImportReport>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Query(params) = query?;
+    let mut events = state.event_repo.lock().await;
+    let mut users = state.user_repo.lock().await;
+    let store = Store {
+        events: &mut **events,
+        users: Some(&mut **users),
+    };
+    let report = archive::import(store, body.as_bytes(), params.keep_roles)?;
+    event!(
+        Level::INFO,
+        "imported {} records, {} duplicate, {} rejected",
+        report.accepted,
+        report.duplicate,
+        report.rejected
+    );
+    Ok(Json(report))
+}
 
-use axum::http::HeaderMap;
+#[derive(Debug, Deserialize)]
+struct SessionParams {
+    /// Apply the mute list to the session's requests by default.
+    #[serde(default)]
+    apply_mutes: bool,
+}
 
-async fn test_middleware_mutex<B>(
+async fn save_session(
     headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    query: Result<Query<SessionParams>, QueryRejection>,
     State(state): State<AppState>,
-    request: Request<B>,
-    next: Next<B>,
-) -> Result<Response, StatusCode> {
-
-    let auth_header = headers.get("authorization");
-    let auth_parts = if auth_header.is_some(){
-        auth_header.unwrap().to_str()
-    } else {
-        return Err(StatusCode::BAD_REQUEST)
+) -> Result<StatusCode, ApiError> {
+    let Query(params) = query?;
+    let token = match auth::credentials(&headers)? {
+        Some(Credentials::Bearer(token)) => token,
+        _ => {
+            return Err(ApiError::Unauthorized(
+                "expected Authorization: Bearer <public key>".to_string(),
+            ))
+        }
     };
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    moderation::moderate(&state, Some(token), ip, false).await?;
 
-    let auth_part = match  auth_parts {
-        Ok(v) => v.split(" ").nth(1),
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let session = Session::try_new(token.to_string(), now + state.config.session.ttl)?
+        .with_apply_mutes(params.apply_mutes);
+    let mut session_repo = state.session_repo.lock().await; //.expect("mutex was poisoned");
+    session_repo.add(session);
+    let msg = format!("new session for  {}", token);
+    event!(Level::INFO, msg);
+    Ok(StatusCode::CREATED)
+}
 
-    let token = match  auth_part {
-        Some(v) => v,
-        _ => return Err(StatusCode::BAD_REQUEST),
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use entity::KeyPair;
+    use rustr_core::conformance::event;
 
-    println!("middleware {:?}",token);
+    fn admin() -> Extension<Caller> {
+        Extension(Caller {
+            public_key: "a".repeat(64),
+            session: None,
+            role: Role::Admin,
+        })
+    }
 
-    let mut sr = state.session_repo.lock().await;
-    let  s =  sr.read(&token.to_string());
-    if s.is_some() {
-        println!("is da");
-    } else {
-        return Err(StatusCode::UNAUTHORIZED)
+    #[tokio::test]
+    async fn test_admin_writes_in_allowlist_mode() {
+        let (admin_kp, other_kp) = (KeyPair::generate(), KeyPair::generate());
+        let mut config = Config::default();
+        config.auth.admin = Some(admin_kp.public_key());
+        let state = AppState::in_memory(config);
+        let mut lists = rustr_core::moderation::Moderation::default();
+        lists.allow.mode = rustr_core::moderation::Mode::Allowlist;
+        state.moderation.lock().await.save(lists).unwrap();
+        let post = |kp: &KeyPair, caller: Option<Extension<Caller>>| {
+            let mut e = Event::new(kp.public_key(), "hello".to_string(), 0);
+            e.sign(kp.private_key());
+            save_event(caller, State(state.clone()), None, Ok(Json(e)))
+        };
+        let caller = Extension(Caller {
+            public_key: admin_kp.public_key(),
+            session: None,
+            role: Role::Admin,
+        });
+        assert_eq!(
+            post(&admin_kp, Some(caller)).await.unwrap(),
+            StatusCode::CREATED
+        );
+        assert!(matches!(
+            post(&other_kp, None).await,
+            Err(ApiError::Forbidden(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_streams_pages() {
+        let state = AppState::in_memory(Config::default());
+        {
+            let mut events = state.event_repo.lock().await;
+            for i in 0..archive::PAGE as u64 + 10 {
+                events
+                    .add(event(&format!("e{}", i), "alice", 1, i))
+                    .unwrap();
+            }
+            let mut users = state.user_repo.lock().await;
+            users.add_user(User::new("alice".to_string(), "b".repeat(64)));
+        }
+        let params = Ok(Query(HashMap::new()));
+        let response = export_store(params, Some(admin()), State(state))
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let records: Vec<Record> = body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), archive::PAGE + 11);
+        assert!(
+            matches!(&records[0], Record::Event(e) if e.id == format!("e{}", archive::PAGE + 9))
+        );
+        assert!(matches!(records.last(), Some(Record::User(_))));
     }
-    let response = next.run(request).await;
-        Ok(response)
 }
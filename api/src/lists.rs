@@ -0,0 +1,88 @@
+//! Reading the lists of a user (see [`entity::lists`]).
+//!
+//! `GET /users/:pubkey/lists/:kind` returns every list of that kind the
+//! user published: at most one for replaceable kinds, one per set for
+//! addressable ones. `GET /users/:pubkey/lists/:kind/:d` returns the set
+//! named `d`. Private items stay encrypted; only the owner can read them.
+
+use crate::auth::Caller;
+use crate::circles::viewer;
+use crate::follows::key_forms;
+use crate::{ApiError, AppState};
+use axum::extract::rejection::PathRejection;
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use entity::lists::{self, List, Published};
+use entity::{kind, Filter};
+use rustr_core::circle::Audience;
+use rustr_core::repository::EventRepo;
+
+fn check_kind(kind: u32) -> Result<(), ApiError> {
+    match lists::is_list(kind) {
+        true => Ok(()),
+        false => Err(ApiError::BadRequest(format!("kind {} is not a list", kind))),
+    }
+}
+
+fn published(events: &mut dyn EventRepo, filter: &Filter) -> Vec<Published> {
+    events
+        .query(filter)
+        .into_iter()
+        .filter_map(|event| {
+            List::from_event(&event)
+                .ok()
+                .map(|list| Published { list, event })
+        })
+        .collect()
+}
+
+pub async fn read_lists(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<(String, u32)>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<Published>>, ApiError> {
+    let Path((public_key, kind)) = path?;
+    check_kind(kind)?;
+    let filter = Filter {
+        authors: Some(key_forms(&public_key)),
+        kinds: Some(vec![kind]),
+        // a key may have published a replaceable list in both key forms
+        limit: (!kind::is_addressable(kind)).then_some(1),
+        ..Filter::new()
+    };
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, viewer(&caller));
+    Ok(Json(published(&mut events, &filter)))
+}
+
+pub async fn read_list(
+    caller: Option<Extension<Caller>>,
+    path: Result<Path<(String, u32, String)>, PathRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Published>, ApiError> {
+    let Path((public_key, kind, d)) = path?;
+    check_kind(kind)?;
+    if !kind::is_addressable(kind) {
+        return Err(ApiError::BadRequest(format!(
+            "lists of kind {} have no name, use /users/{}/lists/{}",
+            kind, public_key, kind
+        )));
+    }
+    let filter = Filter {
+        authors: Some(key_forms(&public_key)),
+        kinds: Some(vec![kind]),
+        limit: Some(1),
+        ..Filter::new().with_tag("d", vec![d.clone()])
+    };
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, viewer(&caller));
+    match published(&mut events, &filter).into_iter().next() {
+        Some(list) => Ok(Json(list)),
+        None => Err(ApiError::NotFound(format!(
+            "{} has no list {} of kind {}",
+            public_key, d, kind
+        ))),
+    }
+}
@@ -0,0 +1,152 @@
+//! Admin API for the moderation lists and the checks applying them.
+//!
+//! `GET /admin/bans` and `GET /admin/allow` return the lists, `POST` adds
+//! the entries in the body and `DELETE` removes them. The allow list's mode
+//! is changed by posting it along with (or instead of) entries, e.g.
+//! `{"mode":"allowlist","pubkeys":["..."]}`. The admin is never moderated.
+
+use crate::auth::Caller;
+use crate::{require_admin, ApiError, AppState};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::{Extension, Json};
+use entity::Event;
+use rustr_core::moderation::{Allow, Bans, Clients, Mode, Moderation};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Whether a client may use the relay, or write if `write`.
+pub(crate) async fn moderate(
+    state: &AppState,
+    pubkey: Option<&str>,
+    ip: Option<IpAddr>,
+    write: bool,
+) -> Result<(), ApiError> {
+    if pubkey.is_some_and(|pk| state.config.auth.is_admin(pk)) {
+        return Ok(());
+    }
+    let moderation = state.moderation.lock().await;
+    moderation
+        .lists()
+        .check_client(pubkey, ip, write)
+        .map_err(ApiError::Forbidden)
+}
+
+/// Whether `e` may be stored, judged by its id, kind and author.
+pub(crate) async fn moderate_event(state: &AppState, e: &Event) -> Result<(), ApiError> {
+    if state.config.auth.is_admin(&e.public_key) {
+        return Ok(());
+    }
+    let moderation = state.moderation.lock().await;
+    moderation
+        .lists()
+        .check_event(e)
+        .map_err(ApiError::Forbidden)
+}
+
+/// Applies `change` to the lists and persists the result.
+async fn update(
+    state: &AppState,
+    change: impl FnOnce(&mut Moderation),
+) -> Result<Moderation, ApiError> {
+    let mut moderation = state.moderation.lock().await;
+    let mut lists = moderation.lists().clone();
+    change(&mut lists);
+    moderation.save(lists.clone())?;
+    Ok(lists)
+}
+
+fn add(clients: &mut Clients, other: Clients) {
+    clients.pubkeys.extend(other.pubkeys);
+    clients.ips.extend(other.ips);
+}
+
+fn remove(clients: &mut Clients, other: &Clients) {
+    clients.pubkeys.retain(|pk| !other.pubkeys.contains(pk));
+    clients.ips.retain(|ip| !other.ips.contains(ip));
+}
+
+pub async fn read_bans(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+) -> Result<Json<Bans>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let moderation = state.moderation.lock().await;
+    Ok(Json(moderation.lists().bans.clone()))
+}
+
+pub async fn add_bans(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<Bans>, JsonRejection>,
+) -> Result<Json<Bans>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Json(bans) = payload?;
+    let lists = update(&state, |lists| {
+        add(&mut lists.bans.clients, bans.clients);
+        lists.bans.events.extend(bans.events);
+        lists.bans.kinds.extend(bans.kinds);
+    })
+    .await?;
+    Ok(Json(lists.bans))
+}
+
+pub async fn remove_bans(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<Bans>, JsonRejection>,
+) -> Result<Json<Bans>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Json(bans) = payload?;
+    let lists = update(&state, |lists| {
+        remove(&mut lists.bans.clients, &bans.clients);
+        lists.bans.events.retain(|id| !bans.events.contains(id));
+        lists.bans.kinds.retain(|k| !bans.kinds.contains(k));
+    })
+    .await?;
+    Ok(Json(lists.bans))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AllowChange {
+    mode: Option<Mode>,
+    #[serde(flatten)]
+    clients: Clients,
+}
+
+pub async fn read_allow(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+) -> Result<Json<Allow>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let moderation = state.moderation.lock().await;
+    Ok(Json(moderation.lists().allow.clone()))
+}
+
+pub async fn add_allow(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<AllowChange>, JsonRejection>,
+) -> Result<Json<Allow>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Json(change) = payload?;
+    let lists = update(&state, |lists| {
+        if let Some(mode) = change.mode {
+            lists.allow.mode = mode;
+        }
+        add(&mut lists.allow.clients, change.clients);
+    })
+    .await?;
+    Ok(Json(lists.allow))
+}
+
+pub async fn remove_allow(
+    caller: Option<Extension<Caller>>,
+    State(state): State<AppState>,
+    payload: Result<Json<Clients>, JsonRejection>,
+) -> Result<Json<Allow>, ApiError> {
+    require_admin(&state, caller.as_ref())?;
+    let Json(clients) = payload?;
+    let lists = update(&state, |lists| remove(&mut lists.allow.clients, &clients)).await?;
+    Ok(Json(lists.allow))
+}
@@ -0,0 +1,57 @@
+//! Server-side muting: `/events`, `/feed`, `/events/stream` and `/events/ws`
+//! leave out what the caller's mute list hides when asked with
+//! `?apply_mutes=true`, or by default for sessions opened with
+//! `POST /authenticate?apply_mutes=true`.
+//! Only the public entries can be applied here; the private ones are
+//! encrypted for the caller.
+
+use crate::auth::Caller;
+use crate::follows::latest_list;
+use crate::{ApiError, AppState};
+use entity::kind;
+use entity::mutes::MuteList;
+
+/// Parses an `apply_mutes` query value.
+pub(crate) fn parse_flag(value: Option<&str>) -> Result<Option<bool>, ApiError> {
+    match value {
+        None => Ok(None),
+        Some("true") => Ok(Some(true)),
+        Some("false") => Ok(Some(false)),
+        Some(v) => Err(ApiError::BadRequest(format!(
+            "invalid value for apply_mutes: {}",
+            v
+        ))),
+    }
+}
+
+/// The mute list to apply for `caller`, if muting is `requested` or, when
+/// that is not given, turned on for the caller's session.
+pub(crate) async fn mutes_for(
+    state: &AppState,
+    caller: Option<&Caller>,
+    requested: Option<bool>,
+) -> Result<Option<MuteList>, ApiError> {
+    let apply = match (requested, caller.and_then(|c| c.session.as_deref())) {
+        (Some(apply), _) => apply,
+        (None, Some(session)) => state
+            .session_repo
+            .lock()
+            .await
+            .read(session)
+            .is_some_and(|s| s.apply_mutes()),
+        (None, None) => false,
+    };
+    if !apply {
+        return Ok(None);
+    }
+    let Some(caller) = caller else {
+        return Err(ApiError::Unauthorized(
+            "apply_mutes needs an authenticated caller".to_string(),
+        ));
+    };
+    let mut events = state.event_repo.lock().await;
+    let list = latest_list(&mut **events, &caller.public_key, kind::MUTE_LIST)
+        .and_then(|e| MuteList::from_event(&e).ok())
+        .unwrap_or_default();
+    Ok(Some(list))
+}
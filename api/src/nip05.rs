@@ -0,0 +1,50 @@
+//! `/.well-known/nostr.json` (NIP-05), which lets clients resolve
+//! `<name>@<relay domain>` to the public key registered under that name.
+//!
+//! `?name=<name>` asks for a single name; without it, all registered names
+//! are listed. Unknown names yield an empty document rather than `404`.
+
+use crate::{ApiError, AppState};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use entity::nip05::Document;
+use entity::User;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    name: Option<String>,
+}
+
+pub async fn nostr_json(
+    query: Result<Query<Params>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    let Query(params) = query?;
+    let users: Vec<User> = {
+        let users = state.user_repo.lock().await;
+        match &params.name {
+            Some(name) => users.find_user_by_name(name).cloned().into_iter().collect(),
+            None => users.read_all_users(),
+        }
+    };
+    let mut document = Document::default();
+    for user in users {
+        let public_key = crypto::x_only(&user.public_key).to_string();
+        if let Some(url) = &state.config.relay.url {
+            document
+                .relays
+                .insert(public_key.clone(), vec![url.clone()]);
+        }
+        document.names.insert(user.name, public_key);
+    }
+    let mut response = Json(document).into_response();
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        HeaderValue::from_static("*"),
+    );
+    Ok(response)
+}
@@ -0,0 +1,145 @@
+//! The caller's inbox: events of others that name the caller in a `p` tag,
+//! such as replies, mentions, reactions, reposts and direct messages.
+//!
+//! `GET /notifications` returns them newest first, paged like `/feed` and
+//! optionally restricted by `since` and `kinds`, together with how many
+//! arrived after the caller's "last seen" marker.
+//! `POST /notifications/seen?at=<created_at>` moves the marker forward, by
+//! default to the current time.
+
+use crate::auth::Caller;
+use crate::follows::{key_forms, parse_before, parse_kinds};
+use crate::sse::cursor;
+use crate::{ApiError, AppState};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::{Extension, Json};
+use entity::{Event, Filter};
+use rustr_core::circle::Audience;
+use rustr_core::repository::EventRepo;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct Params {
+    limit: Option<usize>,
+    /// `<created_at>:<id>` of the last event of the previous page.
+    before: Option<String>,
+    since: Option<u64>,
+    /// Comma separated.
+    kinds: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inbox {
+    pub events: Vec<Event>,
+    pub next: Option<String>,
+    /// Notifications created after `last_seen`.
+    pub unread: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SeenParams {
+    at: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Seen {
+    pub last_seen: u64,
+}
+
+fn caller(caller: Option<Extension<Caller>>) -> Result<Caller, ApiError> {
+    match caller {
+        Some(Extension(caller)) => Ok(caller),
+        None => Err(ApiError::Unauthorized("authorization required".to_string())),
+    }
+}
+
+/// Notifications of `public_key` after `since`, not counting those the key
+/// sent itself, e.g. by reacting to its own notes.
+fn unread(
+    events: &mut dyn EventRepo,
+    public_key: &str,
+    kinds: Option<Vec<u32>>,
+    since: u64,
+) -> u64 {
+    let all = Filter {
+        kinds,
+        since: Some(since.saturating_add(1)),
+        ..Filter::new().with_tag("p", key_forms(public_key))
+    };
+    let own = Filter {
+        authors: Some(key_forms(public_key)),
+        ..all.clone()
+    };
+    let total = events.count(&[all]).count;
+    total.saturating_sub(events.count(&[own]).count)
+}
+
+pub async fn read_notifications(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<Params>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Inbox>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Query(params) = query?;
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::BadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_LIMIT
+        )));
+    }
+    let before = parse_before(params.before.as_deref())?;
+    let kinds = parse_kinds(params.kinds.as_deref())?;
+
+    let last_seen = state.user_repo.lock().await.last_seen(&caller.public_key);
+    let circles = state.circles.lock().await;
+    let mut events = state.event_repo.lock().await;
+    let mut events = Audience::new(&mut **events, &**circles, Some(&caller.public_key));
+    let own = key_forms(&caller.public_key);
+    let filter = Filter {
+        kinds: kinds.clone(),
+        since: params.since,
+        until: before.as_ref().map(|(created_at, _)| *created_at),
+        ..Filter::new().with_tag("p", own.clone())
+    };
+    let mut page = events.query(&filter);
+    page.retain(|e| !own.contains(&e.public_key));
+    if let Some((created_at, id)) = before {
+        page.retain(|e| (e.created_at, &e.id) < (created_at, &id));
+    }
+    let next = (page.len() > limit).then(|| cursor(&page[limit - 1]));
+    page.truncate(limit);
+    let unread = unread(&mut events, &caller.public_key, kinds, last_seen);
+    Ok(Json(Inbox {
+        events: page,
+        next,
+        unread,
+        last_seen,
+    }))
+}
+
+pub async fn mark_seen(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<SeenParams>, QueryRejection>,
+    State(state): State<AppState>,
+) -> Result<Json<Seen>, ApiError> {
+    let caller = self::caller(caller)?;
+    let Query(params) = query?;
+    let at = params.at.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+    });
+    let mut users = state.user_repo.lock().await;
+    users.mark_seen(&caller.public_key, at);
+    Ok(Json(Seen {
+        last_seen: users.last_seen(&caller.public_key),
+    }))
+}
@@ -0,0 +1,274 @@
+//! Token-bucket rate limiting per client IP, public key and session.
+//!
+//! Every request belongs to one [`Class`] and takes a token from the bucket
+//! of each key it can be attributed to. It is only let through if all of
+//! them have a token left; otherwise it is answered with `429` and a
+//! `Retry-After` of the time until the emptiest bucket refills. A bucket
+//! that has refilled completely is equivalent to none, so such buckets are
+//! dropped whenever the table reaches its size limit.
+//!
+//! [`rate_limit`] charges the client IP before anything else runs. The
+//! buckets of the caller's public key and session are charged by the auth
+//! layer through [`charge_caller`], once the credentials are verified, so
+//! nobody can drain the buckets of a key they do not hold. Requests sent over
+//! an open WebSocket are charged to the buckets of the IP and caller the
+//! connection was opened by, see [`connection_keys`].
+
+use crate::auth::Caller;
+use crate::config::{Rate, RateLimits};
+use crate::{ApiError, AppState};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{Method, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Read,
+    Write,
+    Auth,
+    Subscription,
+}
+
+impl Class {
+    pub(crate) fn of(method: &Method, path: &str) -> Class {
+        if path == "/authenticate" {
+            Class::Auth
+        } else if path == "/events/stream" || path == "/events/ws" {
+            Class::Subscription
+        } else if matches!(*method, Method::GET | Method::HEAD) {
+            Class::Read
+        } else {
+            Class::Write
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Ip(IpAddr),
+    PublicKey(String),
+    Session(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second()).min(rate.burst as f64);
+        self.updated = now;
+    }
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(Class, Key), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        RateLimiter {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for every key, or none and returns how long to wait.
+    pub fn check(&self, class: Class, keys: &[Key], now: Instant) -> Result<(), Duration> {
+        let Some(rate) = self.limits.rate(class) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().expect("rate limiter poisoned");
+        if buckets.len() + keys.len() > self.limits.max_clients {
+            evict(&mut buckets, &self.limits, now);
+        }
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let bucket = buckets.entry((class, key.clone())).or_insert(Bucket {
+                tokens: rate.burst as f64,
+                updated: now,
+            });
+            bucket.refill(rate, now);
+            if bucket.tokens < 1.0 {
+                let missing = (1.0 - bucket.tokens) / rate.per_second();
+                wait = wait.max(Duration::from_secs_f64(missing));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(&(class, key.clone())) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn tracked(&self) -> usize {
+        self.buckets.lock().expect("rate limiter poisoned").len()
+    }
+}
+
+/// Drops full buckets, then the least recently used ones until a quarter
+/// of the table is free again.
+fn evict(buckets: &mut HashMap<(Class, Key), Bucket>, limits: &RateLimits, now: Instant) {
+    buckets.retain(|(class, _), bucket| match limits.rate(*class) {
+        Some(rate) => {
+            let mut b = *bucket;
+            b.refill(rate, now);
+            b.tokens < rate.burst as f64
+        }
+        None => false,
+    });
+    let target = limits.max_clients - limits.max_clients / 4;
+    if buckets.len() > target {
+        let mut by_age: Vec<_> = buckets
+            .iter()
+            .map(|(k, b)| (b.updated, k.clone()))
+            .collect();
+        by_age.sort_by_key(|(updated, _)| *updated);
+        for (_, key) in by_age.into_iter().take(buckets.len() - target) {
+            buckets.remove(&key);
+        }
+    }
+}
+
+fn limited(class: Class, wait: Duration) -> ApiError {
+    ApiError::RateLimited {
+        message: format!("too many {:?} requests", class).to_lowercase(),
+        retry_after: wait.as_secs_f64().ceil() as u64,
+    }
+}
+
+pub async fn rate_limit<B>(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, ApiError> {
+    let class = Class::of(request.method(), request.uri().path());
+    let keys: Vec<Key> = connect_info
+        .map(|ConnectInfo(addr)| Key::Ip(addr.ip()))
+        .into_iter()
+        .collect();
+    match state.limiter.check(class, &keys, Instant::now()) {
+        Ok(()) => Ok(next.run(request).await),
+        Err(wait) => Err(limited(class, wait)),
+    }
+}
+
+/// Takes a token from the buckets of the verified `caller`'s public key,
+/// in either key form, and session.
+pub(crate) fn charge_caller(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    caller: &Caller,
+) -> Result<(), ApiError> {
+    let class = Class::of(method, path);
+    state
+        .limiter
+        .check(class, &caller_keys(caller), Instant::now())
+        .map_err(|wait| limited(class, wait))
+}
+
+fn caller_keys(caller: &Caller) -> Vec<Key> {
+    let mut keys = vec![Key::PublicKey(
+        crypto::x_only(&caller.public_key).to_string(),
+    )];
+    if let Some(session) = &caller.session {
+        keys.push(Key::Session(session.clone()));
+    }
+    keys
+}
+
+/// The buckets of a connection opened from `ip` by `caller`.
+pub(crate) fn connection_keys(ip: Option<IpAddr>, caller: Option<&Caller>) -> Vec<Key> {
+    let mut keys: Vec<Key> = ip.map(Key::Ip).into_iter().collect();
+    keys.extend(caller.map(caller_keys).unwrap_or_default());
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_clients: usize) -> RateLimits {
+        RateLimits {
+            writes: Some(Rate {
+                per_minute: 60,
+                burst: 2,
+            }),
+            reads: None,
+            max_clients,
+            ..RateLimits::default()
+        }
+    }
+
+    #[test]
+    fn test_buckets() {
+        let limiter = RateLimiter::new(limits(100));
+        let now = Instant::now();
+        let ip = Key::Ip(IpAddr::from([127, 0, 0, 1]));
+        let alice = Key::PublicKey("alice".to_string());
+        let keys = [ip.clone(), alice.clone()];
+        assert!(limiter.check(Class::Write, &keys, now).is_ok());
+        assert!(limiter.check(Class::Write, &keys, now).is_ok());
+        assert_eq!(
+            limiter.check(Class::Write, &keys, now),
+            Err(Duration::from_secs(1))
+        );
+        // the same ip with another key is still limited by the ip
+        let bob = [ip.clone(), Key::PublicKey("bob".to_string())];
+        assert!(limiter.check(Class::Write, &bob, now).is_err());
+        // and a rejected request takes no token from bob
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.check(Class::Write, &keys, later).is_ok());
+        assert!(limiter.check(Class::Write, &[alice], later).is_err());
+        // unlimited classes are not tracked
+        for _ in 0..10 {
+            assert!(limiter.check(Class::Read, &keys, now).is_ok());
+        }
+        assert_eq!(limiter.tracked(), 3);
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(Class::of(&Method::GET, "/events"), Class::Read);
+        assert_eq!(Class::of(&Method::POST, "/events"), Class::Write);
+        assert_eq!(Class::of(&Method::POST, "/authenticate"), Class::Auth);
+        for path in ["/events/stream", "/events/ws"] {
+            assert_eq!(Class::of(&Method::GET, path), Class::Subscription);
+        }
+    }
+
+    #[test]
+    fn test_eviction() {
+        let limiter = RateLimiter::new(limits(8));
+        let now = Instant::now();
+        for i in 0..8u8 {
+            let key = Key::Ip(IpAddr::from([10, 0, 0, i]));
+            limiter.check(Class::Write, &[key], now).unwrap();
+        }
+        let refilled = now + Duration::from_secs(1);
+        let key = Key::Ip(IpAddr::from([10, 0, 1, 0]));
+        limiter.check(Class::Write, &[key], refilled).unwrap();
+        assert_eq!(limiter.tracked(), 1);
+
+        for i in 0..8u8 {
+            let key = Key::Ip(IpAddr::from([10, 0, 2, i]));
+            limiter.check(Class::Write, &[key], refilled).unwrap();
+        }
+        assert!(limiter.tracked() <= 8);
+    }
+}
@@ -0,0 +1,146 @@
+//! `GET /events/stream`: stored and live events as server-sent events.
+//!
+//! Stored events matching the filter are sent oldest first, followed by new
+//! events as they are stored. Every message carries `<created_at>:<id>` as
+//! its id, so a client that reconnects with `Last-Event-ID` only receives
+//! stored events after that point. A client too slow for the change feed is
+//! disconnected and expected to reconnect the same way. Events addressed to
+//! a circle are only streamed to its members, judged by the membership at
+//! the time each event arrives.
+
+use crate::auth::Caller;
+use crate::{circles, filter_from_params, mutes, ApiError, AppState};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::Extension;
+use entity::mutes::MuteList;
+use entity::{Event, Filter};
+use futures_util::stream::{self, Stream};
+use rustr_core::circle::{self, Audience, CircleRepo};
+use rustr_core::feed::{Change, Subscription};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::{event, Level};
+
+pub async fn stream_events(
+    caller: Option<Extension<Caller>>,
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, ApiError> {
+    let Query(mut params) = query?;
+    let apply_mutes = mutes::parse_flag(params.remove("apply_mutes").as_deref())?;
+    let filter = filter_from_params(&params)?;
+    // the mute list in effect when the stream starts
+    let mutes = Arc::new(
+        mutes::mutes_for(&state, caller.as_deref(), apply_mutes)
+            .await?
+            .unwrap_or_default(),
+    );
+    let resume = match headers.get("last-event-id") {
+        Some(v) => Some(
+            v.to_str()
+                .ok()
+                .and_then(parse_cursor)
+                .ok_or_else(|| ApiError::BadRequest("invalid Last-Event-ID".to_string()))?,
+        ),
+        None => None,
+    };
+    // subscribe before reading the store so nothing stored in between is lost
+    let changes = state.feed.subscribe();
+    let viewer = circles::viewer(&caller).map(str::to_string);
+    let mut backlog = {
+        let circles = state.circles.lock().await;
+        let mut events = state.event_repo.lock().await;
+        let mut events = Audience::new(&mut **events, &**circles, viewer.as_deref());
+        backlog(&mut events, &filter, resume)
+    };
+    let sent: HashSet<String> = backlog.iter().map(|e| e.id.clone()).collect();
+    backlog.retain(|e| !mutes.mutes(e));
+    let stream = stream::unfold(
+        (backlog, changes, filter, sent),
+        move |(mut backlog, mut changes, filter, sent)| {
+            let mutes = mutes.clone();
+            let circles = state.circles.clone();
+            let viewer = viewer.clone();
+            async move {
+                if let Some(e) = backlog.pop_front() {
+                    return Some((Ok(message(&e)), (backlog, changes, filter, sent)));
+                }
+                let viewer = viewer.as_deref();
+                let e = next_match(&mut changes, &filter, &mutes, &circles, viewer, &sent).await?;
+                Some((Ok(message(&e)), (backlog, changes, filter, sent)))
+            }
+        },
+    );
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Stored events matching `filter`, oldest first, after `resume` if given.
+fn backlog(
+    events: &mut dyn rustr_core::repository::EventRepo,
+    filter: &Filter,
+    resume: Option<(u64, String)>,
+) -> VecDeque<Event> {
+    let mut filter = filter.clone();
+    if let Some((created_at, _)) = &resume {
+        filter.since = Some(filter.since.map_or(*created_at, |s| s.max(*created_at)));
+    }
+    let mut backlog = events.query(&filter);
+    backlog.reverse();
+    if let Some((created_at, id)) = resume {
+        backlog.retain(|e| (e.created_at, &e.id) > (created_at, &id));
+    }
+    backlog.into()
+}
+
+/// Waits for the next stored event matching `filter` that is not muted and
+/// that `viewer` may see; `None` ends the stream.
+async fn next_match(
+    changes: &mut Subscription,
+    filter: &Filter,
+    mutes: &MuteList,
+    circles: &Mutex<Box<dyn CircleRepo + Send + Sync>>,
+    viewer: Option<&str>,
+    sent: &HashSet<String>,
+) -> Option<Event> {
+    loop {
+        let e = match changes.recv().await {
+            Ok(Change::Added(e) | Change::Replaced { new: e, .. }) => e,
+            Ok(_) => continue,
+            Err(e) => {
+                event!(Level::INFO, "closing event stream: {}", e);
+                return None;
+            }
+        };
+        if !filter.matches(&e) || mutes.mutes(&e) || sent.contains(&e.id) {
+            continue;
+        }
+        // the circles are only locked for events addressed to one
+        if entity::circle::audience(&e).is_none()
+            || circle::visible(&**circles.lock().await, viewer, &e)
+        {
+            return Some(e);
+        }
+    }
+}
+
+fn message(e: &Event) -> SseEvent {
+    SseEvent::default()
+        .id(cursor(e))
+        .data(serde_json::to_string(e).expect("events serialize"))
+}
+
+/// The position of `e` in a stream or page, as `<created_at>:<id>`.
+pub(crate) fn cursor(e: &Event) -> String {
+    format!("{}:{}", e.created_at, e.id)
+}
+
+pub(crate) fn parse_cursor(v: &str) -> Option<(u64, String)> {
+    let (created_at, id) = v.split_once(':')?;
+    Some((created_at.parse().ok()?, id.to_string()))
+}
@@ -0,0 +1,473 @@
+//! `GET /events/ws`: a WebSocket speaking the relay message format of
+//! NIP-01 for reading.
+//!
+//! `["REQ", <subscription id>, <filter>...]` is answered with the stored
+//! events matching any of the filters as `["EVENT", <subscription id>,
+//! <event>]`, up to each filter's `limit`, then `["EOSE", <subscription
+//! id>]`, and from then on with every new event matching one of them until
+//! `["CLOSE", <subscription id>]`. Filters may carry a `search` (NIP-50).
+//! `["COUNT", <subscription id>, <filter>...]` (NIP-45) gets
+//! `["COUNT", <subscription id>, {"count": <n>}]`.
+//!
+//! An invalid request, or a `REQ` beyond `limits.max_subscriptions`, gets
+//! `["CLOSED", <subscription id>, <reason>]`, and a connection too slow for
+//! the change feed gets it for every subscription it had open. Every `REQ`
+//! and `COUNT` takes a token from the subscription buckets of the client IP
+//! and caller, and is closed with a `rate-limited:` reason while one is
+//! empty.
+//!
+//! Events addressed to a circle are only sent to its members. With
+//! `?apply_mutes=true` on the upgrade request, or by default for sessions
+//! opened that way, events the caller's mute list hides are left out of
+//! every subscription, judged by the list at the time of its `REQ`.
+//! Events are published with `POST /events`; `EVENT` and any other message
+//! is refused with a `NOTICE`.
+
+use crate::auth::Caller;
+use crate::ratelimit::{self, Class, Key};
+use crate::{mutes, ApiError, AppState};
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Query, State};
+use axum::response::Response;
+use axum::Extension;
+use entity::mutes::MuteList;
+use entity::{Event, Filter};
+use rustr_core::circle::{self, Audience};
+use rustr_core::feed::{Change, FeedError, Subscription};
+use rustr_core::repository::EventRepo;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::time::Instant;
+use tracing::{event, Level};
+
+pub async fn socket(
+    caller: Option<Extension<Caller>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    query: Result<Query<HashMap<String, String>>, QueryRejection>,
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+) -> Result<Response, ApiError> {
+    let Query(params) = query?;
+    let apply_mutes = mutes::parse_flag(params.get("apply_mutes").map(String::as_str))?;
+    let caller = caller.map(|Extension(caller)| caller);
+    let ip = connect_info.map(|ConnectInfo(addr)| addr.ip());
+    let buckets = ratelimit::connection_keys(ip, caller.as_ref());
+    let connection = Connection::new(state, caller, apply_mutes, buckets);
+    Ok(ws.on_upgrade(|socket| serve(socket, connection)))
+}
+
+async fn serve(mut socket: WebSocket, mut connection: Connection) {
+    loop {
+        let replies = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => connection.answer(&text).await,
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                // pings are answered by axum
+                Some(Ok(_)) => continue,
+            },
+            change = next_change(&mut connection.changes) => match change {
+                Ok(change) => connection.forward(&change).await,
+                Err(FeedError::Lagged(n)) => connection.drop_all(n),
+                Err(FeedError::Closed) => break,
+            },
+        };
+        for reply in replies {
+            if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The next change, or never if nothing is subscribed.
+async fn next_change(changes: &mut Option<Subscription>) -> Result<Change, FeedError> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+struct Open {
+    filters: Vec<Filter>,
+    /// The caller's mute list when the subscription was opened.
+    mutes: MuteList,
+    /// Events already sent as stored ones, which may be stored again
+    /// between subscribing to the feed and reading the store.
+    sent: HashSet<String>,
+}
+
+struct Connection {
+    state: AppState,
+    /// Who the upgrade request was authorized as.
+    caller: Option<Caller>,
+    /// The `apply_mutes` flag of the upgrade request.
+    apply_mutes: Option<bool>,
+    /// The rate limiter buckets requests are charged to.
+    buckets: Vec<Key>,
+    subscriptions: HashMap<String, Open>,
+    /// Changes to the store while any subscription is open.
+    changes: Option<Subscription>,
+}
+
+impl Connection {
+    fn new(
+        state: AppState,
+        caller: Option<Caller>,
+        apply_mutes: Option<bool>,
+        buckets: Vec<Key>,
+    ) -> Connection {
+        Connection {
+            state,
+            caller,
+            apply_mutes,
+            buckets,
+            subscriptions: HashMap::new(),
+            changes: None,
+        }
+    }
+
+    fn viewer(&self) -> Option<&str> {
+        self.caller.as_ref().map(|c| c.public_key.as_str())
+    }
+
+    async fn answer(&mut self, text: &str) -> Vec<Value> {
+        let request = parse(text);
+        if let Ok(Request::Req { subscription, .. } | Request::Count { subscription, .. }) =
+            &request
+        {
+            if let Err(reason) = self.charge() {
+                return vec![json!(["CLOSED", subscription, reason])];
+            }
+        }
+        match request {
+            Ok(Request::Req {
+                subscription,
+                filters,
+            }) => self.subscribe(subscription, filters).await,
+            Ok(Request::Close { subscription }) => {
+                self.subscriptions.remove(&subscription);
+                if self.subscriptions.is_empty() {
+                    self.changes = None;
+                }
+                Vec::new()
+            }
+            Ok(Request::Count {
+                subscription,
+                filters,
+            }) => {
+                let circles = self.state.circles.lock().await;
+                let mut events = self.state.event_repo.lock().await;
+                let count = Audience::new(&mut **events, &**circles, self.viewer()).count(&filters);
+                event!(Level::DEBUG, "count {} is {:?}", subscription, count);
+                vec![json!(["COUNT", subscription, count])]
+            }
+            Err(Refusal::Closed(subscription, reason)) => {
+                vec![json!(["CLOSED", subscription, reason])]
+            }
+            Err(Refusal::Notice(reason)) => vec![json!(["NOTICE", reason])],
+        }
+    }
+
+    /// Opens or replaces `subscription` and returns its stored events.
+    async fn subscribe(&mut self, subscription: String, filters: Vec<Filter>) -> Vec<Value> {
+        let open = self.subscriptions.len();
+        if let Some(max) = self
+            .state
+            .config
+            .limits
+            .max_subscriptions
+            .filter(|max| open >= *max && !self.subscriptions.contains_key(&subscription))
+        {
+            let reason = format!("error: at most {} subscriptions", max);
+            return vec![json!(["CLOSED", subscription, reason])];
+        }
+        let requested = self.apply_mutes;
+        let mutes = match mutes::mutes_for(&self.state, self.caller.as_ref(), requested).await {
+            Ok(mutes) => mutes.unwrap_or_default(),
+            Err(e) => {
+                let reason = format!("error: {}", e.message());
+                return vec![json!(["CLOSED", subscription, reason])];
+            }
+        };
+        // subscribe before reading the store so nothing stored in between is lost
+        if self.changes.is_none() {
+            self.changes = Some(self.state.feed.subscribe());
+        }
+        let stored = {
+            let circles = self.state.circles.lock().await;
+            let mut events = self.state.event_repo.lock().await;
+            let mut events = Audience::new(&mut **events, &**circles, self.viewer());
+            stored(&mut events, &filters)
+        };
+        let mut replies: Vec<Value> = stored
+            .iter()
+            .filter(|e| !mutes.mutes(e))
+            .map(|e| json!(["EVENT", subscription, e]))
+            .collect();
+        replies.push(json!(["EOSE", subscription]));
+        let sent = stored.into_iter().map(|e| e.id).collect();
+        let open = Open {
+            filters,
+            mutes,
+            sent,
+        };
+        self.subscriptions.insert(subscription, open);
+        replies
+    }
+
+    /// The `EVENT` messages for the subscriptions `change` matches.
+    async fn forward(&mut self, change: &Change) -> Vec<Value> {
+        let Some(e) = change.event() else {
+            return Vec::new();
+        };
+        let matching: Vec<&String> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, open)| {
+                !open.sent.contains(&e.id)
+                    && open.filters.iter().any(|f| f.matches(e))
+                    && !open.mutes.mutes(e)
+            })
+            .map(|(id, _)| id)
+            .collect();
+        if matching.is_empty() {
+            return Vec::new();
+        }
+        // the circles are only locked for events addressed to one
+        if entity::circle::audience(e).is_some()
+            && !circle::visible(&**self.state.circles.lock().await, self.viewer(), e)
+        {
+            return Vec::new();
+        }
+        matching
+            .into_iter()
+            .map(|id| json!(["EVENT", id, e]))
+            .collect()
+    }
+
+    /// Takes a token for a `REQ` or `COUNT`; the reason to refuse it if a
+    /// bucket is empty.
+    fn charge(&self) -> Result<(), String> {
+        self.state
+            .limiter
+            .check(Class::Subscription, &self.buckets, Instant::now())
+            .map_err(|wait| {
+                let wait = wait.as_secs_f64().ceil() as u64;
+                format!("rate-limited: too many subscriptions, retry in {}s", wait)
+            })
+    }
+
+    /// Closes every subscription after missing `lagged` changes.
+    fn drop_all(&mut self, lagged: u64) -> Vec<Value> {
+        event!(
+            Level::INFO,
+            "closing {} subscriptions that missed {} changes",
+            self.subscriptions.len(),
+            lagged
+        );
+        self.changes = None;
+        self.subscriptions
+            .drain()
+            .map(|(id, _)| {
+                let reason = "error: too slow for the change feed, subscribe again";
+                json!(["CLOSED", id, reason])
+            })
+            .collect()
+    }
+}
+
+/// Stored events matching any of `filters`, each at most once, in the
+/// order of the filters.
+fn stored(events: &mut dyn EventRepo, filters: &[Filter]) -> Vec<Event> {
+    let mut seen = HashSet::new();
+    let mut stored = Vec::new();
+    for filter in filters {
+        for e in events.query(filter) {
+            if seen.insert(e.id.clone()) {
+                stored.push(e);
+            }
+        }
+    }
+    stored
+}
+
+#[derive(Debug, PartialEq)]
+enum Request {
+    Req {
+        subscription: String,
+        filters: Vec<Filter>,
+    },
+    Close {
+        subscription: String,
+    },
+    Count {
+        subscription: String,
+        filters: Vec<Filter>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Refusal {
+    /// Ends the subscription with this id.
+    Closed(String, String),
+    Notice(String),
+}
+
+fn parse(text: &str) -> Result<Request, Refusal> {
+    let message: Vec<Value> = serde_json::from_str(text)
+        .map_err(|_| Refusal::Notice("invalid: expected a JSON array".to_string()))?;
+    let Some(kind) = message.first().and_then(Value::as_str) else {
+        return Err(Refusal::Notice(
+            "invalid: expected a message type".to_string(),
+        ));
+    };
+    if !matches!(kind, "REQ" | "CLOSE" | "COUNT") {
+        return Err(Refusal::Notice(format!(
+            "unsupported: {} messages are not served here, publish with POST /events",
+            kind
+        )));
+    }
+    let Some(subscription) = message.get(1).and_then(Value::as_str) else {
+        return Err(Refusal::Notice(format!(
+            "invalid: {} needs a subscription id",
+            kind
+        )));
+    };
+    let subscription = subscription.to_string();
+    if kind == "CLOSE" {
+        return Ok(Request::Close { subscription });
+    }
+    let filters = match message[2..]
+        .iter()
+        .map(|f| serde_json::from_value(f.clone()))
+        .collect::<Result<_, _>>()
+    {
+        Ok(filters) => filters,
+        Err(e) => return Err(Refusal::Closed(subscription, format!("invalid: {}", e))),
+    };
+    Ok(match kind {
+        "REQ" => Request::Req {
+            subscription,
+            filters,
+        },
+        _ => Request::Count {
+            subscription,
+            filters,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Rate};
+    use entity::{kind, Role};
+    use rustr_core::conformance::event;
+    use std::net::IpAddr;
+
+    #[tokio::test]
+    async fn test_mutes() {
+        let state = AppState::in_memory(Config::default());
+        let (viewer, bob, carol) = ("a".repeat(64), "b".repeat(64), "c".repeat(64));
+        {
+            let mut events = state.event_repo.lock().await;
+            let mut list = event("m", &viewer, kind::MUTE_LIST, 1);
+            list.tags = vec![vec!["p".to_string(), bob.clone()]];
+            events.add(list).unwrap();
+            events.add(event("b1", &bob, 1, 2)).unwrap();
+            events.add(event("c1", &carol, 1, 3)).unwrap();
+        }
+        let caller = Caller {
+            public_key: viewer,
+            session: None,
+            role: Role::Member,
+        };
+        let req = r#"["REQ","s1",{"kinds":[1]}]"#;
+        let mut connection =
+            Connection::new(state.clone(), Some(caller.clone()), Some(true), Vec::new());
+        assert_eq!(
+            connection.answer(req).await,
+            vec![
+                json!(["EVENT", "s1", event("c1", &carol, 1, 3)]),
+                json!(["EOSE", "s1"])
+            ]
+        );
+        let muted = Change::Added(event("b2", &bob, 1, 4));
+        assert!(connection.forward(&muted).await.is_empty());
+        let other = Change::Added(event("c2", &carol, 1, 5));
+        assert_eq!(connection.forward(&other).await.len(), 1);
+
+        let mut unmuted = Connection::new(state, Some(caller), None, Vec::new());
+        assert_eq!(unmuted.answer(req).await.len(), 3);
+        assert_eq!(unmuted.forward(&muted).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited() {
+        let mut config = Config::default();
+        config.rate_limits.subscriptions = Some(Rate {
+            per_minute: 1,
+            burst: 2,
+        });
+        let state = AppState::in_memory(config);
+        let ip = Key::Ip(IpAddr::from([127, 0, 0, 1]));
+        let mut connection = Connection::new(state.clone(), None, None, vec![ip.clone()]);
+        let eose = json!(["EOSE", "s1"]);
+        assert_eq!(connection.answer(r#"["REQ","s1",{}]"#).await, vec![eose]);
+        let count = connection.answer(r#"["COUNT","c1",{}]"#).await;
+        assert_eq!(count[0][0], "COUNT");
+        // a second connection from the same address shares the bucket
+        let mut other = Connection::new(state, None, None, vec![ip]);
+        let closed = other.answer(r#"["REQ","s2",{}]"#).await;
+        assert_eq!(closed[0][0], "CLOSED");
+        assert!(closed[0][2].as_str().unwrap().starts_with("rate-limited:"));
+        assert!(!other.subscriptions.contains_key("s2"));
+        // closing needs no token
+        assert!(connection.answer(r#"["CLOSE","s1"]"#).await.is_empty());
+    }
+
+    #[test]
+    fn test_parse() {
+        let count = parse(r##"["COUNT","s1",{"kinds":[1]},{"#t":["rust"]}]"##).unwrap();
+        assert_eq!(
+            count,
+            Request::Count {
+                subscription: "s1".to_string(),
+                filters: vec![
+                    Filter {
+                        kinds: Some(vec![1]),
+                        ..Filter::new()
+                    },
+                    Filter::new().with_tag("t", vec!["rust".to_string()]),
+                ],
+            }
+        );
+        assert!(matches!(
+            parse(r#"["COUNT","s1",{"kinds":"x"}]"#),
+            Err(Refusal::Closed(id, _)) if id == "s1"
+        ));
+        assert_eq!(
+            parse(r#"["REQ","s1",{"search":"rust relay","limit":5}]"#).unwrap(),
+            Request::Req {
+                subscription: "s1".to_string(),
+                filters: vec![Filter {
+                    search: Some("rust relay".to_string()),
+                    limit: Some(5),
+                    ..Filter::new()
+                }],
+            }
+        );
+        assert_eq!(
+            parse(r#"["CLOSE","s1"]"#).unwrap(),
+            Request::Close {
+                subscription: "s1".to_string()
+            }
+        );
+        assert!(matches!(parse(r#"["EVENT",{}]"#), Err(Refusal::Notice(_))));
+        assert!(matches!(parse(r#"["REQ"]"#), Err(Refusal::Notice(_))));
+        assert!(matches!(parse(r#"["COUNT"]"#), Err(Refusal::Notice(_))));
+        assert!(matches!(parse("{}"), Err(Refusal::Notice(_))));
+    }
+}
@@ -1,13 +1,25 @@
-use clap::Parser;
-use entity::{Event,KeyPair};
-use reqwest::{header::CONTENT_TYPE, StatusCode};
-use serde::{Deserialize, Serialize};
-use std::fs;
-use colored::*;
+use base64::Engine;
 use chrono::prelude::DateTime;
-use chrono::Utc;
 use chrono::NaiveDateTime;
-use std::time::{UNIX_EPOCH, Duration};
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use colored::*;
+use entity::article::Article;
+use entity::circle::{self, Circle};
+use entity::contacts::{Contact, ContactList};
+use entity::lists::{Item, List, Published};
+use entity::mutes::{Mute, MuteList};
+use entity::nip05::{Document, Identifier};
+use entity::reaction::{self, Stats};
+use entity::thread::{self, Node, Thread};
+use entity::{kind, ErrorBody, Event, KeyPair};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -26,18 +38,546 @@ struct Args {
     id: String,
     #[arg(short='x', long, default_value_t = String::from("1970-01-01 00:00:00"))]
     expiration_date: String,
+    /// Relay to talk to
+    #[arg(long, env = "RUSTR_URL", default_value_t = String::from("http://localhost:3000"))]
+    url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Work with events
+    Event {
+        #[command(subcommand)]
+        command: EventCommand,
+    },
+    /// Resolve an internet identifier such as alice@example.com and check
+    /// that it belongs to a public key, by default the one in the key file
+    Verify {
+        identifier: String,
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+    /// Register or update the profile of the key in the key file
+    Profile {
+        name: String,
+        #[arg(long)]
+        display_name: Option<String>,
+        #[arg(long)]
+        about: Option<String>,
+        #[arg(long)]
+        picture: Option<String>,
+        #[arg(long)]
+        website: Option<String>,
+        /// Internet identifier to claim, e.g. alice@example.com
+        #[arg(long)]
+        nip05: Option<String>,
+    },
+    /// Add a public key to the contact list of the key in the key file
+    Follow {
+        public_key: String,
+        /// Relay to find them on
+        #[arg(long)]
+        relay: Option<String>,
+        #[arg(long)]
+        petname: Option<String>,
+    },
+    /// Remove a public key from the contact list
+    Unfollow { public_key: String },
+    /// Show the conversation around an event as a tree
+    Thread {
+        id: String,
+        /// Levels of parents and replies to show
+        #[arg(short, long)]
+        depth: Option<usize>,
+    },
+    /// Reply to an event
+    Reply { id: String, content: String },
+    /// React to an event, with a like unless another reaction is given
+    React {
+        id: String,
+        #[arg(default_value_t = String::from("+"))]
+        content: String,
+    },
+    /// Repost an event
+    Repost { id: String },
+    /// Ask the relay to delete events you published, such as reactions
+    Delete {
+        #[arg(required = true)]
+        ids: Vec<String>,
+    },
+    /// Show the reactions, reposts and replies an event got
+    Stats { id: String },
+    /// Hide authors, hashtags, words or threads
+    Mute {
+        #[command(subcommand)]
+        command: MuteCommand,
+    },
+    /// Publish long-form articles
+    Article {
+        #[command(subcommand)]
+        command: ArticleCommand,
+    },
+    /// Share notes with a circle of people only
+    Circle {
+        #[command(subcommand)]
+        command: CircleCommand,
+    },
+    /// Keep pinned notes, bookmarks, interests and sets of people
+    List {
+        #[command(subcommand)]
+        command: ListCommand,
+    },
+    /// Show replies, mentions, reactions, reposts and messages addressed to
+    /// you, newest first, and mark them as read
+    Notifications {
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Cursor printed at the end of the previous page
+        #[arg(long)]
+        before: Option<String>,
+        /// Only notifications created at or after this unix time
+        #[arg(long)]
+        since: Option<u64>,
+        /// Do not mark the notifications as read
+        #[arg(long)]
+        keep_unread: bool,
+    },
+    /// Show what the people you follow posted, newest first
+    Feed {
+        #[arg(short, long)]
+        limit: Option<usize>,
+        /// Cursor printed at the end of the previous page
+        #[arg(long)]
+        before: Option<String>,
+        /// Hide what your mute list hides, private entries included
+        #[arg(long)]
+        apply_mutes: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MuteCommand {
+    /// Add an entry to your mute list
+    Add {
+        #[command(flatten)]
+        target: MuteTarget,
+        /// Encrypt the entry so that only you can read it; the relay cannot
+        /// apply private entries
+        #[arg(long)]
+        private: bool,
+    },
+    /// Remove an entry from your mute list
+    Remove {
+        #[command(flatten)]
+        target: MuteTarget,
+    },
+    /// Show your mute list
+    List,
+}
+
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = false)]
+struct MuteTarget {
+    #[arg(long)]
+    author: Option<String>,
+    #[arg(long)]
+    hashtag: Option<String>,
+    #[arg(long)]
+    word: Option<String>,
+    /// Id of the event that started the thread
+    #[arg(long)]
+    thread: Option<String>,
+}
+
+impl MuteTarget {
+    fn mute(self) -> Mute {
+        match self {
+            MuteTarget {
+                author: Some(pk), ..
+            } => Mute::Author(pk),
+            MuteTarget {
+                hashtag: Some(t), ..
+            } => Mute::Hashtag(t.trim_start_matches('#').to_lowercase()),
+            MuteTarget { word: Some(w), .. } => Mute::Word(w.to_lowercase()),
+            MuteTarget {
+                thread: Some(id), ..
+            } => Mute::Thread(id),
+            _ => unreachable!("clap requires one target"),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum ArticleCommand {
+    /// Publish a Markdown file, or a new version of it. Front matter sets
+    /// the title, summary, image, tags and publication date; the article is
+    /// named by its `d` or `slug`, by default the file name
+    Publish { file: String },
+}
+
+/// Circles are named by `<owner>:<name>`, or by their name alone if they
+/// are your own.
+#[derive(Subcommand, Debug)]
+enum CircleCommand {
+    /// Create a circle
+    Create {
+        name: String,
+        members: Vec<String>,
+        /// Hand a key to the members that notes posted to the circle are
+        /// encrypted with, so that the relay cannot read them
+        #[arg(long)]
+        encrypted: bool,
+    },
+    /// Show the circles you own or belong to
+    List,
+    /// Add members to a circle you own; they get its key if it has one
+    Add {
+        circle: String,
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Remove members from a circle you own, or leave one. A circle key is
+    /// replaced, so that former members cannot read new notes
+    Remove {
+        circle: String,
+        #[arg(required = true)]
+        members: Vec<String>,
+    },
+    /// Delete a circle you own
+    Delete { circle: String },
+    /// Post a note only the circle can read, encrypted if it has a key
+    Post { circle: String, content: String },
+    /// Show the notes posted to a circle, newest first
+    Read {
+        circle: String,
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ListCommand {
+    /// Show a list, or all sets of a kind when no set is named
+    Show {
+        #[command(flatten)]
+        list: ListRef,
+        /// Whose list to show, by default yours
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+    /// Add an item to one of your lists
+    Add {
+        #[command(flatten)]
+        list: ListRef,
+        #[command(flatten)]
+        item: ListItem,
+        /// Encrypt the item so that only you can read it
+        #[arg(long)]
+        private: bool,
+    },
+    /// Remove an item from one of your lists
+    Remove {
+        #[command(flatten)]
+        list: ListRef,
+        #[command(flatten)]
+        item: ListItem,
+    },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ListName {
+    Pins,
+    Bookmarks,
+    Interests,
+    People,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListRef {
+    name: ListName,
+    /// Name of the set; people are always kept in sets
+    #[arg(long)]
+    set: Option<String>,
+}
+
+impl ListRef {
+    fn kind(&self) -> Result<u32, String> {
+        match (self.name, &self.set) {
+            (ListName::Pins, None) => Ok(kind::PIN_LIST),
+            (ListName::Pins, Some(_)) => Err("pins are not kept in sets".to_string()),
+            (ListName::Bookmarks, None) => Ok(kind::BOOKMARKS),
+            (ListName::Bookmarks, Some(_)) => Ok(kind::BOOKMARK_SET),
+            (ListName::Interests, None) => Ok(kind::INTERESTS),
+            (ListName::Interests, Some(_)) => Ok(kind::INTEREST_SET),
+            (ListName::People, _) => Ok(kind::FOLLOW_SET),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = false)]
+struct ListItem {
+    #[arg(long)]
+    event: Option<String>,
+    /// `kind:public_key:d` of an addressable event, e.g. an article
+    #[arg(long)]
+    address: Option<String>,
+    #[arg(long)]
+    person: Option<String>,
+    #[arg(long)]
+    hashtag: Option<String>,
+    #[arg(long)]
+    url: Option<String>,
+}
+
+impl ListItem {
+    fn item(self) -> Item {
+        match self {
+            ListItem {
+                event: Some(id), ..
+            } => Item::Event(id),
+            ListItem {
+                address: Some(a), ..
+            } => Item::Address(a),
+            ListItem {
+                person: Some(pk), ..
+            } => Item::Person(pk),
+            ListItem {
+                hashtag: Some(t), ..
+            } => Item::Hashtag(t.trim_start_matches('#').to_lowercase()),
+            ListItem { url: Some(r), .. } => Item::Url(r),
+            _ => unreachable!("clap requires one item"),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum EventCommand {
+    /// Full-text search over event content, best matches first
+    Search {
+        #[arg(required = true)]
+        terms: Vec<String>,
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
 }
 
 fn main() {
-    let args = Args::parse();
-    let api_url =  String::from("http://localhost:3000");
+    if let Err(e) = run(Args::parse()) {
+        eprintln!("{} {}", "error:".red(), e);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let api_url = args.url.trim_end_matches('/').to_string();
+    // requests are signed with the key file if there is one
+    let key = fs::read_to_string(&args.key_file)
+        .ok()
+        .and_then(|data| serde_json::from_str::<KeyPair>(&data).ok());
+    let key = key.as_ref();
+    if let Some(command) = args.command {
+        return match command {
+            Command::Event {
+                command: EventCommand::Search { terms, limit },
+            } => search_events(&terms, limit, &format!("{}/{}", &api_url, "events"), key),
+            Command::Verify {
+                identifier,
+                public_key,
+            } => {
+                let public_key = public_key
+                    .or(key.map(|k| k.public_key()))
+                    .ok_or(format!("no --public-key and no key in {}", args.key_file))?;
+                verify_identifier(&identifier, &public_key)
+            }
+            Command::Follow {
+                public_key,
+                relay,
+                petname,
+            } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let contact = Contact {
+                    public_key,
+                    relay,
+                    petname,
+                };
+                update_contacts(&api_url, key, |list| {
+                    list.follow(contact);
+                    Ok(())
+                })
+            }
+            Command::Unfollow { public_key } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                update_contacts(&api_url, key, |list| {
+                    if !list.unfollow(&public_key) {
+                        return Err(format!("not following {}", public_key));
+                    }
+                    Ok(())
+                })
+            }
+            Command::Thread { id, depth } => {
+                read_thread(&format!("{}/events/{}/thread", &api_url, id), depth, key)
+            }
+            Command::Reply { id, content } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let url = format!("{}/{}", &api_url, "events");
+                let to = fetch_event(&url, &id, key)?;
+                let mut e = thread::reply(key.public_key(), content, &to);
+                e.sign(key.private_key());
+                create_event(e, &url, key)
+            }
+            Command::React { id, content } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let url = format!("{}/{}", &api_url, "events");
+                let to = fetch_event(&url, &id, key)?;
+                let mut e = reaction::reaction(key.public_key(), content, &to);
+                e.sign(key.private_key());
+                create_event(e, &url, key)
+            }
+            Command::Repost { id } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let url = format!("{}/{}", &api_url, "events");
+                let to = fetch_event(&url, &id, key)?;
+                let mut e = reaction::repost(key.public_key(), &to);
+                e.sign(key.private_key());
+                create_event(e, &url, key)
+            }
+            Command::Delete { ids } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let mut e = Event::new(key.public_key(), String::new(), 0);
+                e.kind = entity::kind::DELETION;
+                e.tags = ids
+                    .into_iter()
+                    .map(|id| vec!["e".to_string(), id])
+                    .collect();
+                e.sign(key.private_key());
+                create_event(e, &format!("{}/{}", &api_url, "events"), key)
+            }
+            Command::Stats { id } => read_stats(&format!("{}/events/{}/stats", &api_url, id), key),
+            Command::Mute { command } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                match command {
+                    MuteCommand::Add { target, private } => {
+                        let mute = target.mute();
+                        update_mutes(&api_url, key, |list| {
+                            match list.add(mute.clone(), private) {
+                                true => Ok(()),
+                                false => Err(format!("{} is already muted", mute)),
+                            }
+                        })
+                    }
+                    MuteCommand::Remove { target } => {
+                        let mute = target.mute();
+                        update_mutes(&api_url, key, |list| match list.remove(&mute) {
+                            true => Ok(()),
+                            false => Err(format!("{} is not muted", mute)),
+                        })
+                    }
+                    MuteCommand::List => {
+                        let (list, _) = read_mutes(&api_url, key)?;
+                        for mute in &list.public {
+                            println!("{}", mute);
+                        }
+                        for mute in &list.private {
+                            println!("{} {}", mute, "(private)".dimmed());
+                        }
+                        Ok(())
+                    }
+                }
+            }
+            Command::Article {
+                command: ArticleCommand::Publish { file },
+            } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                publish_article(&api_url, &file, key)
+            }
+            Command::Circle { command } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                manage_circle(&api_url, command, key)
+            }
+            Command::List { command } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                match command {
+                    ListCommand::Show { list, public_key } => {
+                        show_lists(&api_url, list, public_key, key)
+                    }
+                    ListCommand::Add {
+                        list,
+                        item,
+                        private,
+                    } => {
+                        let item = item.item();
+                        update_list(&api_url, key, list, |list| {
+                            match list.add(item.clone(), private)? {
+                                true => Ok(()),
+                                false => Err(format!("{} is already listed", item)),
+                            }
+                        })
+                    }
+                    ListCommand::Remove { list, item } => {
+                        let item = item.item();
+                        update_list(&api_url, key, list, |list| match list.remove(&item) {
+                            true => Ok(()),
+                            false => Err(format!("{} is not listed", item)),
+                        })
+                    }
+                }
+            }
+            Command::Notifications {
+                limit,
+                before,
+                since,
+                keep_unread,
+            } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                read_notifications(&api_url, limit, before, since, keep_unread, key)
+            }
+            Command::Feed {
+                limit,
+                before,
+                apply_mutes,
+            } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                // the relay applies the public entries, the private ones are
+                // only readable here
+                let mutes = match apply_mutes {
+                    true => Some(read_mutes(&api_url, key)?.0),
+                    false => None,
+                };
+                read_feed(&api_url, limit, before, mutes, key)
+            }
+            Command::Profile {
+                name,
+                display_name,
+                about,
+                picture,
+                website,
+                nip05,
+            } => {
+                let key = key.ok_or(format!("no key in {}", args.key_file))?;
+                let metadata = serde_json::json!({
+                    "name": name,
+                    "display_name": display_name,
+                    "about": about,
+                    "picture": picture,
+                    "website": website,
+                    "nip05": nip05,
+                });
+                let mut e = Event::new(key.public_key(), metadata.to_string(), 0);
+                e.kind = entity::kind::METADATA;
+                e.sign(key.private_key());
+                save_profile(e, &format!("{}/{}", &api_url, "users"), key)
+            }
+        };
+    }
     if args.events {
-        if args.id == "" {
-        read_all_events(&format!("{}/{}",&api_url,"events"));
+        return if args.id.is_empty() {
+            read_all_events(&format!("{}/{}", &api_url, "events"), key)
         } else {
-            read_single_event(args.id, &format!("{}/{}",&api_url,"events"));
-        }
-        return;
+            read_single_event(args.id, &format!("{}/{}", &api_url, "events"), key)
+        };
     }
 
 
@@ -46,83 +586,692 @@ fn main() {
         let kp = KeyPair::generate();
         let serialized_kp = serde_json::to_string(&kp).unwrap();
         fs::write(&args.key_file, serialized_kp).expect("Unable to write file");
-        return;
+        return Ok(());
     }
-    if args.content == "" {
-        println!("-c content missing");
-        return;
+    if args.content.is_empty() {
+        return Err("-c content missing".to_string());
     }
     // read keyfile
-    let data = match fs::read_to_string(&args.key_file) {
-        Ok(val) => val,
-        Err(err) => {println!("{}",err.to_string()); return;},
-    };
+    let data = fs::read_to_string(&args.key_file).map_err(|e| e.to_string())?;
     let key_pair: KeyPair = serde_json::from_str(&data).unwrap();
     let expiration_time = from_pretty_time(args.expiration_date);
     let mut e = Event::new(key_pair.public_key(), args.content, expiration_time);
     e.sign(key_pair.private_key());
-    create_event(e, &format!("{}/{}",&api_url,"events"));
+    create_event(e, &format!("{}/{}", &api_url, "events"), &key_pair)
+}
+
+fn create_event(event: Event, url: &String, key: &KeyPair) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    send(
+        client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .json(&event),
+        Some(key),
+    )?;
+    Ok(())
+}
+
+fn fetch_event(url: &str, id: &str, key: &KeyPair) -> Result<Event, String> {
+    let client = reqwest::blocking::Client::new();
+    parse(send(client.get(format!("{}/{}", url, id)), Some(key))?)
+}
+
+fn read_stats(url: &str, key: Option<&KeyPair>) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    let stats: Stats = parse(send(client.get(url), key)?)?;
+    let reactions: Vec<String> = stats
+        .reactions
+        .iter()
+        .map(|(content, n)| format!("{} {}", content, n))
+        .collect();
+    println!(
+        "reactions: {}",
+        if reactions.is_empty() {
+            "none".to_string()
+        } else {
+            reactions.join("  ")
+        }
+    );
+    println!("reposts:   {}", stats.reposts);
+    println!("replies:   {}", stats.replies);
+    Ok(())
+}
+
+/// Fetches the latest contact list, applies `change` and publishes the
+/// result.
+fn update_contacts(
+    api_url: &str,
+    key: &KeyPair,
+    change: impl FnOnce(&mut ContactList) -> Result<(), String>,
+) -> Result<(), String> {
+    let url = format!("{}/{}", api_url, "events");
+    let client = reqwest::blocking::Client::new();
+    let query = [
+        ("authors", key.public_key()),
+        ("kinds", entity::kind::CONTACTS.to_string()),
+        ("limit", "1".to_string()),
+    ];
+    let latest: Vec<Event> = parse(send(client.get(&url).query(&query), Some(key))?)?;
+    let mut list = match latest.first() {
+        Some(e) => ContactList::from_event(e)?,
+        None => ContactList::default(),
+    };
+    change(&mut list)?;
+    publish_replacement(&url, list.to_event(key.public_key()), latest.first(), key)?;
+    println!("following {} public keys", list.contacts.len());
+    Ok(())
+}
+
+/// Signs and publishes `e`, which replaces `old`.
+fn publish_replacement(
+    url: &str,
+    mut e: Event,
+    old: Option<&Event>,
+    key: &KeyPair,
+) -> Result<(), String> {
+    e.sign(key.private_key());
+    // a replacement has to be newer than the event it replaces
+    while old.is_some_and(|old| old.created_at >= e.created_at) {
+        std::thread::sleep(Duration::from_millis(200));
+        e.sign(key.private_key());
+    }
+    let client = reqwest::blocking::Client::new();
+    send(client.post(url).json(&e), Some(key))?;
+    Ok(())
+}
+
+/// The latest mute list of the key, with its private entries decrypted,
+/// and the event it came from.
+fn read_mutes(api_url: &str, key: &KeyPair) -> Result<(MuteList, Option<Event>), String> {
+    let url = format!("{}/{}", api_url, "events");
+    let client = reqwest::blocking::Client::new();
+    let query = [
+        ("authors", key.public_key()),
+        ("kinds", kind::MUTE_LIST.to_string()),
+        ("limit", "1".to_string()),
+    ];
+    let latest: Vec<Event> = parse(send(client.get(&url).query(&query), Some(key))?)?;
+    let Some(e) = latest.into_iter().next() else {
+        return Ok((MuteList::default(), None));
+    };
+    let mut list = MuteList::from_event(&e)?;
+    list.decrypt(&key.public_key(), key.private_key())?;
+    Ok((list, Some(e)))
+}
+
+/// Fetches the latest mute list, applies `change` and publishes the result.
+fn update_mutes(
+    api_url: &str,
+    key: &KeyPair,
+    change: impl FnOnce(&mut MuteList) -> Result<(), String>,
+) -> Result<(), String> {
+    let (mut list, latest) = read_mutes(api_url, key)?;
+    change(&mut list)?;
+    let e = list.to_event(key.public_key(), key.private_key())?;
+    publish_replacement(
+        &format!("{}/{}", api_url, "events"),
+        e,
+        latest.as_ref(),
+        key,
+    )?;
+    println!(
+        "muting {} entries, {} of them private",
+        list.public.len() + list.private.len(),
+        list.private.len()
+    );
+    Ok(())
+}
+
+/// The id of `circle`, which is either an id or the name of one of the
+/// key's own circles.
+fn circle_id(circle: &str, key: &KeyPair) -> String {
+    match circle.contains(':') {
+        true => circle.to_string(),
+        false => format!("{}:{}", crypto::x_only(&key.public_key()), circle),
+    }
+}
+
+fn manage_circle(api_url: &str, command: CircleCommand, key: &KeyPair) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    let url = |id: &str, rest: &str| format!("{}/circles/{}{}", api_url, id, rest);
+    match command {
+        CircleCommand::Create {
+            name,
+            members,
+            encrypted,
+        } => {
+            let body = serde_json::json!({ "name": name, "members": members });
+            let request = client.post(format!("{}/circles", api_url)).json(&body);
+            let circle: Circle = parse(send(request, Some(key))?)?;
+            if encrypted {
+                hand_out_key(api_url, &circle, &KeyPair::generate(), &circle.members, key)?;
+            }
+            println!("created {}", circle.id());
+        }
+        CircleCommand::List => {
+            let request = client.get(format!("{}/circles", api_url));
+            let circles: Vec<Circle> = parse(send(request, Some(key))?)?;
+            for circle in circles {
+                let role = match circle.is_owner(&key.public_key()) {
+                    true => "owner",
+                    false => "member",
+                };
+                println!(
+                    "{} {} {} members",
+                    circle.id(),
+                    format!("({})", role).dimmed(),
+                    circle.members.len()
+                );
+            }
+        }
+        CircleCommand::Add { circle, members } => {
+            let id = circle_id(&circle, key);
+            let body = serde_json::json!({ "members": members });
+            let request = client.post(url(&id, "/members")).json(&body);
+            let circle: Circle = parse(send(request, Some(key))?)?;
+            if let Some(current) = circle_keys(api_url, &circle, key)?.into_iter().next() {
+                hand_out_key(api_url, &circle, &current, &members, key)?;
+            }
+            println!("{} has {} members", id, circle.members.len());
+        }
+        CircleCommand::Remove { circle, members } => {
+            let id = circle_id(&circle, key);
+            let body = serde_json::json!({ "members": members });
+            let request = client.delete(url(&id, "/members")).json(&body);
+            let circle: Circle = parse(send(request, Some(key))?)?;
+            let owner = circle.is_owner(&key.public_key());
+            if owner && !circle_keys(api_url, &circle, key)?.is_empty() {
+                hand_out_key(api_url, &circle, &KeyPair::generate(), &circle.members, key)?;
+                println!("replaced the key of {}", id);
+            }
+            println!("{} has {} members", id, circle.members.len());
+        }
+        CircleCommand::Delete { circle } => {
+            let id = circle_id(&circle, key);
+            send(client.delete(url(&id, "")), Some(key))?;
+            println!("deleted {}", id);
+        }
+        CircleCommand::Post { circle, content } => {
+            let id = circle_id(&circle, key);
+            let circle: Circle = parse(send(client.get(url(&id, "")), Some(key))?)?;
+            let keys = circle_keys(api_url, &circle, key)?;
+            let mut e = Event::new(key.public_key(), content, 0);
+            circle::address(&mut e, &id, keys.first())?;
+            e.sign(key.private_key());
+            create_event(e, &format!("{}/{}", api_url, "events"), key)?;
+        }
+        CircleCommand::Read { circle, limit } => {
+            let id = circle_id(&circle, key);
+            let circle: Circle = parse(send(client.get(url(&id, "")), Some(key))?)?;
+            let keys = circle_keys(api_url, &circle, key)?;
+            let mut query = vec![("kinds", kind::TEXT_NOTE.to_string())];
+            if let Some(limit) = limit {
+                query.push(("limit", limit.to_string()));
+            }
+            let request = client.get(url(&id, "/events")).query(&query);
+            let events: Vec<Event> = parse(send(request, Some(key))?)?;
+            for e in events {
+                let content = circle::open(&e, &keys)
+                    .unwrap_or_else(|err| format!("({})", err).dimmed().to_string());
+                println!("{}", event_line_with(&e, &content));
+            }
+        }
+    }
+    Ok(())
 }
 
+/// The keys of `circle` the owner handed to the key, newest first.
+fn circle_keys(api_url: &str, circle: &Circle, key: &KeyPair) -> Result<Vec<KeyPair>, String> {
+    let url = format!("{}/circles/{}/events", api_url, circle.id());
+    let query = [("kinds", kind::ENCRYPTED_DM.to_string())];
+    let client = reqwest::blocking::Client::new();
+    let messages: Vec<Event> = parse(send(client.get(url).query(&query), Some(key))?)?;
+    let me = crypto::x_only(&key.public_key()).to_string();
+    Ok(messages
+        .iter()
+        .filter(|e| circle.is_owner(&e.public_key))
+        .filter(|e| e.tag_values("p").any(|p| crypto::x_only(p) == me))
+        .filter_map(|e| circle::read_key_message(e, key).ok())
+        .map(|(_, circle_key)| circle_key)
+        .collect())
+}
+
+/// Sends `circle_key` to `members` and the owner's own copy to the owner.
+fn hand_out_key<'a>(
+    api_url: &str,
+    circle: &Circle,
+    circle_key: &KeyPair,
+    members: impl IntoIterator<Item = &'a String>,
+    key: &KeyPair,
+) -> Result<(), String> {
+    let url = format!("{}/{}", api_url, "events");
+    let own = key.public_key();
+    let mut receivers: Vec<&str> = members.into_iter().map(String::as_str).collect();
+    receivers.push(&own);
+    for member in receivers {
+        let mut e = circle::key_message(key, member, &circle.id(), circle_key)?;
+        e.sign(key.private_key());
+        create_event(e, &url, key)?;
+    }
+    Ok(())
+}
 
+fn publish_article(api_url: &str, file: &str, key: &KeyPair) -> Result<(), String> {
+    let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+    let mut article = Article::from_markdown(&text)?;
+    if article.d.is_empty() {
+        article.d = Path::new(file)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .ok_or(format!(
+                "{}: name the article with d in the front matter",
+                file
+            ))?;
+    }
+    if article.title.is_empty() {
+        return Err(format!("{}: the front matter needs a title", file));
+    }
+    let url = format!("{}/{}", api_url, "events");
+    let client = reqwest::blocking::Client::new();
+    let query = [
+        ("authors", key.public_key()),
+        ("kinds", kind::LONG_FORM.to_string()),
+        ("d", article.d.clone()),
+        ("limit", "1".to_string()),
+    ];
+    let latest: Vec<Event> = parse(send(client.get(&url).query(&query), Some(key))?)?;
+    let previous = latest.first();
+    // a new version keeps the date of the first one
+    if article.published_at.is_none() {
+        article.published_at = match previous {
+            Some(e) => Article::from_event(e)?.published_at.or(Some(e.created_at)),
+            None => Some(UNIX_EPOCH.elapsed().map_err(|e| e.to_string())?.as_secs()),
+        };
+    }
+    publish_replacement(&url, article.to_event(key.public_key()), previous, key)?;
+    let verb = match previous {
+        Some(_) => "updated",
+        None => "published",
+    };
+    println!(
+        "{} {}/articles/{}/{}",
+        verb,
+        api_url,
+        key.public_key(),
+        article.d
+    );
+    Ok(())
+}
 
+/// The lists of `kind` that `public_key` published, with the private
+/// items decrypted when they are the key's own.
+fn read_lists(
+    api_url: &str,
+    public_key: &str,
+    kind: u32,
+    key: &KeyPair,
+) -> Result<Vec<Published>, String> {
+    let url = format!("{}/users/{}/lists/{}", api_url, public_key, kind);
+    let client = reqwest::blocking::Client::new();
+    let mut lists: Vec<Published> = parse(send(client.get(url), Some(key))?)?;
+    if public_key == key.public_key() {
+        for published in &mut lists {
+            published
+                .list
+                .decrypt(&key.public_key(), key.private_key())?;
+        }
+    }
+    Ok(lists)
+}
+
+fn show_lists(
+    api_url: &str,
+    list: ListRef,
+    public_key: Option<String>,
+    key: &KeyPair,
+) -> Result<(), String> {
+    let public_key = public_key.unwrap_or_else(|| key.public_key());
+    let mut lists = read_lists(api_url, &public_key, list.kind()?, key)?;
+    if let Some(set) = &list.set {
+        lists.retain(|p| p.list.d.as_ref() == Some(set));
+    }
+    if lists.is_empty() {
+        println!("{}", "(empty)".dimmed());
+    }
+    for Published { list, .. } in &lists {
+        if let Some(d) = &list.d {
+            match list.title() {
+                Some(title) => println!("{} ({})", title.bold(), d),
+                None => println!("{}", d.bold()),
+            }
+        }
+        for item in &list.public {
+            println!("{}", item);
+        }
+        for item in &list.private {
+            println!("{} {}", item, "(private)".dimmed());
+        }
+        if !list.content.is_empty() {
+            println!("{}", "(private items hidden)".dimmed());
+        }
+    }
+    Ok(())
+}
 
-#[derive(Serialize, Deserialize, Debug)]
-struct GETAPIResponse {
-    origin: String,
+/// Fetches the named list of the key, applies `change` and publishes the
+/// result.
+fn update_list(
+    api_url: &str,
+    key: &KeyPair,
+    list: ListRef,
+    change: impl FnOnce(&mut List) -> Result<(), String>,
+) -> Result<(), String> {
+    let kind = list.kind()?;
+    let latest = read_lists(api_url, &key.public_key(), kind, key)?
+        .into_iter()
+        .find(|p| p.list.d == list.set);
+    let (mut updated, old) = match latest {
+        Some(Published { list, event }) => (list, Some(event)),
+        None => (List::new(kind, list.set)?, None),
+    };
+    change(&mut updated)?;
+    let e = updated.to_event(key.public_key(), key.private_key())?;
+    publish_replacement(&format!("{}/{}", api_url, "events"), e, old.as_ref(), key)?;
+    println!(
+        "listing {} items, {} of them private",
+        updated.public.len() + updated.private.len(),
+        updated.private.len()
+    );
+    Ok(())
 }
 
-fn create_event(event: Event, url: &String) {
+fn read_feed(
+    api_url: &str,
+    limit: Option<usize>,
+    before: Option<String>,
+    mutes: Option<MuteList>,
+    key: &KeyPair,
+) -> Result<(), String> {
+    let mut query = Vec::new();
+    if let Some(limit) = limit {
+        query.push(("limit", limit.to_string()));
+    }
+    if let Some(before) = before {
+        query.push(("before", before));
+    }
+    if mutes.is_some() {
+        query.push(("apply_mutes", "true".to_string()));
+    }
     let client = reqwest::blocking::Client::new();
-    let response = client
-        .post(url)
-        .header(CONTENT_TYPE, "application/json")
-        .json(&event)
-        .send()
-        .expect("dsd");
-    if response.status() != StatusCode::CREATED {
-        println!("{} status code", response.status());
+    let url = format!("{}/{}", api_url, "feed");
+    let page: FeedPage = parse(send(client.get(url).query(&query), Some(key))?)?;
+    let muted = |e: &Event| mutes.as_ref().is_some_and(|m| m.mutes(e));
+    for e in page.events.into_iter().filter(|e| !muted(e)) {
+        print_event(e);
+    }
+    if let Some(next) = page.next {
+        println!("more: --before {}", next);
     }
-    //println!("{:#?}", response_event);
+    Ok(())
 }
 
-fn read_all_events(url: &String) {
+fn read_notifications(
+    api_url: &str,
+    limit: Option<usize>,
+    before: Option<String>,
+    since: Option<u64>,
+    keep_unread: bool,
+    key: &KeyPair,
+) -> Result<(), String> {
+    let url = format!("{}/{}", api_url, "notifications");
+    let mut query = Vec::new();
+    if let Some(limit) = limit {
+        query.push(("limit", limit.to_string()));
+    }
+    if let Some(since) = since {
+        query.push(("since", since.to_string()));
+    }
+    // only the first page moves the read marker
+    let mark = !keep_unread && before.is_none();
+    if let Some(before) = before {
+        query.push(("before", before));
+    }
     let client = reqwest::blocking::Client::new();
-    let response_event = client
-        .get(url)
-        .header(CONTENT_TYPE, "application/json")
-        .send()
-        .expect("dsd")
-        .json::<Vec<Event>>()
-        .expect("sdsd");
+    let inbox: Inbox = parse(send(client.get(&url).query(&query), Some(key))?)?;
+    println!("{} unread", inbox.unread);
+    for e in &inbox.events {
+        let unread = if e.created_at > inbox.last_seen {
+            "•".blue()
+        } else {
+            " ".normal()
+        };
+        let author = crypto::x_only(&e.public_key);
+        // reposts carry JSON and messages ciphertext, neither worth printing
+        let content = match e.kind {
+            kind::TEXT_NOTE => e.content.as_str(),
+            _ => "",
+        };
+        println!(
+            "{} [{}] {} {} {}",
+            unread,
+            pretty_time(e.created_at),
+            &author[..author.len().min(8)],
+            notification_reason(e),
+            content
+        );
+    }
+    if let Some(next) = inbox.next {
+        println!("more: --before {}", next);
+    }
+    if mark {
+        let newest = inbox.events.first().map(|e| e.created_at);
+        let query: Vec<_> = newest
+            .map(|at| ("at", at.to_string()))
+            .into_iter()
+            .collect();
+        send(
+            client.post(format!("{}/seen", url)).query(&query),
+            Some(key),
+        )?;
+    }
+    Ok(())
+}
+
+fn notification_reason(e: &Event) -> String {
+    match e.kind {
+        kind::REACTION => format!("reacted {}", e.content),
+        kind::REPOST | kind::GENERIC_REPOST => "reposted".to_string(),
+        kind::ENCRYPTED_DM => "sent you a message".to_string(),
+        kind::TEXT_NOTE if thread::parent(e).is_some() => "replied".to_string(),
+        _ => "mentioned you".to_string(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Inbox {
+    events: Vec<Event>,
+    next: Option<String>,
+    unread: u64,
+    last_seen: u64,
+}
+
+#[derive(Deserialize)]
+struct FeedPage {
+    events: Vec<Event>,
+    next: Option<String>,
+}
+
+fn save_profile(profile: Event, url: &String, key: &KeyPair) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    send(client.post(url).json(&profile), Some(key))?;
+    Ok(())
+}
+
+fn verify_identifier(identifier: &str, public_key: &str) -> Result<(), String> {
+    let id: Identifier = identifier.parse()?;
+    let client = reqwest::blocking::Client::new();
+    let document: Document = parse(send(client.get(id.url()), None)?)?;
+    document.verify(&id, public_key)?;
+    println!("({}) {} is {}", "✓".green(), id, public_key);
+    for relay in document.relays(public_key) {
+        println!("    relay {}", relay);
+    }
+    Ok(())
+}
+
+fn read_all_events(url: &String, key: Option<&KeyPair>) -> Result<(), String> {
+    let client = reqwest::blocking::Client::new();
+    let response_event: Vec<Event> = parse(send(
+        client.get(url).header(CONTENT_TYPE, "application/json"),
+        key,
+    )?)?;
+    for e in response_event {
+        print_event(e);
+    }
+    Ok(())
+}
+
+fn search_events(
+    terms: &[String],
+    limit: Option<usize>,
+    url: &String,
+    key: Option<&KeyPair>,
+) -> Result<(), String> {
+    let mut query = vec![("search", terms.join(" "))];
+    if let Some(limit) = limit {
+        query.push(("limit", limit.to_string()));
+    }
+    let client = reqwest::blocking::Client::new();
+    let response_event: Vec<Event> = parse(send(
+        client
+            .get(url)
+            .query(&query)
+            .header(CONTENT_TYPE, "application/json"),
+        key,
+    )?)?;
     for e in response_event {
         print_event(e);
     }
+    Ok(())
 }
 
-fn read_single_event(id: String, url: &String) {
+fn read_single_event(id: String, url: &String, key: Option<&KeyPair>) -> Result<(), String> {
     let client = reqwest::blocking::Client::new();
     let full_url = format!("{}/{}", url, id);
-    let response_event = client
-        .get(full_url)
-        .header(CONTENT_TYPE, "application/json")
-        .send()
-        .expect("dsd")
-        .json::<Event>()
-        .expect("sdsd");
+    let response_event: Event = parse(send(
+        client
+            .get(full_url)
+            .header(CONTENT_TYPE, "application/json"),
+        key,
+    )?)?;
     print_event(response_event);
+    Ok(())
 }
 
+/// Adds an `Authorization: Nostr` header signing the method, URL and body
+/// of `request` with `key`.
+fn authorize(request: RequestBuilder, key: &KeyPair) -> Result<RequestBuilder, String> {
+    let built = request
+        .try_clone()
+        .ok_or("cannot sign a streamed body")?
+        .build()
+        .map_err(|e| e.to_string())?;
+    let body = built.body().and_then(|b| b.as_bytes());
+    let mut auth = Event::http_auth(
+        key.public_key(),
+        built.url().as_str(),
+        built.method().as_str(),
+        body,
+    );
+    auth.sign(key.private_key());
+    let json = serde_json::to_vec(&auth).map_err(|e| e.to_string())?;
+    let token = base64::engine::general_purpose::STANDARD.encode(json);
+    Ok(request.header(AUTHORIZATION, format!("Nostr {}", token)))
+}
 
+/// Sends `request`, signed with `key` if given, and turns an error response
+/// into the message the relay sent with it.
+fn send(request: RequestBuilder, key: Option<&KeyPair>) -> Result<Response, String> {
+    let request = match key {
+        Some(key) => authorize(request, key)?,
+        None => request,
+    };
+    let response = request.send().map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    match response.json::<ErrorBody>() {
+        Ok(body) => Err(format!(
+            "{} ({}): {}",
+            body.code,
+            status.as_u16(),
+            body.message
+        )),
+        Err(_) => Err(format!("{} status code", status)),
+    }
+}
+
+fn parse<T: DeserializeOwned>(response: Response) -> Result<T, String> {
+    response
+        .json()
+        .map_err(|e| format!("unexpected response: {}", e))
+}
+
+fn read_thread(url: &str, depth: Option<usize>, key: Option<&KeyPair>) -> Result<(), String> {
+    let query: Vec<_> = depth
+        .map(|d| ("depth", d.to_string()))
+        .into_iter()
+        .collect();
+    let client = reqwest::blocking::Client::new();
+    let thread: Thread = parse(send(client.get(url).query(&query), key)?)?;
+    for (level, e) in thread.ancestors.iter().enumerate() {
+        print_thread_event(e, level);
+    }
+    print_tree(&thread.tree, thread.ancestors.len());
+    if thread.truncated {
+        println!("{}", "(more not shown)".dimmed());
+    }
+    Ok(())
+}
+
+/// Prints `node` and its replies indented by their depth, with the ids to
+/// reply to.
+fn print_tree(node: &Node, level: usize) {
+    print_thread_event(&node.event, level);
+    for reply in &node.replies {
+        print_tree(reply, level + 1);
+    }
+}
+
+fn print_thread_event(event: &Event, level: usize) {
+    println!(
+        "{}{} {}",
+        "  ".repeat(level),
+        event_line(event),
+        event.id.dimmed()
+    );
+}
 
 fn print_event(event: Event) {
+    println!("{}", event_line(&event));
+}
+
+fn event_line(event: &Event) -> String {
+    event_line_with(event, &event.content)
+}
+
+/// Like [`event_line`], showing `content` instead of the event's, e.g.
+/// after decrypting it.
+fn event_line_with(event: &Event, content: &str) -> String {
     let valid = if event.verify() {
         String::from("✓").green()
     } else {
         String::from("✗").red()
     };
     // show exporation_date
-    println!("({})[{}] {}",valid,pretty_time(event.created_at),event.content);
+    format!("({})[{}] {}", valid, pretty_time(event.created_at), content)
 }
 
 
@@ -130,10 +1279,10 @@ fn print_event(event: Event) {
 fn pretty_time(time_stamp : u64) -> String {
     let d = UNIX_EPOCH + Duration::from_secs(time_stamp);
     let datetime = DateTime::<Utc>::from(d);
-    return datetime.format("%Y-%m-%d %H:%M:%S").to_string();
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
 fn from_pretty_time(str_time: String) -> u64 {
     let no_timezone = NaiveDateTime::parse_from_str(&str_time, "%Y-%m-%d %H:%M:%S").expect("s");
-    return no_timezone.timestamp().try_into().unwrap();
-}
\ No newline at end of file
+    no_timezone.and_utc().timestamp().try_into().unwrap()
+}
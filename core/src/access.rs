@@ -0,0 +1,120 @@
+//! What each [`Role`] may do.
+//!
+//! A request needs one [`Permission`], decided by its route. Publishing an
+//! event additionally needs the event's kind to be open to the role: kinds
+//! listed in [`AccessPolicy::kinds`] are limited to the roles given there,
+//! all others are open to every role allowed to publish. Admins pass every
+//! check.
+
+use entity::{kind, Role};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Read,
+    Publish,
+    /// Changing one's own user entry.
+    EditProfile,
+    /// The `/admin` routes and other users' entries.
+    Admin,
+}
+
+impl Permission {
+    pub fn allowed(self, role: Role) -> bool {
+        matches!(
+            (role, self),
+            (Role::Admin, _)
+                | (_, Permission::Read)
+                | (Role::Member, Permission::Publish | Permission::EditProfile)
+                | (Role::Bot, Permission::Publish)
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessPolicy {
+    /// Role of public keys without a user entry.
+    pub default_role: Role,
+    /// Roles allowed to publish each restricted kind; by default only admins
+    /// may publish relay announcements.
+    #[serde(deserialize_with = "kind_keys")]
+    pub kinds: BTreeMap<u32, BTreeSet<Role>>,
+}
+
+/// Reads kinds from map keys, which are strings in both JSON and TOML.
+fn kind_keys<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeMap<u32, BTreeSet<Role>>, D::Error> {
+    BTreeMap::<String, BTreeSet<Role>>::deserialize(d)?
+        .into_iter()
+        .map(|(k, roles)| match k.parse() {
+            Ok(kind) => Ok((kind, roles)),
+            Err(_) => Err(de::Error::custom(format!("invalid kind {}", k))),
+        })
+        .collect()
+}
+
+impl Default for AccessPolicy {
+    fn default() -> AccessPolicy {
+        let admins = BTreeSet::from([Role::Admin]);
+        AccessPolicy {
+            default_role: Role::Member,
+            kinds: BTreeMap::from([
+                (kind::RELAY_MONITOR, admins.clone()),
+                (kind::RELAY_DISCOVERY, admins),
+            ]),
+        }
+    }
+}
+
+impl AccessPolicy {
+    pub fn check(&self, role: Role, permission: Permission) -> Result<(), String> {
+        if permission.allowed(role) {
+            return Ok(());
+        }
+        Err(format!("role {} may not do this", role))
+    }
+
+    /// Whether `role` may publish events of `kind`.
+    pub fn check_kind(&self, role: Role, kind: u32) -> Result<(), String> {
+        self.check(role, Permission::Publish)?;
+        match self.kinds.get(&kind) {
+            Some(roles) if role != Role::Admin && !roles.contains(&role) => {
+                Err(format!("role {} may not publish kind {}", role, kind))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let policy = AccessPolicy::default();
+        assert!(policy.check(Role::ReadOnly, Permission::Read).is_ok());
+        assert!(policy.check(Role::ReadOnly, Permission::Publish).is_err());
+        assert!(policy.check(Role::Bot, Permission::Publish).is_ok());
+        assert!(policy.check(Role::Bot, Permission::EditProfile).is_err());
+        assert!(policy.check(Role::Member, Permission::Admin).is_err());
+        assert!(policy.check(Role::Admin, Permission::Admin).is_ok());
+
+        assert!(policy.check_kind(Role::Member, kind::TEXT_NOTE).is_ok());
+        assert!(policy
+            .check_kind(Role::Member, kind::RELAY_DISCOVERY)
+            .is_err());
+        assert!(policy.check_kind(Role::Admin, kind::RELAY_MONITOR).is_ok());
+        assert!(policy.check_kind(Role::ReadOnly, kind::TEXT_NOTE).is_err());
+
+        let policy: AccessPolicy =
+            serde_json::from_str(r#"{"kinds":{"1":["bot"]},"default_role":"read-only"}"#).unwrap();
+        assert_eq!(policy.default_role, Role::ReadOnly);
+        assert!(policy.check_kind(Role::Bot, kind::TEXT_NOTE).is_ok());
+        assert!(policy.check_kind(Role::Member, kind::TEXT_NOTE).is_err());
+        assert!(policy
+            .check_kind(Role::Member, kind::RELAY_DISCOVERY)
+            .is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+//! Backup and migration of a whole store as newline-delimited JSON.
+//!
+//! Every line is one record tagged with its type, e.g.
+//! `{"type":"event","id":...}` or `{"type":"user","name":...}`. Import also
+//! accepts untagged lines holding a plain event, which is what other relays
+//! and tools produce when they dump their database. Sessions are left out,
+//! as they hold the private keys the relay signs with.
+
+use crate::repository::{EventRepo, Pages, RepoError, UserRepo};
+use entity::{Event, Filter, Role, User};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Record {
+    Event(Event),
+    User(User),
+}
+
+/// The repositories an export reads from or an import writes to. Users are
+/// optional, e.g. when only an event store is at hand.
+pub struct Store<'a> {
+    pub events: &'a mut dyn EventRepo,
+    pub users: Option<&'a mut dyn UserRepo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ExportReport {
+    pub events: usize,
+    pub users: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub accepted: usize,
+    pub duplicate: usize,
+    pub rejected: usize,
+    /// Why lines were rejected, as `line <n>: <reason>`; capped at
+    /// [`MAX_ERRORS`] entries.
+    pub errors: Vec<String>,
+}
+
+pub const MAX_ERRORS: usize = 100;
+
+/// Events read per query while exporting.
+pub const PAGE: usize = 500;
+
+impl ImportReport {
+    fn reject(&mut self, line: usize, reason: impl std::fmt::Display) {
+        self.rejected += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(format!("line {}: {}", line, reason));
+        }
+    }
+}
+
+/// Writes the events matching `filter` (all of them if `None`), newest
+/// first, followed by all users to `out`, one record per line. Events are
+/// read [`PAGE`] at a time.
+pub fn export(
+    store: Store,
+    filter: Option<&Filter>,
+    mut out: impl Write,
+) -> io::Result<ExportReport> {
+    let mut report = ExportReport::default();
+    let mut pages = Pages::new(filter.unwrap_or(&Filter::new()), PAGE);
+    while let Some(events) = pages.next(store.events) {
+        for e in events {
+            write_record(&mut out, &Record::Event(e))?;
+            report.events += 1;
+        }
+    }
+    if let Some(users) = store.users {
+        for u in users.read_all_users() {
+            write_record(&mut out, &Record::User(u))?;
+            report.users += 1;
+        }
+    }
+    out.flush()?;
+    Ok(report)
+}
+
+/// Writes `record` to `out` as one line.
+pub fn write_record(out: &mut impl Write, record: &Record) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")
+}
+
+/// Loads records from `input`. Events must carry a valid signature; ids
+/// and users that already exist are counted as duplicates and left
+/// untouched. Imported users get the default role unless `keep_roles` is
+/// set, so a dump cannot grant admin rights. Only a failing store or reader
+/// aborts the import.
+pub fn import(
+    store: Store,
+    input: impl BufRead,
+    keep_roles: bool,
+) -> Result<ImportReport, RepoError> {
+    let mut report = ImportReport::default();
+    let Store { events, mut users } = store;
+    for (n, line) in input.lines().enumerate() {
+        let line = line.map_err(|e| RepoError::Storage(e.to_string()))?;
+        let n = n + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match parse(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                report.reject(n, e);
+                continue;
+            }
+        };
+        match record {
+            Record::Event(e) => {
+                if !e.verify() {
+                    report.reject(n, format!("invalid signature for event {}", e.id));
+                    continue;
+                }
+                match events.add(e) {
+                    Ok(()) => report.accepted += 1,
+                    Err(RepoError::Duplicate(_)) | Err(RepoError::Outdated(_)) => {
+                        report.duplicate += 1
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Record::User(u) => match users.as_deref_mut() {
+                None => report.reject(n, "users are not imported"),
+                Some(users) if users.read_user(&u.public_key).is_some() => report.duplicate += 1,
+                Some(users) => {
+                    let mut u = u;
+                    if !keep_roles {
+                        u.role = Role::default();
+                    }
+                    users.add_user(u);
+                    report.accepted += 1;
+                }
+            },
+        }
+    }
+    Ok(report)
+}
+
+/// A tagged record, or a plain event from another relay's dump.
+fn parse(line: &str) -> Result<Record, serde_json::Error> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    if value.get("type").is_some() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(Record::Event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::{EventRepoInMemory, UserRepoInMemory};
+    use entity::KeyPair;
+
+    fn signed(kp: &KeyPair, content: &str) -> Event {
+        let mut e = Event::new(kp.public_key(), content.to_string(), 0);
+        e.sign(kp.private_key());
+        e
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let kp = KeyPair::generate();
+        let mut events = EventRepoInMemory::new();
+        let mut users = UserRepoInMemory::new();
+        let mut profile = signed(&kp, "{}");
+        profile.kind = entity::kind::METADATA;
+        profile.sign(kp.private_key());
+        events.add(signed(&kp, "hello")).unwrap();
+        events.add(profile).unwrap();
+        users.add_user(User::new("alice".to_string(), kp.public_key()));
+
+        let mut dump = Vec::new();
+        let store = Store {
+            events: &mut events,
+            users: Some(&mut users),
+        };
+        let report = export(store, None, &mut dump).unwrap();
+        assert_eq!((report.events, report.users), (2, 1));
+
+        let mut target = EventRepoInMemory::new();
+        let mut target_users = UserRepoInMemory::new();
+        for expected in [(3, 0, 0), (0, 3, 0)] {
+            let store = Store {
+                events: &mut target,
+                users: Some(&mut target_users),
+            };
+            let report = import(store, dump.as_slice(), false).unwrap();
+            assert_eq!(
+                (report.accepted, report.duplicate, report.rejected),
+                expected
+            );
+        }
+        assert_eq!(target.read_all().len(), 2);
+        assert_eq!(
+            target_users.read_user(&kp.public_key()).unwrap().name,
+            "alice"
+        );
+    }
+
+    #[test]
+    fn test_import_roles() {
+        let kp = KeyPair::generate();
+        let mut admin = User::new("alice".to_string(), kp.public_key());
+        admin.role = Role::Admin;
+        let dump = serde_json::to_string(&Record::User(admin)).unwrap();
+        for (keep_roles, role) in [(false, Role::Member), (true, Role::Admin)] {
+            let mut events = EventRepoInMemory::new();
+            let mut users = UserRepoInMemory::new();
+            let store = Store {
+                events: &mut events,
+                users: Some(&mut users),
+            };
+            import(store, dump.as_bytes(), keep_roles).unwrap();
+            assert_eq!(users.read_user(&kp.public_key()).unwrap().role, role);
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_bad_lines() {
+        let kp = KeyPair::generate();
+        let good = signed(&kp, "good");
+        let mut forged = signed(&kp, "forged");
+        forged.content = "changed".to_string();
+        let plain = serde_json::to_string(&good).unwrap();
+        let input = format!(
+            "{}\n\nnot json\n{}\n{{\"type\":\"user\",\"name\":\"x\",\"public_key\":\"y\"}}\n",
+            plain,
+            serde_json::to_string(&Record::Event(forged)).unwrap()
+        );
+        let mut events = EventRepoInMemory::new();
+        let store = Store {
+            events: &mut events,
+            users: None,
+        };
+        let report = import(store, input.as_bytes(), false).unwrap();
+        assert_eq!(
+            (report.accepted, report.duplicate, report.rejected),
+            (1, 0, 3)
+        );
+        assert!(report.errors[0].starts_with("line 3:"));
+        assert!(report.errors[1].contains("invalid signature"));
+        assert!(events.read(&good.id).is_some());
+    }
+}
@@ -0,0 +1,364 @@
+//! Who may read events addressed to a circle (see [`entity::circle`]).
+//!
+//! [`CircleRepo`] keeps the circles; [`Audience`] wraps an [`EventRepo`] so
+//! that reading, querying and counting only take circle events into account
+//! for the circle's owner and members. Anyone else, including anonymous
+//! readers, gets the store as if those events did not exist. The engagement
+//! counters of a visible event still include reactions and replies posted
+//! in circles.
+
+use crate::repository::{EventRepo, RepoError};
+use entity::circle::{self, Circle};
+use entity::reaction::Stats;
+use entity::{Count, Event, Filter};
+use std::collections::{BTreeMap, HashSet};
+
+pub trait CircleRepo {
+    fn read(&self, id: &str) -> Option<&Circle>;
+    /// Circles `public_key` owns or is a member of, in either key form.
+    fn read_for(&self, public_key: &str) -> Vec<Circle>;
+    /// Stores `circle`, replacing the one with the same id.
+    fn save(&mut self, circle: Circle) -> Result<(), RepoError>;
+    fn delete(&mut self, id: &str) -> Result<(), RepoError>;
+}
+
+/// Whether `viewer` may see `e`: always, unless `e` is addressed to a
+/// circle that does not include `viewer`. Events of unknown circles are
+/// hidden from everyone.
+pub fn visible(circles: &dyn CircleRepo, viewer: Option<&str>, e: &Event) -> bool {
+    match circle::audience(e) {
+        None => true,
+        Some(id) => match (circles.read(id), viewer) {
+            (Some(circle), Some(viewer)) => circle.includes(viewer),
+            _ => false,
+        },
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct CircleRepoInMemory {
+    circles: BTreeMap<String, Circle>,
+}
+
+impl CircleRepoInMemory {
+    pub fn new() -> CircleRepoInMemory {
+        CircleRepoInMemory::default()
+    }
+    /// A repository holding `circles`, e.g. as loaded from storage.
+    pub fn with(circles: BTreeMap<String, Circle>) -> CircleRepoInMemory {
+        CircleRepoInMemory { circles }
+    }
+    pub fn circles(&self) -> &BTreeMap<String, Circle> {
+        &self.circles
+    }
+}
+
+impl CircleRepo for CircleRepoInMemory {
+    fn read(&self, id: &str) -> Option<&Circle> {
+        self.circles.get(id)
+    }
+    fn read_for(&self, public_key: &str) -> Vec<Circle> {
+        self.circles
+            .values()
+            .filter(|c| c.includes(public_key))
+            .cloned()
+            .collect()
+    }
+    fn save(&mut self, circle: Circle) -> Result<(), RepoError> {
+        self.circles.insert(circle.id(), circle);
+        Ok(())
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        self.circles.remove(id);
+        Ok(())
+    }
+}
+
+/// The events of a store as `viewer` may see them.
+pub struct Audience<'a> {
+    events: &'a mut dyn EventRepo,
+    circles: &'a dyn CircleRepo,
+    viewer: Option<&'a str>,
+}
+
+impl<'a> Audience<'a> {
+    pub fn new(
+        events: &'a mut dyn EventRepo,
+        circles: &'a dyn CircleRepo,
+        viewer: Option<&'a str>,
+    ) -> Audience<'a> {
+        Audience {
+            events,
+            circles,
+            viewer,
+        }
+    }
+    pub fn sees(&self, e: &Event) -> bool {
+        visible(self.circles, self.viewer, e)
+    }
+    /// The ids of the circles with events in the store the viewer may not
+    /// see.
+    fn hidden_circles(&mut self) -> Vec<String> {
+        let (circles, viewer) = (self.circles, self.viewer);
+        let mut ids = self.events.tag_values("circle");
+        ids.retain(|id| match (circles.read(id), viewer) {
+            (Some(circle), Some(viewer)) => !circle.includes(viewer),
+            _ => true,
+        });
+        ids
+    }
+}
+
+/// `filter` restricted to events addressed to one of `circles`, without a
+/// limit; `None` if it cannot match any.
+fn addressed_to(filter: &Filter, circles: &[String]) -> Option<Filter> {
+    let circles: Vec<String> = match filter.tags.get("#circle") {
+        Some(wanted) => circles
+            .iter()
+            .filter(|id| wanted.contains(id))
+            .cloned()
+            .collect(),
+        None => circles.to_vec(),
+    };
+    if circles.is_empty() {
+        return None;
+    }
+    let filter = Filter {
+        limit: None,
+        ..filter.clone()
+    };
+    Some(filter.with_tag("circle", circles))
+}
+
+impl EventRepo for Audience<'_> {
+    fn add(&mut self, e: Event) -> Result<(), RepoError> {
+        self.events.add(e)
+    }
+    fn read(&mut self, id: &str) -> Option<Event> {
+        self.events.read(id).filter(|e| self.sees(e))
+    }
+    fn read_all(&mut self) -> Vec<Event> {
+        let mut events = self.events.read_all();
+        events.retain(|e| self.sees(e));
+        events
+    }
+    fn query(&mut self, filter: &Filter) -> Vec<Event> {
+        let Some(limit) = filter.limit else {
+            let mut found = self.events.query(filter);
+            found.retain(|e| self.sees(e));
+            return found;
+        };
+        // hidden events must not use up the limit, so page through bounded
+        // windows until enough visible events are found
+        let ranked = !filter.search_terms().is_empty();
+        let mut window = filter.clone();
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        loop {
+            let size = window.limit.unwrap_or(limit);
+            let page = self.events.query(&window);
+            let full = page.len() >= size;
+            let oldest = page.last().map(|e| e.created_at);
+            let mut new = 0;
+            for e in page {
+                if seen.insert(e.id.clone()) {
+                    new += 1;
+                    if self.sees(&e) {
+                        found.push(e);
+                    }
+                }
+            }
+            if found.len() >= limit || !full {
+                break;
+            }
+            if ranked || new == 0 {
+                // ranked results cannot be paged by time, and a window may
+                // be filled by events of a single second
+                window.limit = Some(size.saturating_mul(2));
+            } else {
+                // events created at `oldest` are seen again and skipped
+                window.until = oldest;
+            }
+        }
+        found.truncate(limit);
+        found
+    }
+    fn count(&mut self, filters: &[Filter]) -> Count {
+        let mut count = self.events.count(filters);
+        let hidden = self.hidden_circles();
+        if hidden.is_empty() {
+            return count;
+        }
+        // only the events of hidden circles in range are read
+        let mut ids = HashSet::new();
+        for filter in filters {
+            let Some(filter) = addressed_to(filter, &hidden) else {
+                continue;
+            };
+            for e in self.events.query(&filter) {
+                if !self.sees(&e) {
+                    ids.insert(e.id);
+                }
+            }
+        }
+        count.count = count.count.saturating_sub(ids.len() as u64);
+        count
+    }
+    fn tag_values(&mut self, name: &str) -> Vec<String> {
+        let mut values = self.events.tag_values(name);
+        values.retain(|v| {
+            let filter = Filter {
+                limit: Some(1),
+                ..Filter::new().with_tag(name, vec![v.clone()])
+            };
+            !self.query(&filter).is_empty()
+        });
+        values
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        self.events.delete(id)
+    }
+    fn delete_expired(&mut self) -> Result<Vec<String>, RepoError> {
+        self.events.delete_expired()
+    }
+    fn stats(&mut self, id: &str) -> Stats {
+        self.events.stats(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::event;
+    use crate::repository::EventRepoInMemory;
+
+    #[test]
+    fn test_conformance() {
+        // every event is public without circles, so nothing may be hidden
+        crate::conformance::event_repo(|| {
+            let events = Box::leak(Box::new(EventRepoInMemory::new()));
+            let circles = Box::leak(Box::new(CircleRepoInMemory::new()));
+            Audience::new(events, circles, Some("viewer"))
+        });
+    }
+
+    #[test]
+    fn test_audience() {
+        let (owner, member, stranger) = ("a".repeat(64), "b".repeat(64), "c".repeat(64));
+        let mut team = Circle::new(&owner, "team").unwrap();
+        team.add([&member]);
+        let mut circles = CircleRepoInMemory::new();
+        circles.save(team.clone()).unwrap();
+
+        let mut events = EventRepoInMemory::new();
+        events.add(event("public", &stranger, 1, 10)).unwrap();
+        for (id, audience) in [("inner", team.id()), ("lost", format!("{}:gone", owner))] {
+            let mut e = event(id, &owner, 1, 20);
+            circle::address(&mut e, &audience, None).unwrap();
+            events.add(e).unwrap();
+        }
+        let latest = Filter {
+            limit: Some(1),
+            ..Filter::new()
+        };
+        let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        let mut view = Audience::new(&mut events, &circles, Some(&member));
+        assert!(view.read("inner").is_some());
+        assert!(view.read("lost").is_none());
+        assert_eq!(ids(view.query(&latest)), vec!["inner"]);
+
+        for viewer in [Some(stranger.as_str()), None] {
+            let mut view = Audience::new(&mut events, &circles, viewer);
+            assert!(view.read("inner").is_none());
+            assert_eq!(ids(view.read_all()), vec!["public"]);
+            assert_eq!(ids(view.query(&latest)), vec!["public"]);
+        }
+    }
+
+    #[test]
+    fn test_query_pages() {
+        let (owner, stranger) = ("a".repeat(64), "c".repeat(64));
+        let team = Circle::new(&owner, "team").unwrap();
+        let mut circles = CircleRepoInMemory::new();
+        circles.save(team.clone()).unwrap();
+
+        let mut events = EventRepoInMemory::new();
+        for (id, created_at) in [("v1", 10), ("v2", 20), ("v3", 25), ("v4", 40)] {
+            let mut e = event(id, &stranger, 1, created_at);
+            e.content = "rust".to_string();
+            events.add(e).unwrap();
+        }
+        // hidden events newer than most visible ones, several in one second
+        for (id, created_at) in [("h1", 25), ("h2", 25), ("h3", 25), ("h4", 30), ("h5", 50)] {
+            let mut e = event(id, &owner, 1, created_at);
+            e.content = "rust".to_string();
+            circle::address(&mut e, &team.id(), None).unwrap();
+            events.add(e).unwrap();
+        }
+        let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        let mut view = Audience::new(&mut events, &circles, Some(&stranger));
+        for (limit, expected) in [
+            (1, vec!["v4"]),
+            (2, vec!["v4", "v3"]),
+            (3, vec!["v4", "v3", "v2"]),
+            (9, vec!["v4", "v3", "v2", "v1"]),
+        ] {
+            let latest = Filter {
+                limit: Some(limit),
+                ..Filter::new()
+            };
+            assert_eq!(ids(view.query(&latest)), expected, "limit {}", limit);
+            let search = Filter {
+                search: Some("rust".to_string()),
+                ..latest
+            };
+            assert_eq!(view.query(&search).len(), expected.len(), "limit {}", limit);
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let (owner, member, stranger) = ("a".repeat(64), "b".repeat(64), "c".repeat(64));
+        let mut team = Circle::new(&owner, "team").unwrap();
+        team.add([&member]);
+        let mut circles = CircleRepoInMemory::new();
+        circles.save(team.clone()).unwrap();
+
+        let mut events = EventRepoInMemory::new();
+        events.add(event("public", &owner, 1, 10)).unwrap();
+        for (id, audience) in [
+            ("inner", team.id()),
+            ("inner2", team.id()),
+            ("lost", format!("{}:gone", owner)),
+        ] {
+            let mut e = event(id, &owner, 1, 20);
+            circle::address(&mut e, &audience, None).unwrap();
+            events.add(e).unwrap();
+        }
+        let notes = Filter {
+            kinds: Some(vec![1]),
+            ..Filter::new()
+        };
+        let by_owner = Filter {
+            authors: Some(vec![owner.clone()]),
+            ..Filter::new()
+        };
+        let in_team = Filter::new().with_tag("circle", vec![team.id()]);
+        let filters = [notes.clone(), by_owner];
+
+        let mut view = Audience::new(&mut events, &circles, Some(&member));
+        assert_eq!(view.count(std::slice::from_ref(&notes)).count, 3);
+        assert_eq!(view.count(&filters).count, 3);
+        assert_eq!(view.count(std::slice::from_ref(&in_team)).count, 2);
+        assert_eq!(view.tag_values("circle"), vec![team.id()]);
+
+        for viewer in [Some(stranger.as_str()), None] {
+            let mut view = Audience::new(&mut events, &circles, viewer);
+            assert_eq!(view.count(std::slice::from_ref(&notes)).count, 1);
+            assert_eq!(view.count(&filters).count, 1);
+            assert_eq!(view.count(std::slice::from_ref(&in_team)).count, 0);
+            assert!(view.tag_values("circle").is_empty());
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Behaviour every repository implementation has to share.
+//!
+//! Each entry point takes a constructor for empty repositories and runs all
+//! scenarios against fresh instances, panicking on the first mismatch. Call
+//! them from a test of the crate that implements the backend:
+//!
+//! ```ignore
+//! #[test]
+//! fn conformance() {
+//!     rustr_core::conformance::event_repo(MyEventRepo::new);
+//! }
+//! ```
+
+use crate::circle::CircleRepo;
+use crate::feed::{Change, ChangeFeed, Publishing};
+use crate::moderation::{Moderation, ModerationRepo};
+use crate::repository::{EventRepo, RepoError, SessionRepo, UserRepo};
+use entity::circle::Circle;
+use entity::reaction::Stats;
+use entity::{kind, Event, Filter, KeyPair, Role, Session, User};
+
+/// Runs all `EventRepo` scenarios.
+pub fn event_repo<R: EventRepo>(new_repo: impl Fn() -> R) {
+    event_insert_and_read(&mut new_repo());
+    event_duplicate_ids(&mut new_repo());
+    event_delete(&mut new_repo());
+    event_expiration(&mut new_repo());
+    event_expiry_published(Publishing::new(new_repo(), ChangeFeed::default()));
+    event_filtering(&mut new_repo());
+    event_replaceable(&mut new_repo());
+    event_addressable(&mut new_repo());
+    event_ordering(&mut new_repo());
+    event_search(&mut new_repo());
+    event_stats(&mut new_repo());
+    event_count(&mut new_repo());
+    event_tag_values(&mut new_repo());
+}
+
+/// Runs all `UserRepo` scenarios.
+pub fn user_repo<R: UserRepo>(new_repo: impl Fn() -> R) {
+    user_insert_and_read(&mut new_repo());
+    user_overwrite(&mut new_repo());
+    user_find_by_name(&mut new_repo());
+    user_last_seen(&mut new_repo());
+    user_key_forms(&mut new_repo());
+}
+
+/// Runs all `SessionRepo` scenarios.
+pub fn session_repo<R: SessionRepo>(new_repo: impl Fn() -> R) {
+    session_insert_and_read(&mut new_repo());
+    session_delete(&mut new_repo());
+    session_expiration(&mut new_repo());
+}
+
+/// Runs all `ModerationRepo` scenarios.
+pub fn moderation_repo<R: ModerationRepo>(new_repo: impl Fn() -> R) {
+    moderation_save(&mut new_repo());
+}
+
+/// Runs all `CircleRepo` scenarios.
+pub fn circle_repo<R: CircleRepo>(new_repo: impl Fn() -> R) {
+    circle_save_and_read(&mut new_repo());
+    circle_delete(&mut new_repo());
+}
+
+/// An unsigned event with a fixed id; repositories do not verify signatures.
+pub fn event(id: &str, author: &str, kind: u32, created_at: u64) -> Event {
+    let mut e = Event::new(author.to_string(), format!("content of {}", id), 0);
+    e.id = id.to_string();
+    e.kind = kind;
+    e.created_at = created_at;
+    e
+}
+
+fn ids(events: &[Event]) -> Vec<&str> {
+    events.iter().map(|e| e.id.as_str()).collect()
+}
+
+fn sorted_ids(events: &[Event]) -> Vec<&str> {
+    let mut ids = ids(events);
+    ids.sort();
+    ids
+}
+
+fn event_insert_and_read<R: EventRepo>(repo: &mut R) {
+    assert!(repo.read("a").is_none(), "empty repo returned an event");
+    assert!(repo.read_all().is_empty(), "empty repo returned events");
+    let mut e = event("a", "alice", kind::TEXT_NOTE, 10);
+    e.tags = vec![vec!["t".to_string(), "rust".to_string()]];
+    repo.add(e.clone()).expect("insert failed");
+    let read = repo.read("a").expect("inserted event not found");
+    assert_eq!(read.content, e.content);
+    assert_eq!(read.tags, e.tags);
+    assert_eq!(read.created_at, 10);
+    repo.add(event("b", "bob", kind::TEXT_NOTE, 20)).unwrap();
+    assert_eq!(sorted_ids(&repo.read_all()), vec!["a", "b"]);
+}
+
+fn event_duplicate_ids<R: EventRepo>(repo: &mut R) {
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+    let mut copy = event("a", "alice", kind::TEXT_NOTE, 10);
+    copy.content = "changed".to_string();
+    assert!(
+        matches!(repo.add(copy), Err(RepoError::Duplicate(id)) if id == "a"),
+        "duplicate id was accepted"
+    );
+    assert_eq!(repo.read("a").unwrap().content, "content of a");
+    assert_eq!(repo.read_all().len(), 1);
+}
+
+fn event_delete<R: EventRepo>(repo: &mut R) {
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+    repo.add(event("b", "alice", kind::TEXT_NOTE, 20)).unwrap();
+    repo.delete("a").expect("delete failed");
+    assert!(repo.read("a").is_none(), "deleted event still readable");
+    assert_eq!(ids(&repo.read_all()), vec!["b"]);
+    assert_eq!(ids(&repo.query(&Filter::new())), vec!["b"]);
+    repo.delete("a")
+        .expect("deleting a missing event must succeed");
+    // the id is free again
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+}
+
+fn event_expiration<R: EventRepo>(repo: &mut R) {
+    let mut expired = event("old", "alice", kind::TEXT_NOTE, 10);
+    expired.expires_at = 1;
+    let mut pending = event("new", "alice", kind::TEXT_NOTE, 20);
+    pending.expires_at = u64::MAX;
+    repo.add(expired).unwrap();
+    repo.add(pending).unwrap();
+    repo.add(event("forever", "alice", kind::TEXT_NOTE, 30))
+        .unwrap();
+
+    assert!(repo.read("old").is_none(), "expired event was returned");
+    assert_eq!(sorted_ids(&repo.read_all()), vec!["forever", "new"]);
+    assert_eq!(ids(&repo.query(&Filter::new())), vec!["forever", "new"]);
+
+    let mut expired = event("later", "bob", kind::TEXT_NOTE, 40);
+    expired.expires_at = 1;
+    repo.add(expired).unwrap();
+    assert_eq!(ids(&repo.query(&Filter::new())), vec!["forever", "new"]);
+    assert_eq!(repo.read_all().len(), 2);
+
+    let mut expired = event("gone", "bob", kind::TEXT_NOTE, 50);
+    expired.expires_at = 1;
+    repo.add(expired).unwrap();
+    assert!(repo.read("gone").is_none());
+    // reading leaves the removal, and reporting it, to delete_expired
+    let mut removed = repo.delete_expired().unwrap();
+    removed.sort();
+    assert_eq!(removed, vec!["gone", "later", "old"]);
+    assert!(repo.delete_expired().unwrap().is_empty());
+    assert_eq!(repo.read_all().len(), 2);
+}
+
+/// Reads skip an expired event, and its removal is still announced.
+fn event_expiry_published<R: EventRepo>(mut repo: Publishing<R>) {
+    let mut expired = event("x", "bob", kind::TEXT_NOTE, 5);
+    expired.expires_at = 1;
+    repo.add(expired).unwrap();
+    let mut changes = repo.feed().subscribe();
+    assert!(repo.read("x").is_none());
+    assert!(repo.read_all().is_empty());
+    assert!(repo.query(&Filter::new()).is_empty());
+    assert_eq!(changes.try_recv(), Ok(None), "reading changed the store");
+    assert_eq!(repo.delete_expired().unwrap(), vec!["x"]);
+    assert_eq!(
+        changes.try_recv(),
+        Ok(Some(Change::Expired("x".to_string())))
+    );
+}
+
+fn event_filtering<R: EventRepo>(repo: &mut R) {
+    let mut tagged = event("c", "alice", 7, 30);
+    tagged.tags = vec![
+        vec!["e".to_string(), "a".to_string()],
+        vec!["p".to_string(), "bob".to_string()],
+    ];
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+    repo.add(event("b", "bob", kind::TEXT_NOTE, 20)).unwrap();
+    repo.add(tagged).unwrap();
+    repo.add(event("d", "carol", 7, 40)).unwrap();
+
+    let cases = vec![
+        (Filter::new(), vec!["d", "c", "b", "a"]),
+        (
+            Filter {
+                ids: Some(vec!["a".to_string(), "d".to_string(), "x".to_string()]),
+                ..Filter::default()
+            },
+            vec!["d", "a"],
+        ),
+        (
+            Filter {
+                authors: Some(vec!["alice".to_string(), "carol".to_string()]),
+                ..Filter::default()
+            },
+            vec!["d", "c", "a"],
+        ),
+        (
+            Filter {
+                kinds: Some(vec![7]),
+                ..Filter::default()
+            },
+            vec!["d", "c"],
+        ),
+        (
+            Filter {
+                kinds: Some(vec![7]),
+                authors: Some(vec!["alice".to_string()]),
+                ..Filter::default()
+            },
+            vec!["c"],
+        ),
+        (
+            Filter {
+                since: Some(20),
+                until: Some(30),
+                ..Filter::default()
+            },
+            vec!["c", "b"],
+        ),
+        (
+            Filter::new().with_tag("e", vec!["a".to_string()]),
+            vec!["c"],
+        ),
+        (
+            Filter::new()
+                .with_tag("e", vec!["a".to_string()])
+                .with_tag("p", vec!["alice".to_string()]),
+            vec![],
+        ),
+        (
+            Filter {
+                limit: Some(2),
+                ..Filter::default()
+            },
+            vec!["d", "c"],
+        ),
+        (
+            Filter {
+                authors: Some(vec!["alice".to_string(), "bob".to_string()]),
+                limit: Some(2),
+                ..Filter::default()
+            },
+            vec!["c", "b"],
+        ),
+    ];
+    for (filter, expected) in cases {
+        assert_eq!(
+            ids(&repo.query(&filter)),
+            expected,
+            "unexpected result for {:?}",
+            filter
+        );
+    }
+}
+
+fn event_replaceable<R: EventRepo>(repo: &mut R) {
+    repo.add(event("m1", "alice", kind::METADATA, 10)).unwrap();
+    repo.add(event("m2", "alice", kind::METADATA, 20)).unwrap();
+    repo.add(event("mb", "bob", kind::METADATA, 5)).unwrap();
+    assert!(repo.read("m1").is_none(), "replaced event still stored");
+    assert_eq!(repo.read("m2").unwrap().created_at, 20);
+    assert!(
+        matches!(
+            repo.add(event("m0", "alice", kind::METADATA, 15)),
+            Err(RepoError::Outdated(_))
+        ),
+        "older replaceable event was accepted"
+    );
+    assert!(repo.read("m0").is_none());
+    let metadata = Filter {
+        kinds: Some(vec![kind::METADATA]),
+        ..Filter::default()
+    };
+    assert_eq!(ids(&repo.query(&metadata)), vec!["m2", "mb"]);
+
+    // same second: the lower id wins
+    repo.add(event("m1b", "alice", kind::METADATA, 20)).unwrap();
+    assert!(repo.read("m2").is_none());
+    assert!(matches!(
+        repo.add(event("m9", "alice", kind::METADATA, 20)),
+        Err(RepoError::Outdated(_))
+    ));
+
+    // deleting the current version frees the slot
+    repo.delete("m1b").unwrap();
+    repo.add(event("m4", "alice", kind::METADATA, 1)).unwrap();
+    assert_eq!(ids(&repo.query(&metadata)), vec!["mb", "m4"]);
+}
+
+fn event_addressable<R: EventRepo>(repo: &mut R) {
+    let article = |id: &str, d: &str, created_at: u64| {
+        let mut e = event(id, "alice", 30_023, created_at);
+        e.tags = vec![vec!["d".to_string(), d.to_string()]];
+        e
+    };
+    repo.add(article("a1", "intro", 10)).unwrap();
+    repo.add(article("b1", "design", 10)).unwrap();
+    repo.add(article("a2", "intro", 20)).unwrap();
+    assert!(repo.read("a1").is_none(), "replaced address still stored");
+    assert_eq!(sorted_ids(&repo.read_all()), vec!["a2", "b1"]);
+    assert!(matches!(
+        repo.add(article("a0", "intro", 5)),
+        Err(RepoError::Outdated(_))
+    ));
+}
+
+fn event_ordering<R: EventRepo>(repo: &mut R) {
+    for (id, created_at) in [("b", 10), ("a", 30), ("d", 20), ("c", 20)] {
+        repo.add(event(id, "alice", kind::TEXT_NOTE, created_at))
+            .unwrap();
+    }
+    assert_eq!(ids(&repo.query(&Filter::new())), vec!["a", "d", "c", "b"]);
+    let author = Filter {
+        authors: Some(vec!["alice".to_string()]),
+        limit: Some(3),
+        ..Filter::default()
+    };
+    assert_eq!(ids(&repo.query(&author)), vec!["a", "d", "c"]);
+}
+
+fn event_search<R: EventRepo>(repo: &mut R) {
+    let note = |id: &str, kind: u32, created_at: u64, content: &str| {
+        let mut e = event(id, "alice", kind, created_at);
+        e.content = content.to_string();
+        e
+    };
+    repo.add(note(
+        "a",
+        kind::TEXT_NOTE,
+        10,
+        "Rust relay, rust client, RUST!",
+    ))
+    .unwrap();
+    repo.add(note("b", kind::TEXT_NOTE, 20, "a relay written in rust"))
+        .unwrap();
+    repo.add(note("c", kind::TEXT_NOTE, 30, "nothing to see"))
+        .unwrap();
+    repo.add(note("d", 7, 40, "rust")).unwrap();
+    let mut expired = note("e", kind::TEXT_NOTE, 50, "rust relay");
+    expired.expires_at = 1;
+    repo.add(expired).unwrap();
+
+    let search = |q: &str| Filter {
+        search: Some(q.to_string()),
+        ..Filter::default()
+    };
+    assert_eq!(ids(&repo.query(&search("RELAY rust"))), vec!["a", "b"]);
+    assert_eq!(ids(&repo.query(&search("rust"))), vec!["a", "d", "b"]);
+    assert!(repo.query(&search("rust go")).is_empty());
+    let notes = Filter {
+        kinds: Some(vec![kind::TEXT_NOTE]),
+        limit: Some(1),
+        ..search("rust")
+    };
+    assert_eq!(ids(&repo.query(&notes)), vec!["a"]);
+
+    repo.delete("a").unwrap();
+    assert_eq!(ids(&repo.query(&search("client"))), Vec::<&str>::new());
+    let mut profile = note("p1", kind::METADATA, 10, "old bio");
+    repo.add(profile.clone()).unwrap();
+    profile.id = "p2".to_string();
+    profile.created_at = 20;
+    profile.content = "new bio".to_string();
+    repo.add(profile).unwrap();
+    assert_eq!(ids(&repo.query(&search("bio"))), vec!["p2"]);
+    assert!(repo.query(&search("old")).is_empty());
+}
+
+fn event_count<R: EventRepo>(repo: &mut R) {
+    assert_eq!(repo.count(&[Filter::new()]).count, 0);
+    let mut tagged = event("c", "alice", 7, 30);
+    tagged.tags = vec![
+        vec!["t".to_string(), "rust".to_string()],
+        vec!["t".to_string(), "nostr".to_string()],
+    ];
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+    repo.add(event("b", "bob", kind::TEXT_NOTE, 20)).unwrap();
+    repo.add(tagged).unwrap();
+    let mut note = event("d", "carol", kind::TEXT_NOTE, 40);
+    note.content = "rust relay".to_string();
+    repo.add(note).unwrap();
+
+    let count = |repo: &mut R, filters: &[Filter]| repo.count(filters).count;
+    let limited = Filter {
+        limit: Some(1),
+        ..Filter::default()
+    };
+    assert_eq!(count(repo, &[limited]), 4, "limits must be ignored");
+    let notes = Filter {
+        kinds: Some(vec![kind::TEXT_NOTE]),
+        ..Filter::default()
+    };
+    assert_eq!(count(repo, std::slice::from_ref(&notes)), 3);
+    let alices_notes = Filter {
+        authors: Some(vec!["alice".to_string()]),
+        ..notes.clone()
+    };
+    assert_eq!(count(repo, &[alices_notes]), 1);
+    let recent = Filter {
+        since: Some(20),
+        until: Some(30),
+        ..Filter::default()
+    };
+    assert_eq!(count(repo, &[recent]), 2);
+    let tags = Filter::new().with_tag("t", vec!["rust".to_string(), "nostr".to_string()]);
+    assert_eq!(
+        count(repo, std::slice::from_ref(&tags)),
+        1,
+        "an event counts once"
+    );
+    assert_eq!(count(repo, &[notes, tags]), 4);
+    let ids = Filter {
+        ids: Some(vec!["a".to_string(), "a".to_string(), "x".to_string()]),
+        ..Filter::default()
+    };
+    assert_eq!(count(repo, &[ids]), 1);
+    let search = Filter {
+        search: Some("rust".to_string()),
+        ..Filter::default()
+    };
+    assert_eq!(count(repo, &[search]), 1);
+    assert_eq!(count(repo, &[]), 0);
+    repo.delete("a").unwrap();
+    assert_eq!(count(repo, &[Filter::new()]), 3);
+    assert!(!repo.count(&[Filter::new()]).approximate);
+}
+
+fn event_tag_values<R: EventRepo>(repo: &mut R) {
+    assert!(repo.tag_values("t").is_empty());
+    let tagged = |id: &str, values: &[&str]| {
+        let mut e = event(id, "alice", kind::TEXT_NOTE, 10);
+        e.tags = values
+            .iter()
+            .map(|v| vec!["t".to_string(), v.to_string()])
+            .collect();
+        e.tags.push(vec!["tt".to_string(), "other".to_string()]);
+        e
+    };
+    repo.add(tagged("a", &["rust", "nostr"])).unwrap();
+    repo.add(tagged("b", &["rust", "relay"])).unwrap();
+    assert_eq!(repo.tag_values("t"), vec!["nostr", "relay", "rust"]);
+    assert_eq!(repo.tag_values("tt"), vec!["other"]);
+    repo.delete("b").unwrap();
+    assert_eq!(repo.tag_values("t"), vec!["nostr", "rust"]);
+    assert!(repo.tag_values("e").is_empty());
+}
+
+fn event_stats<R: EventRepo>(repo: &mut R) {
+    let pointing_at = |id: &str, kind: u32, content: &str, target: &str| {
+        let mut e = event(id, "bob", kind, 20);
+        e.content = content.to_string();
+        e.tags = vec![vec!["e".to_string(), target.to_string()]];
+        e
+    };
+    repo.add(event("a", "alice", kind::TEXT_NOTE, 10)).unwrap();
+    repo.add(pointing_at("r1", kind::REACTION, "+", "a"))
+        .unwrap();
+    repo.add(pointing_at("r2", kind::REACTION, "", "a"))
+        .unwrap();
+    repo.add(pointing_at("r3", kind::REACTION, "🤙", "a"))
+        .unwrap();
+    repo.add(pointing_at("s1", kind::REPOST, "", "a")).unwrap();
+    repo.add(pointing_at("n1", kind::TEXT_NOTE, "reply", "a"))
+        .unwrap();
+    assert!(repo
+        .add(pointing_at("r1", kind::REACTION, "+", "a"))
+        .is_err());
+
+    let stats = repo.stats("a");
+    assert_eq!(
+        stats.reactions.into_iter().collect::<Vec<_>>(),
+        vec![("+".to_string(), 2), ("🤙".to_string(), 1)]
+    );
+    assert_eq!((stats.reposts, stats.replies), (1, 1));
+    assert_eq!(repo.stats("n1"), Stats::default());
+
+    repo.delete("r1").unwrap();
+    repo.delete("r3").unwrap();
+    repo.delete("s1").unwrap();
+    repo.delete("s1").unwrap();
+    let stats = repo.stats("a");
+    assert_eq!(stats.reactions.get("+"), Some(&1));
+    assert!(!stats.reactions.contains_key("🤙"));
+    assert_eq!((stats.reposts, stats.replies), (0, 1));
+
+    let mut expired = pointing_at("r4", kind::REACTION, "-", "a");
+    expired.expires_at = 1;
+    repo.add(expired).unwrap();
+    repo.delete_expired().unwrap();
+    assert!(!repo.stats("a").reactions.contains_key("-"));
+}
+
+fn user_insert_and_read<R: UserRepo>(repo: &mut R) {
+    assert!(repo.read_user("pk1").is_none());
+    repo.add_user(User::new("alice".to_string(), "pk1".to_string()));
+    repo.add_user(User::new("bob".to_string(), "pk2".to_string()));
+    assert_eq!(repo.read_user("pk1").unwrap().name, "alice");
+    let mut names: Vec<String> = repo.read_all_users().into_iter().map(|u| u.name).collect();
+    names.sort();
+    assert_eq!(names, vec!["alice", "bob"]);
+}
+
+fn user_overwrite<R: UserRepo>(repo: &mut R) {
+    repo.add_user(User::new("alice".to_string(), "pk1".to_string()));
+    repo.add_user(User::new("alicia".to_string(), "pk1".to_string()));
+    assert_eq!(repo.read_user("pk1").unwrap().name, "alicia");
+    assert_eq!(repo.read_all_users().len(), 1);
+}
+
+fn user_find_by_name<R: UserRepo>(repo: &mut R) {
+    repo.add_user(User::new("alice".to_string(), "pk1".to_string()));
+    assert_eq!(repo.find_user_by_name("ALICE").unwrap().public_key, "pk1");
+    assert!(repo.find_user_by_name("bob").is_none());
+    repo.add_user(User::new("alicia".to_string(), "pk1".to_string()));
+    assert!(repo.find_user_by_name("alice").is_none());
+}
+
+fn user_last_seen<R: UserRepo>(repo: &mut R) {
+    assert_eq!(repo.last_seen("pk1"), 0);
+    repo.mark_seen("pk1", 20);
+    repo.mark_seen("pk1", 10);
+    assert_eq!(repo.last_seen("pk1"), 20, "the marker moved back");
+    repo.add_user(User::new("alice".to_string(), "pk1".to_string()));
+    assert_eq!(repo.last_seen("pk1"), 20);
+    assert_eq!(repo.last_seen("pk2"), 0);
+}
+
+fn user_key_forms<R: UserRepo>(repo: &mut R) {
+    let x = "a".repeat(64);
+    let mut user = User::new("alice".to_string(), format!("02{}", x));
+    user.role = Role::Bot;
+    repo.add_user(user);
+    for pk in [x.clone(), format!("02{}", x), format!("03{}", x)] {
+        assert_eq!(
+            repo.read_user(&pk).map(|u| u.role),
+            Some(Role::Bot),
+            "{}",
+            pk
+        );
+    }
+    repo.add_user(User::new("alicia".to_string(), x.clone()));
+    assert_eq!(repo.read_all_users().len(), 1, "one key, two entries");
+    repo.mark_seen(&format!("03{}", x), 20);
+    assert_eq!(repo.last_seen(&x), 20);
+}
+
+fn session(expires_at: u64) -> Session {
+    Session::new(KeyPair::generate().public_key(), expires_at)
+}
+
+fn session_insert_and_read<R: SessionRepo>(repo: &mut R) {
+    let s = session(0);
+    let id = s.get_id();
+    assert!(repo.read(&id).is_none());
+    repo.add(s);
+    assert_eq!(repo.read(&id).expect("session not found").get_id(), id);
+    repo.add(session(0));
+    assert_eq!(repo.read_all().len(), 2);
+}
+
+fn session_delete<R: SessionRepo>(repo: &mut R) {
+    let s = session(0);
+    let id = s.get_id();
+    repo.add(s);
+    repo.delete(&id);
+    assert!(repo.read(&id).is_none(), "deleted session still readable");
+    repo.delete(&id);
+}
+
+fn session_expiration<R: SessionRepo>(repo: &mut R) {
+    let expired = session(1);
+    let expired_id = expired.get_id();
+    let valid = session(0);
+    let valid_id = valid.get_id();
+    repo.add(expired);
+    repo.add(valid);
+    assert!(repo.read(&expired_id).is_none(), "expired session returned");
+    let all: Vec<String> = repo.read_all().iter().map(|s| s.get_id()).collect();
+    assert_eq!(all, vec![valid_id]);
+}
+
+fn moderation_save<R: ModerationRepo>(repo: &mut R) {
+    assert_eq!(repo.lists(), &Moderation::default());
+    let mut lists = Moderation::default();
+    lists.bans.clients.pubkeys.insert("mallory".to_string());
+    lists.bans.kinds.insert(4);
+    lists
+        .allow
+        .clients
+        .ips
+        .insert("10.0.0.0/8".parse().unwrap());
+    repo.save(lists.clone()).unwrap();
+    assert_eq!(repo.lists(), &lists);
+    repo.save(Moderation::default()).unwrap();
+    assert_eq!(repo.lists(), &Moderation::default());
+}
+
+fn circle_save_and_read<R: CircleRepo>(repo: &mut R) {
+    let (owner, member) = ("a".repeat(64), "b".repeat(64));
+    let mut team = Circle::new(&owner, "team").unwrap();
+    assert!(
+        repo.read(&team.id()).is_none(),
+        "empty repo returned a circle"
+    );
+    repo.save(team.clone()).unwrap();
+    repo.save(Circle::new(&member, "own").unwrap()).unwrap();
+    assert_eq!(repo.read(&team.id()), Some(&team));
+    assert_eq!(
+        repo.read_for(&member).len(),
+        1,
+        "member sees foreign circles"
+    );
+
+    team.add(&[format!("02{}", member)]);
+    repo.save(team.clone()).unwrap();
+    assert_eq!(
+        repo.read(&team.id()),
+        Some(&team),
+        "circle was not replaced"
+    );
+    let mut names: Vec<_> = repo.read_for(&member).into_iter().map(|c| c.name).collect();
+    names.sort();
+    assert_eq!(names, vec!["own", "team"]);
+    assert_eq!(repo.read_for(&format!("03{}", owner)).len(), 1);
+}
+
+fn circle_delete<R: CircleRepo>(repo: &mut R) {
+    let team = Circle::new(&"a".repeat(64), "team").unwrap();
+    repo.save(team.clone()).unwrap();
+    repo.delete(&team.id()).unwrap();
+    assert!(
+        repo.read(&team.id()).is_none(),
+        "deleted circle still readable"
+    );
+    repo.delete(&team.id()).expect("deleting twice failed");
+}
@@ -0,0 +1,303 @@
+//! Notifications about changes to an event store.
+//!
+//! [`Publishing`] wraps any [`EventRepo`] and announces every successful
+//! write on a [`ChangeFeed`]. The feed is a bounded broadcast channel: each
+//! subscriber has its own cursor, and one that falls more than the capacity
+//! behind loses the oldest changes and is told how many with
+//! [`FeedError::Lagged`], after which it continues with the oldest change
+//! still buffered.
+
+use crate::repository::{EventRepo, RepoError};
+use entity::reaction::Stats;
+use entity::{kind, Count, Event, Filter};
+use tokio::sync::broadcast;
+
+/// Changes kept for subscribers that have not caught up yet.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(Event),
+    /// `new` took the address of the event with id `old`.
+    Replaced {
+        old: String,
+        new: Event,
+    },
+    Deleted(String),
+    Expired(String),
+}
+
+impl Change {
+    /// The stored event, for additions and replacements.
+    pub fn event(&self) -> Option<&Event> {
+        match self {
+            Change::Added(e) | Change::Replaced { new: e, .. } => Some(e),
+            Change::Deleted(_) | Change::Expired(_) => None,
+        }
+    }
+    /// Whether this change stored an event matching `filter`.
+    pub fn matches(&self, filter: &Filter) -> bool {
+        self.event().is_some_and(|e| filter.matches(e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedError {
+    /// The subscriber missed this many changes.
+    Lagged(u64),
+    /// The feed was dropped.
+    Closed,
+}
+
+impl std::fmt::Display for FeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeedError::Lagged(n) => write!(f, "subscriber lagged behind by {} changes", n),
+            FeedError::Closed => write!(f, "change feed closed"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+#[derive(Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<Change>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> ChangeFeed {
+        let (sender, _) = broadcast::channel(capacity);
+        ChangeFeed { sender }
+    }
+    /// Receives all changes published from now on.
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.sender.subscribe(),
+        }
+    }
+    pub fn publish(&self, change: Change) {
+        // no subscribers is not an error
+        let _ = self.sender.send(change);
+    }
+    pub fn subscribers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Default for ChangeFeed {
+    fn default() -> ChangeFeed {
+        ChangeFeed::new(DEFAULT_CAPACITY)
+    }
+}
+
+pub struct Subscription {
+    receiver: broadcast::Receiver<Change>,
+}
+
+impl Subscription {
+    /// Waits for the next change.
+    pub async fn recv(&mut self) -> Result<Change, FeedError> {
+        self.receiver.recv().await.map_err(|e| match e {
+            broadcast::error::RecvError::Lagged(n) => FeedError::Lagged(n),
+            broadcast::error::RecvError::Closed => FeedError::Closed,
+        })
+    }
+    /// The next change if one is buffered.
+    pub fn try_recv(&mut self) -> Result<Option<Change>, FeedError> {
+        match self.receiver.try_recv() {
+            Ok(change) => Ok(Some(change)),
+            Err(broadcast::error::TryRecvError::Empty) => Ok(None),
+            Err(broadcast::error::TryRecvError::Lagged(n)) => Err(FeedError::Lagged(n)),
+            Err(broadcast::error::TryRecvError::Closed) => Err(FeedError::Closed),
+        }
+    }
+}
+
+/// An event repository that publishes its changes on a feed.
+pub struct Publishing<R> {
+    inner: R,
+    feed: ChangeFeed,
+}
+
+impl<R: EventRepo> Publishing<R> {
+    pub fn new(inner: R, feed: ChangeFeed) -> Publishing<R> {
+        Publishing { inner, feed }
+    }
+    pub fn feed(&self) -> &ChangeFeed {
+        &self.feed
+    }
+
+    /// The stored event `e` would replace, if any. At most one event is
+    /// stored per address, so a single one is read through the indexes;
+    /// only an addressable event without a `d` tag needs all events of its
+    /// author and kind.
+    fn current(&mut self, e: &Event) -> Result<Option<Event>, RepoError> {
+        let Some(address) = e.address() else {
+            return Ok(None);
+        };
+        let mut filter = Filter {
+            authors: Some(vec![e.public_key.clone()]),
+            kinds: Some(vec![e.kind]),
+            ..Filter::new()
+        };
+        let d = e.tag_values("d").next();
+        if !kind::is_addressable(e.kind) {
+            filter.limit = Some(1);
+        } else if let Some(d) = d {
+            filter = filter.with_tag("d", vec![d.to_string()]);
+            filter.limit = Some(1);
+        }
+        Ok(self
+            .inner
+            .try_query(&filter)?
+            .into_iter()
+            .find(|old| old.address().as_ref() == Some(&address)))
+    }
+}
+
+impl<R: EventRepo> EventRepo for Publishing<R> {
+    fn add(&mut self, e: Event) -> Result<(), RepoError> {
+        let current = self.current(&e)?;
+        self.inner.add(e.clone())?;
+        self.feed.publish(match current {
+            Some(old) => Change::Replaced {
+                old: old.id,
+                new: e,
+            },
+            None => Change::Added(e),
+        });
+        Ok(())
+    }
+    fn read(&mut self, id: &str) -> Option<Event> {
+        self.inner.read(id)
+    }
+    fn read_all(&mut self) -> Vec<Event> {
+        self.inner.read_all()
+    }
+    fn query(&mut self, filter: &Filter) -> Vec<Event> {
+        self.inner.query(filter)
+    }
+    fn try_query(&mut self, filter: &Filter) -> Result<Vec<Event>, RepoError> {
+        self.inner.try_query(filter)
+    }
+    fn count(&mut self, filters: &[Filter]) -> Count {
+        self.inner.count(filters)
+    }
+    fn tag_values(&mut self, name: &str) -> Vec<String> {
+        self.inner.tag_values(name)
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        let filter = Filter {
+            ids: Some(vec![id.to_string()]),
+            ..Filter::new()
+        };
+        let existed = !self.inner.try_query(&filter)?.is_empty();
+        self.inner.delete(id)?;
+        if existed {
+            self.feed.publish(Change::Deleted(id.to_string()));
+        }
+        Ok(())
+    }
+    fn delete_expired(&mut self) -> Result<Vec<String>, RepoError> {
+        let expired = self.inner.delete_expired()?;
+        for id in &expired {
+            self.feed.publish(Change::Expired(id.clone()));
+        }
+        Ok(expired)
+    }
+    fn stats(&mut self, id: &str) -> Stats {
+        self.inner.stats(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::event;
+    use crate::repository::EventRepoInMemory;
+    use entity::kind;
+
+    #[test]
+    fn test_conformance() {
+        crate::conformance::event_repo(|| {
+            Publishing::new(EventRepoInMemory::new(), ChangeFeed::default())
+        });
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut repo = Publishing::new(EventRepoInMemory::new(), ChangeFeed::default());
+        let mut sub = repo.feed().subscribe();
+        repo.add(event("a", "alice", 1, 10)).unwrap();
+        repo.add(event("m1", "alice", kind::METADATA, 10)).unwrap();
+        repo.add(event("m2", "alice", kind::METADATA, 20)).unwrap();
+        assert!(repo.add(event("a", "alice", 1, 10)).is_err());
+        repo.delete("a").unwrap();
+        repo.delete("missing").unwrap();
+        let mut expired = event("x", "bob", 1, 5);
+        expired.expires_at = 1;
+        repo.add(expired.clone()).unwrap();
+        repo.delete_expired().unwrap();
+
+        let mut changes = Vec::new();
+        while let Some(change) = sub.try_recv().unwrap() {
+            changes.push(change);
+        }
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added(event("a", "alice", 1, 10)),
+                Change::Added(event("m1", "alice", kind::METADATA, 10)),
+                Change::Replaced {
+                    old: "m1".to_string(),
+                    new: event("m2", "alice", kind::METADATA, 20)
+                },
+                Change::Deleted("a".to_string()),
+                Change::Added(expired),
+                Change::Expired("x".to_string()),
+            ]
+        );
+        assert!(changes[0].matches(&Filter {
+            authors: Some(vec!["alice".to_string()]),
+            ..Filter::new()
+        }));
+        assert!(!changes[3].matches(&Filter::new()));
+    }
+
+    #[test]
+    fn test_replaced_addressable() {
+        let mut repo = Publishing::new(EventRepoInMemory::new(), ChangeFeed::default());
+        let article = |id: &str, d: &str, created_at: u64| {
+            let mut e = event(id, "alice", 30_023, created_at);
+            e.tags = vec![vec!["d".to_string(), d.to_string()]];
+            e
+        };
+        repo.add(article("a1", "a", 10)).unwrap();
+        repo.add(article("b1", "b", 20)).unwrap();
+        let mut sub = repo.feed().subscribe();
+        repo.add(article("a2", "a", 30)).unwrap();
+        assert_eq!(
+            sub.try_recv().unwrap(),
+            Some(Change::Replaced {
+                old: "a1".to_string(),
+                new: article("a2", "a", 30)
+            })
+        );
+    }
+
+    #[test]
+    fn test_lagging_subscriber() {
+        let feed = ChangeFeed::new(2);
+        let mut sub = feed.subscribe();
+        for id in ["a", "b", "c"] {
+            feed.publish(Change::Deleted(id.to_string()));
+        }
+        assert_eq!(sub.try_recv(), Err(FeedError::Lagged(1)));
+        assert_eq!(sub.try_recv(), Ok(Some(Change::Deleted("b".to_string()))));
+        assert_eq!(sub.try_recv(), Ok(Some(Change::Deleted("c".to_string()))));
+        assert_eq!(sub.try_recv(), Ok(None));
+        drop(feed);
+        assert_eq!(sub.try_recv(), Err(FeedError::Closed));
+    }
+}
@@ -0,0 +1,933 @@
+//! `EventRepo` on top of the embedded transactional key-value store redb.
+//!
+//! Events are stored once under their id and referenced from ordered index
+//! tables whose keys end in `created_at` (big endian) followed by the id, so a
+//! reverse range scan over a key prefix yields the newest events first:
+//!
+//! | table               | key                                        |
+//! |---------------------|--------------------------------------------|
+//! | `events`            | `id`                                       |
+//! | `events_by_created` | `created_at ‖ id`                          |
+//! | `events_by_author`  | `author ‖ 0 ‖ created_at ‖ id`             |
+//! | `events_by_kind`    | `kind ‖ created_at ‖ id`                   |
+//! | `events_by_tag`     | `tag ‖ 0 ‖ value ‖ 0 ‖ created_at ‖ id`    |
+//!
+//! `events_by_address` maps the address of replaceable and addressable
+//! events to the id currently occupying it, and `events_by_term` maps
+//! `term ‖ 0 ‖ id` to the term frequency for full-text search.
+//! `events_by_expiry` holds `expires_at ‖ id` for events with an expiration,
+//! so expired events are found without reading the others.
+//! `event_stats` holds the engagement counters of each event that has any,
+//! as JSON under its id.
+//!
+//! Counting walks the same index ranges as querying, but reads the events
+//! only for conditions the index does not cover.
+//!
+//! Every write updates the event and all of its index entries in a single
+//! transaction. Relay state other than events, such as the moderation lists
+//! and the circles, is kept as JSON in the `settings` table of the same
+//! database. Users are kept as JSON in `users` and their notification
+//! markers in `users_last_seen`, both keyed by x-only public key.
+
+use crate::circle::{CircleRepo, CircleRepoInMemory};
+use crate::moderation::{Moderation, ModerationRepo};
+use crate::repository::{sort_newest_first, EventRepo, RepoError, UserRepo, UserRepoInMemory};
+use crate::search;
+use entity::circle::Circle;
+use entity::reaction::{self, Engagement, Stats};
+use entity::{Count, Event, Filter, User};
+use redb::backends::InMemoryBackend;
+use redb::{
+    Database, ReadTransaction, ReadableDatabase, ReadableTable, ReadableTableMetadata,
+    TableDefinition, TableHandle, WriteTransaction,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::event;
+
+const EVENTS: TableDefinition<&str, &[u8]> = TableDefinition::new("events");
+const BY_CREATED: TableDefinition<&[u8], ()> = TableDefinition::new("events_by_created");
+const BY_AUTHOR: TableDefinition<&[u8], ()> = TableDefinition::new("events_by_author");
+const BY_KIND: TableDefinition<&[u8], ()> = TableDefinition::new("events_by_kind");
+const BY_TAG: TableDefinition<&[u8], ()> = TableDefinition::new("events_by_tag");
+const BY_ADDRESS: TableDefinition<&str, &str> = TableDefinition::new("events_by_address");
+const BY_TERM: TableDefinition<&[u8], u32> = TableDefinition::new("events_by_term");
+const BY_EXPIRY: TableDefinition<&[u8], ()> = TableDefinition::new("events_by_expiry");
+const STATS: TableDefinition<&str, &[u8]> = TableDefinition::new("event_stats");
+const SETTINGS: TableDefinition<&str, &[u8]> = TableDefinition::new("settings");
+const USERS: TableDefinition<&str, &[u8]> = TableDefinition::new("users");
+const LAST_SEEN: TableDefinition<&str, u64> = TableDefinition::new("users_last_seen");
+
+const MODERATION: &str = "moderation";
+const CIRCLES: &str = "circles";
+
+/// Distinct ids remembered while counting several filters; beyond this, the
+/// count is an upper bound marked approximate.
+const MAX_COUNTED_IDS: usize = 100_000;
+
+type Index = TableDefinition<'static, &'static [u8], ()>;
+
+impl From<redb::Error> for RepoError {
+    fn from(e: redb::Error) -> Self {
+        RepoError::Storage(e.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct EventRepoKv {
+    db: Arc<Database>,
+}
+
+impl EventRepoKv {
+    /// Opens the store at `path`, creating the file if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<EventRepoKv, RepoError> {
+        let db = Database::create(path).map_err(redb::Error::from)?;
+        EventRepoKv::init(db)
+    }
+    /// A store that lives in memory only, mainly useful for tests.
+    pub fn in_memory() -> Result<EventRepoKv, RepoError> {
+        let db = Database::builder()
+            .create_with_backend(InMemoryBackend::new())
+            .map_err(redb::Error::from)?;
+        EventRepoKv::init(db)
+    }
+    fn init(db: Database) -> Result<EventRepoKv, RepoError> {
+        let txn = db.begin_write().map_err(redb::Error::from)?;
+        let has_expiry = txn
+            .list_tables()
+            .map_err(redb::Error::from)?
+            .any(|t| t.name() == BY_EXPIRY.name());
+        {
+            txn.open_table(EVENTS).map_err(redb::Error::from)?;
+            txn.open_table(BY_ADDRESS).map_err(redb::Error::from)?;
+            txn.open_table(BY_TERM).map_err(redb::Error::from)?;
+            txn.open_table(STATS).map_err(redb::Error::from)?;
+            txn.open_table(SETTINGS).map_err(redb::Error::from)?;
+            txn.open_table(USERS).map_err(redb::Error::from)?;
+            txn.open_table(LAST_SEEN).map_err(redb::Error::from)?;
+            for table in [BY_CREATED, BY_AUTHOR, BY_KIND, BY_TAG, BY_EXPIRY] {
+                txn.open_table(table).map_err(redb::Error::from)?;
+            }
+        }
+        if !has_expiry {
+            index_expiry(&txn)?;
+        }
+        txn.commit().map_err(redb::Error::from)?;
+        Ok(EventRepoKv { db: Arc::new(db) })
+    }
+
+    /// The moderation lists stored in the same database.
+    pub fn moderation(&self) -> Result<ModerationRepoKv, RepoError> {
+        Ok(ModerationRepoKv {
+            db: self.db.clone(),
+            lists: read_setting(&self.db, MODERATION, "moderation lists")?,
+        })
+    }
+
+    /// The circles stored in the same database.
+    pub fn circles(&self) -> Result<CircleRepoKv, RepoError> {
+        let circles = read_setting(&self.db, CIRCLES, "circles")?;
+        Ok(CircleRepoKv {
+            db: self.db.clone(),
+            circles: CircleRepoInMemory::with(circles),
+        })
+    }
+
+    /// The users stored in the same database.
+    pub fn users(&self) -> Result<UserRepoKv, RepoError> {
+        let txn = self.db.begin_read().map_err(redb::Error::from)?;
+        let mut users = HashMap::new();
+        let table = txn.open_table(USERS).map_err(redb::Error::from)?;
+        for entry in table.iter().map_err(redb::Error::from)? {
+            let (key, value) = entry.map_err(redb::Error::from)?;
+            let user: User = serde_json::from_slice(value.value())
+                .map_err(|e| RepoError::Storage(format!("corrupt user: {}", e)))?;
+            users.insert(key.value().to_string(), user);
+        }
+        let mut last_seen = HashMap::new();
+        let table = txn.open_table(LAST_SEEN).map_err(redb::Error::from)?;
+        for entry in table.iter().map_err(redb::Error::from)? {
+            let (key, value) = entry.map_err(redb::Error::from)?;
+            last_seen.insert(key.value().to_string(), value.value());
+        }
+        Ok(UserRepoKv {
+            db: self.db.clone(),
+            users: UserRepoInMemory::with(users, last_seen),
+        })
+    }
+
+    fn write<T>(
+        &self,
+        f: impl FnOnce(&WriteTransaction) -> Result<T, redb::Error>,
+    ) -> Result<T, RepoError> {
+        let txn = self.db.begin_write().map_err(redb::Error::from)?;
+        let res = f(&txn)?;
+        txn.commit().map_err(redb::Error::from)?;
+        Ok(res)
+    }
+    fn try_read<T>(
+        &self,
+        f: impl FnOnce(&ReadTransaction) -> Result<T, redb::Error>,
+    ) -> Result<T, RepoError> {
+        let txn = self.db.begin_read().map_err(redb::Error::from)?;
+        Ok(f(&txn)?)
+    }
+    /// Runs the read `f` for trait methods that cannot report errors: a
+    /// failed `operation` is logged and yields nothing.
+    fn read_with<T: Default>(
+        &self,
+        operation: &str,
+        f: impl FnOnce(&ReadTransaction) -> Result<T, redb::Error>,
+    ) -> T {
+        logged(operation, self.try_read(f))
+    }
+
+    /// Full-text query: intersects the posting lists of `terms`, then ranks.
+    fn search(&self, filter: &Filter, terms: &[String]) -> Result<Vec<Event>, RepoError> {
+        let mut res: Vec<(f64, Event)> = self.try_read(|txn| {
+            let events = txn.open_table(EVENTS)?;
+            let index = txn.open_table(BY_TERM)?;
+            let mut postings = Vec::new();
+            for term in terms {
+                let lower = term_prefix(term);
+                let mut upper = lower.clone();
+                *upper.last_mut().expect("prefix ends in a separator") = 1;
+                let mut ids = HashMap::new();
+                for entry in index.range(lower.as_slice()..upper.as_slice())? {
+                    let (key, tf) = entry?;
+                    let id = std::str::from_utf8(&key.value()[lower.len()..])
+                        .map_err(|e| redb::Error::Corrupted(e.to_string()))?;
+                    ids.insert(id.to_string(), tf.value());
+                }
+                postings.push(ids);
+            }
+            let mut res = Vec::new();
+            for (id, score) in search::rank(&postings, events.len()?) {
+                let Some(guard) = events.get(id.as_str())? else {
+                    continue;
+                };
+                let e = decode(guard.value())?;
+                if !e.expired() && filter.matches(&e) {
+                    res.push((score, e));
+                }
+            }
+            Ok(res)
+        })?;
+        search::sort_by_rank(&mut res);
+        let limit = filter.limit.unwrap_or(usize::MAX);
+        Ok(res.into_iter().take(limit).map(|(_, e)| e).collect())
+    }
+}
+
+/// The value of a read that went through, or the default after logging why
+/// `operation` failed.
+fn logged<T: Default>(operation: &str, res: Result<T, RepoError>) -> T {
+    res.unwrap_or_else(|e| {
+        let msg = format!("event store {} failed: {}", operation, e);
+        event!(tracing::Level::ERROR, msg);
+        T::default()
+    })
+}
+
+fn index_keys(e: &Event) -> Vec<(Index, Vec<u8>)> {
+    let suffix = |mut key: Vec<u8>| {
+        key.extend_from_slice(&e.created_at.to_be_bytes());
+        key.extend_from_slice(e.id.as_bytes());
+        key
+    };
+    let mut keys = vec![
+        (BY_CREATED, suffix(Vec::new())),
+        (BY_AUTHOR, suffix(author_prefix(&e.public_key))),
+        (BY_KIND, suffix(kind_prefix(e.kind))),
+    ];
+    for tag in e.tags.iter().filter(|t| t.len() > 1) {
+        keys.push((BY_TAG, suffix(tag_prefix(&tag[0], &tag[1]))));
+    }
+    if let Some(key) = expiry_key(e) {
+        keys.push((BY_EXPIRY, key));
+    }
+    keys
+}
+
+fn expiry_key(e: &Event) -> Option<Vec<u8>> {
+    if e.expires_at == 0 {
+        return None;
+    }
+    let mut key = e.expires_at.to_be_bytes().to_vec();
+    key.extend_from_slice(e.id.as_bytes());
+    Some(key)
+}
+
+/// Fills `events_by_expiry` for a store written before it existed.
+fn index_expiry(txn: &WriteTransaction) -> Result<(), redb::Error> {
+    let events = txn.open_table(EVENTS)?;
+    let mut index = txn.open_table(BY_EXPIRY)?;
+    for entry in events.iter()? {
+        let (_, value) = entry?;
+        let e = decode(value.value())?;
+        if let Some(key) = expiry_key(&e) {
+            index.insert(key.as_slice(), ())?;
+        }
+    }
+    Ok(())
+}
+
+fn insert_indexes(txn: &WriteTransaction, e: &Event) -> Result<(), redb::Error> {
+    for (table, key) in index_keys(e) {
+        txn.open_table(table)?.insert(key.as_slice(), ())?;
+    }
+    let mut terms = txn.open_table(BY_TERM)?;
+    for (term, tf) in search::term_frequencies(&e.content) {
+        let mut key = term_prefix(&term);
+        key.extend_from_slice(e.id.as_bytes());
+        terms.insert(key.as_slice(), tf)?;
+    }
+    count_engagement(txn, e, Stats::add)
+}
+
+fn remove_indexes(txn: &WriteTransaction, e: &Event) -> Result<(), redb::Error> {
+    for (table, key) in index_keys(e) {
+        txn.open_table(table)?.remove(key.as_slice())?;
+    }
+    let mut terms = txn.open_table(BY_TERM)?;
+    for term in search::term_frequencies(&e.content).keys() {
+        let mut key = term_prefix(term);
+        key.extend_from_slice(e.id.as_bytes());
+        terms.remove(key.as_slice())?;
+    }
+    count_engagement(txn, e, Stats::remove)?;
+    if let Some(address) = e.address() {
+        let mut addresses = txn.open_table(BY_ADDRESS)?;
+        let current = addresses.get(address.as_str())?.map(|g| g.value() == e.id);
+        if current == Some(true) {
+            addresses.remove(address.as_str())?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies `update` to the counters of the event `e` engages with.
+fn count_engagement(
+    txn: &WriteTransaction,
+    e: &Event,
+    update: fn(&mut Stats, &Engagement),
+) -> Result<(), redb::Error> {
+    let Some((target, engagement)) = reaction::engagement(e) else {
+        return Ok(());
+    };
+    let mut table = txn.open_table(STATS)?;
+    let mut stats = match table.get(target.as_str())? {
+        Some(v) => decode_stats(v.value())?,
+        None => Stats::default(),
+    };
+    update(&mut stats, &engagement);
+    if stats.is_empty() {
+        table.remove(target.as_str())?;
+    } else {
+        let json = serde_json::to_vec(&stats).expect("stats serialize");
+        table.insert(target.as_str(), json.as_slice())?;
+    }
+    Ok(())
+}
+
+fn decode_stats(bytes: &[u8]) -> Result<Stats, redb::Error> {
+    serde_json::from_slice(bytes).map_err(|e| redb::Error::Corrupted(e.to_string()))
+}
+
+fn term_prefix(term: &str) -> Vec<u8> {
+    let mut key = term.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn author_prefix(author: &str) -> Vec<u8> {
+    let mut key = author.as_bytes().to_vec();
+    key.push(0);
+    key
+}
+
+fn kind_prefix(kind: u32) -> Vec<u8> {
+    kind.to_be_bytes().to_vec()
+}
+
+fn tag_prefix(name: &str, value: &str) -> Vec<u8> {
+    let mut key = name.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(value.as_bytes());
+    key.push(0);
+    key
+}
+
+/// Picks the most selective index for `filter` and returns its table and
+/// the key prefixes to scan.
+fn plan(filter: &Filter) -> (Index, Vec<Vec<u8>>) {
+    if let Some((name, values)) = filter.tag_conditions().next() {
+        let prefixes = values.iter().map(|v| tag_prefix(name, v)).collect();
+        return (BY_TAG, prefixes);
+    }
+    if let Some(authors) = &filter.authors {
+        return (
+            BY_AUTHOR,
+            authors.iter().map(|a| author_prefix(a)).collect(),
+        );
+    }
+    if let Some(kinds) = &filter.kinds {
+        return (BY_KIND, kinds.iter().map(|k| kind_prefix(*k)).collect());
+    }
+    (BY_CREATED, vec![Vec::new()])
+}
+
+/// Whether the index [`plan`] picks for `filter` decides the match on its
+/// own, together with the time range in its keys.
+fn covered(filter: &Filter) -> bool {
+    let conditions = filter.tag_conditions().count()
+        + usize::from(filter.authors.is_some())
+        + usize::from(filter.kinds.is_some());
+    filter.ids.is_none() && filter.search_terms().is_empty() && conditions <= 1
+}
+
+/// Counts ids, once each if they may repeat.
+struct Tally {
+    dedupe: bool,
+    seen: HashSet<String>,
+    count: Count,
+}
+
+impl Tally {
+    fn add(&mut self, id: &str) {
+        if self.dedupe && self.seen.contains(id) {
+            return;
+        }
+        if self.dedupe && self.seen.len() < MAX_COUNTED_IDS {
+            self.seen.insert(id.to_string());
+        } else if self.dedupe {
+            self.count.approximate = true;
+        }
+        self.count.count += 1;
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<Event, redb::Error> {
+    serde_json::from_slice(bytes).map_err(|e| redb::Error::Corrupted(e.to_string()))
+}
+
+impl EventRepo for EventRepoKv {
+    fn add(&mut self, e: Event) -> Result<(), RepoError> {
+        let bytes = serde_json::to_vec(&e).map_err(|e| RepoError::Storage(e.to_string()))?;
+        self.write(|txn| {
+            let mut events = txn.open_table(EVENTS)?;
+            if events.get(e.id.as_str())?.is_some() {
+                return Ok(Err(RepoError::Duplicate(e.id.clone())));
+            }
+            if let Some(address) = e.address() {
+                let current = txn
+                    .open_table(BY_ADDRESS)?
+                    .get(address.as_str())?
+                    .map(|g| g.value().to_string());
+                let old = match current {
+                    Some(id) => events.get(id.as_str())?.map(|g| decode(g.value())),
+                    None => None,
+                };
+                if let Some(old) = old.transpose()? {
+                    if !e.supersedes(&old) {
+                        return Ok(Err(RepoError::Outdated(e.id.clone())));
+                    }
+                    events.remove(old.id.as_str())?;
+                    remove_indexes(txn, &old)?;
+                }
+                txn.open_table(BY_ADDRESS)?
+                    .insert(address.as_str(), e.id.as_str())?;
+            }
+            events.insert(e.id.as_str(), bytes.as_slice())?;
+            insert_indexes(txn, &e)?;
+            Ok(Ok(()))
+        })?
+    }
+    fn read(&mut self, id: &str) -> Option<Event> {
+        let found = self.read_with("read", |txn| {
+            let events = txn.open_table(EVENTS)?;
+            match events.get(id)? {
+                Some(guard) => Ok(Some(decode(guard.value())?)),
+                None => Ok(None),
+            }
+        });
+        found.filter(|e| !e.expired())
+    }
+    fn read_all(&mut self) -> Vec<Event> {
+        self.read_with("read_all", |txn| {
+            let events = txn.open_table(EVENTS)?;
+            let mut res = Vec::new();
+            for entry in events.iter()? {
+                let (_, value) = entry?;
+                let e = decode(value.value())?;
+                if !e.expired() {
+                    res.push(e);
+                }
+            }
+            Ok(res)
+        })
+    }
+    fn query(&mut self, filter: &Filter) -> Vec<Event> {
+        logged("query", self.try_query(filter))
+    }
+    fn try_query(&mut self, filter: &Filter) -> Result<Vec<Event>, RepoError> {
+        let terms = filter.search_terms();
+        if !terms.is_empty() {
+            return self.search(filter, &terms);
+        }
+        let limit = filter.limit.unwrap_or(usize::MAX);
+        let found: HashMap<String, Event> = self.try_read(|txn| {
+            let events = txn.open_table(EVENTS)?;
+            let mut found = HashMap::new();
+            if let Some(ids) = &filter.ids {
+                for id in ids {
+                    if let Some(guard) = events.get(id.as_str())? {
+                        let e = decode(guard.value())?;
+                        found.insert(e.id.clone(), e);
+                    }
+                }
+                return Ok(found);
+            }
+            let (table, prefixes) = plan(filter);
+            let index = txn.open_table(table)?;
+            let since = filter.since.unwrap_or(0);
+            let until = filter.until.unwrap_or(u64::MAX);
+            for prefix in prefixes {
+                let mut lower = prefix.clone();
+                lower.extend_from_slice(&since.to_be_bytes());
+                let mut upper = prefix.clone();
+                upper.extend_from_slice(&until.to_be_bytes());
+                upper.push(u8::MAX);
+                let mut taken = 0;
+                for entry in index.range(lower.as_slice()..upper.as_slice())?.rev() {
+                    let (key, _) = entry?;
+                    // the id is borrowed straight out of the index key
+                    let id = std::str::from_utf8(&key.value()[prefix.len() + 8..])
+                        .map_err(|e| redb::Error::Corrupted(e.to_string()))?;
+                    if found.contains_key(id) {
+                        continue;
+                    }
+                    let Some(guard) = events.get(id)? else {
+                        continue;
+                    };
+                    let e = decode(guard.value())?;
+                    if e.expired() || !filter.matches(&e) {
+                        continue;
+                    }
+                    found.insert(e.id.clone(), e);
+                    taken += 1;
+                    if taken >= limit {
+                        break;
+                    }
+                }
+            }
+            Ok(found)
+        })?;
+        let mut res: Vec<Event> = found
+            .into_values()
+            .filter(|e| !e.expired() && filter.matches(e))
+            .collect();
+        sort_newest_first(&mut res);
+        res.truncate(limit);
+        Ok(res)
+    }
+    fn count(&mut self, filters: &[Filter]) -> Count {
+        // an event matching several filters or tag values is found repeatedly
+        let dedupe = filters.len() > 1
+            || filters
+                .iter()
+                .any(|f| f.tag_conditions().next().is_some_and(|(_, v)| v.len() > 1));
+        let mut tally = Tally {
+            dedupe,
+            seen: HashSet::new(),
+            count: Count::default(),
+        };
+        let (searches, filters): (Vec<&Filter>, Vec<&Filter>) =
+            filters.iter().partition(|f| !f.search_terms().is_empty());
+        self.read_with("count", |txn| {
+            let events = txn.open_table(EVENTS)?;
+            for filter in filters {
+                if let Some(ids) = &filter.ids {
+                    let ids: HashSet<&String> = ids.iter().collect();
+                    for id in ids {
+                        if let Some(guard) = events.get(id.as_str())? {
+                            let e = decode(guard.value())?;
+                            if !e.expired() && filter.matches(&e) {
+                                tally.add(id);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                let (table, prefixes) = plan(filter);
+                let index = txn.open_table(table)?;
+                let covered = covered(filter);
+                // every event is in the creation index exactly once
+                if !dedupe
+                    && *filter
+                        == (Filter {
+                            limit: filter.limit,
+                            ..Filter::new()
+                        })
+                {
+                    tally.count.count += index.len()?;
+                    continue;
+                }
+                let since = filter.since.unwrap_or(0);
+                let until = filter.until.unwrap_or(u64::MAX);
+                for prefix in prefixes {
+                    let mut lower = prefix.clone();
+                    lower.extend_from_slice(&since.to_be_bytes());
+                    let mut upper = prefix.clone();
+                    upper.extend_from_slice(&until.to_be_bytes());
+                    upper.push(u8::MAX);
+                    for entry in index.range(lower.as_slice()..upper.as_slice())? {
+                        let (key, _) = entry?;
+                        let id = std::str::from_utf8(&key.value()[prefix.len() + 8..])
+                            .map_err(|e| redb::Error::Corrupted(e.to_string()))?;
+                        if covered {
+                            tally.add(id);
+                            continue;
+                        }
+                        let Some(guard) = events.get(id)? else {
+                            continue;
+                        };
+                        let e = decode(guard.value())?;
+                        if !e.expired() && filter.matches(&e) {
+                            tally.add(id);
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+        for filter in searches {
+            let unlimited = Filter {
+                limit: None,
+                ..filter.clone()
+            };
+            for e in logged("count", self.search(&unlimited, &unlimited.search_terms())) {
+                tally.add(&e.id);
+            }
+        }
+        tally.count
+    }
+    fn tag_values(&mut self, name: &str) -> Vec<String> {
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(0);
+        let mut upper = prefix.clone();
+        *upper.last_mut().expect("prefix ends in a separator") = 1;
+        self.read_with("tag_values", |txn| {
+            let index = txn.open_table(BY_TAG)?;
+            let mut values = Vec::new();
+            let mut lower = prefix.clone();
+            // one lookup per value: skip past all entries of the value found
+            while let Some(entry) = index.range(lower.as_slice()..upper.as_slice())?.next() {
+                let (key, _) = entry?;
+                let rest = &key.value()[prefix.len()..];
+                let end = rest
+                    .iter()
+                    .position(|b| *b == 0)
+                    .ok_or_else(|| redb::Error::Corrupted("tag key without value".to_string()))?;
+                let value = std::str::from_utf8(&rest[..end])
+                    .map_err(|e| redb::Error::Corrupted(e.to_string()))?;
+                lower = tag_prefix(name, value);
+                *lower.last_mut().expect("prefix ends in a separator") = 1;
+                values.push(value.to_string());
+            }
+            Ok(values)
+        })
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        self.write(|txn| remove_event(txn, id))
+    }
+    fn delete_expired(&mut self) -> Result<Vec<String>, RepoError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.write(|txn| {
+            // an event expires at `expires_at`, so every key below `now + 1`
+            let upper = (now + 1).to_be_bytes();
+            let mut expired = Vec::new();
+            for entry in txn.open_table(BY_EXPIRY)?.range(..upper.as_slice())? {
+                let (key, _) = entry?;
+                let id = std::str::from_utf8(&key.value()[8..])
+                    .map_err(|e| redb::Error::Corrupted(e.to_string()))?;
+                expired.push(id.to_string());
+            }
+            for id in &expired {
+                remove_event(txn, id)?;
+            }
+            Ok(expired)
+        })
+    }
+    fn stats(&mut self, id: &str) -> Stats {
+        self.read_with("stats", |txn| match txn.open_table(STATS)?.get(id)? {
+            Some(v) => decode_stats(v.value()),
+            None => Ok(Stats::default()),
+        })
+    }
+}
+
+fn remove_event(txn: &WriteTransaction, id: &str) -> Result<(), redb::Error> {
+    let mut events = txn.open_table(EVENTS)?;
+    let Some(old) = events.remove(id)? else {
+        return Ok(());
+    };
+    let e = decode(old.value())?;
+    drop(old);
+    remove_indexes(txn, &e)
+}
+
+/// Moderation lists of an [`EventRepoKv`], cached in memory.
+pub struct ModerationRepoKv {
+    db: Arc<Database>,
+    lists: Moderation,
+}
+
+impl ModerationRepo for ModerationRepoKv {
+    fn lists(&self) -> &Moderation {
+        &self.lists
+    }
+    fn save(&mut self, lists: Moderation) -> Result<(), RepoError> {
+        write_setting(&self.db, MODERATION, &lists)?;
+        self.lists = lists;
+        Ok(())
+    }
+}
+
+/// Circles of an [`EventRepoKv`], cached in memory.
+pub struct CircleRepoKv {
+    db: Arc<Database>,
+    circles: CircleRepoInMemory,
+}
+
+impl CircleRepoKv {
+    /// Applies `change` to a copy of the circles and stores the result.
+    fn update(
+        &mut self,
+        change: impl FnOnce(&mut CircleRepoInMemory) -> Result<(), RepoError>,
+    ) -> Result<(), RepoError> {
+        let mut circles = self.circles.clone();
+        change(&mut circles)?;
+        write_setting(&self.db, CIRCLES, circles.circles())?;
+        self.circles = circles;
+        Ok(())
+    }
+}
+
+impl CircleRepo for CircleRepoKv {
+    fn read(&self, id: &str) -> Option<&Circle> {
+        self.circles.read(id)
+    }
+    fn read_for(&self, public_key: &str) -> Vec<Circle> {
+        self.circles.read_for(public_key)
+    }
+    fn save(&mut self, circle: Circle) -> Result<(), RepoError> {
+        self.update(|circles| circles.save(circle))
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        self.update(|circles| circles.delete(id))
+    }
+}
+
+/// Users of an [`EventRepoKv`], cached in memory. Writes that fail are
+/// logged and only kept in the cache.
+#[derive(Clone)]
+pub struct UserRepoKv {
+    db: Arc<Database>,
+    users: UserRepoInMemory,
+}
+
+impl UserRepoKv {
+    fn store(&self, what: &str, f: impl FnOnce(&WriteTransaction) -> Result<(), redb::Error>) {
+        let res = self
+            .db
+            .begin_write()
+            .map_err(redb::Error::from)
+            .and_then(|txn| {
+                f(&txn)?;
+                txn.commit().map_err(redb::Error::from)
+            });
+        if let Err(e) = res {
+            let msg = format!("could not store {}: {}", what, e);
+            event!(tracing::Level::ERROR, msg);
+        }
+    }
+}
+
+impl UserRepo for UserRepoKv {
+    fn add_user(&mut self, u: User) {
+        let key = crypto::x_only(&u.public_key).to_string();
+        let json = serde_json::to_vec(&u).expect("users serialize");
+        self.store(&format!("user {}", key), |txn| {
+            txn.open_table(USERS)?
+                .insert(key.as_str(), json.as_slice())?;
+            Ok(())
+        });
+        self.users.add_user(u);
+    }
+    fn read_user(&self, public_key: &str) -> Option<&User> {
+        self.users.read_user(public_key)
+    }
+    fn find_user_by_name(&self, name: &str) -> Option<&User> {
+        self.users.find_user_by_name(name)
+    }
+    fn read_all_users(&self) -> Vec<User> {
+        self.users.read_all_users()
+    }
+    fn last_seen(&self, public_key: &str) -> u64 {
+        self.users.last_seen(public_key)
+    }
+    fn mark_seen(&mut self, public_key: &str, at: u64) {
+        self.users.mark_seen(public_key, at);
+        let key = crypto::x_only(public_key);
+        let at = self.users.last_seen(key);
+        self.store(&format!("marker of {}", key), |txn| {
+            txn.open_table(LAST_SEEN)?.insert(key, at)?;
+            Ok(())
+        });
+    }
+}
+
+/// The JSON value under `key` in the settings table, or the default.
+fn read_setting<T: DeserializeOwned + Default>(
+    db: &Database,
+    key: &str,
+    what: &str,
+) -> Result<T, RepoError> {
+    let txn = db.begin_read().map_err(redb::Error::from)?;
+    let table = txn.open_table(SETTINGS).map_err(redb::Error::from)?;
+    match table.get(key).map_err(redb::Error::from)? {
+        Some(v) => serde_json::from_slice(v.value())
+            .map_err(|e| RepoError::Storage(format!("corrupt {}: {}", what, e))),
+        None => Ok(T::default()),
+    }
+}
+
+fn write_setting<T: Serialize>(db: &Database, key: &str, value: &T) -> Result<(), RepoError> {
+    let json = serde_json::to_vec(value).expect("settings serialize");
+    let txn = db.begin_write().map_err(redb::Error::from)?;
+    {
+        let mut table = txn.open_table(SETTINGS).map_err(redb::Error::from)?;
+        table
+            .insert(key, json.as_slice())
+            .map_err(redb::Error::from)?;
+    }
+    txn.commit().map_err(redb::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, author: &str, kind: u32, created_at: u64) -> Event {
+        let mut e = Event::new(author.to_string(), format!("content {}", id), 0);
+        e.id = id.to_string();
+        e.kind = kind;
+        e.created_at = created_at;
+        e
+    }
+
+    #[test]
+    fn test_conformance() {
+        crate::conformance::event_repo(|| EventRepoKv::in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_moderation_conformance() {
+        crate::conformance::moderation_repo(|| {
+            EventRepoKv::in_memory().unwrap().moderation().unwrap()
+        });
+        let events = EventRepoKv::in_memory().unwrap();
+        let mut lists = Moderation::default();
+        lists.bans.kinds.insert(4);
+        events.moderation().unwrap().save(lists.clone()).unwrap();
+        assert_eq!(events.moderation().unwrap().lists(), &lists);
+    }
+
+    #[test]
+    fn test_circle_conformance() {
+        crate::conformance::circle_repo(|| EventRepoKv::in_memory().unwrap().circles().unwrap());
+        let events = EventRepoKv::in_memory().unwrap();
+        let circle = Circle::new(&"a".repeat(64), "team").unwrap();
+        events.circles().unwrap().save(circle.clone()).unwrap();
+        assert_eq!(events.circles().unwrap().read(&circle.id()), Some(&circle));
+    }
+
+    #[test]
+    fn test_user_conformance() {
+        crate::conformance::user_repo(|| EventRepoKv::in_memory().unwrap().users().unwrap());
+        let events = EventRepoKv::in_memory().unwrap();
+        let mut users = events.users().unwrap();
+        let mut user = User::new("alice".to_string(), format!("02{}", "a".repeat(64)));
+        user.role = entity::Role::Bot;
+        users.add_user(user);
+        users.mark_seen(&"a".repeat(64), 20);
+        let users = events.users().unwrap();
+        let alice = users.read_user(&"a".repeat(64)).unwrap();
+        assert_eq!(
+            (alice.name.as_str(), alice.role),
+            ("alice", entity::Role::Bot)
+        );
+        assert_eq!(users.last_seen(&format!("03{}", "a".repeat(64))), 20);
+    }
+
+    #[test]
+    fn test_query_uses_indexes() {
+        let mut repo = EventRepoKv::in_memory().unwrap();
+        repo.add(event("a", "alice", 1, 10)).unwrap();
+        repo.add(event("b", "bob", 1, 20)).unwrap();
+        let mut tagged = event("c", "alice", 7, 30);
+        tagged.tags = vec![vec!["e".to_string(), "a".to_string()]];
+        repo.add(tagged).unwrap();
+
+        let ids = |events: Vec<Event>| events.into_iter().map(|e| e.id).collect::<Vec<_>>();
+        let by_author = Filter {
+            authors: Some(vec!["alice".to_string()]),
+            ..Filter::default()
+        };
+        assert_eq!(ids(repo.query(&by_author)), vec!["c", "a"]);
+        let by_kind = Filter {
+            kinds: Some(vec![1]),
+            since: Some(15),
+            ..Filter::default()
+        };
+        assert_eq!(ids(repo.query(&by_kind)), vec!["b"]);
+        let by_tag = Filter::new().with_tag("e", vec!["a".to_string()]);
+        assert_eq!(ids(repo.query(&by_tag)), vec!["c"]);
+        let limited = Filter {
+            limit: Some(2),
+            ..Filter::default()
+        };
+        assert_eq!(ids(repo.query(&limited)), vec!["c", "b"]);
+    }
+
+    #[test]
+    fn test_delete_expired_uses_index() {
+        let mut repo = EventRepoKv::in_memory().unwrap();
+        let mut expired = event("a", "alice", 1, 10);
+        expired.expires_at = 1;
+        repo.add(expired).unwrap();
+        let mut pending = event("b", "alice", 1, 20);
+        pending.expires_at = u64::MAX;
+        repo.add(pending).unwrap();
+        repo.add(event("c", "alice", 1, 30)).unwrap();
+
+        let index_len = |repo: &EventRepoKv| {
+            let txn = repo.db.begin_read().unwrap();
+            txn.open_table(BY_EXPIRY).unwrap().len().unwrap()
+        };
+        assert_eq!(index_len(&repo), 2);
+        assert_eq!(repo.delete_expired().unwrap(), vec!["a".to_string()]);
+        assert_eq!(index_len(&repo), 1);
+        repo.delete("b").unwrap();
+        assert_eq!(index_len(&repo), 0);
+        assert_eq!(repo.read_all().len(), 1);
+    }
+}
@@ -1,16 +1,11 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
-
+pub mod access;
+pub mod archive;
+pub mod circle;
+pub mod conformance;
+pub mod feed;
+pub mod kv;
+pub mod moderation;
 pub mod repository;
+pub mod retention;
+pub mod search;
+pub mod thread;
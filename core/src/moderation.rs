@@ -0,0 +1,286 @@
+//! Who may use the relay and what it stores.
+//!
+//! Banned public keys, IP ranges, event ids and kinds are always refused.
+//! In [`Mode::Allowlist`] writes are additionally limited to the allowed
+//! public keys and IP ranges; reads stay open to everyone not banned.
+
+use crate::repository::RepoError;
+use entity::Event;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Everyone not banned may write.
+    #[default]
+    Denylist,
+    /// Only allowed public keys from allowed IP ranges may write.
+    Allowlist,
+}
+
+/// An address or a CIDR block such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let (bytes, bits) = ((prefix / 8) as usize, prefix % 8);
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    bits == 0 || (a[bytes] ^ b[bytes]) >> (8 - bits) == 0
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+    fn from_str(s: &str) -> Result<IpRange, String> {
+        let invalid = || format!("invalid IP range {}", s);
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(IpRange { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for IpRange {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpRange {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<IpRange, D::Error> {
+        String::deserialize(d)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Public keys and IP ranges, as used by both the ban and the allow list.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Clients {
+    /// x-only public keys; keys are read in either form.
+    #[serde(deserialize_with = "x_only_keys")]
+    pub pubkeys: BTreeSet<String>,
+    pub ips: BTreeSet<IpRange>,
+}
+
+fn x_only_keys<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeSet<String>, D::Error> {
+    let keys = BTreeSet::<String>::deserialize(d)?;
+    Ok(keys
+        .iter()
+        .map(|pk| crypto::x_only(pk).to_string())
+        .collect())
+}
+
+impl Clients {
+    /// Whether `pubkey`, in either key form, or `ip` is listed.
+    pub fn contains(&self, pubkey: Option<&str>, ip: Option<IpAddr>) -> bool {
+        pubkey.is_some_and(|pk| self.pubkeys.contains(crypto::x_only(pk)))
+            || ip.is_some_and(|ip| self.ips.iter().any(|r| r.contains(ip)))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Bans {
+    #[serde(flatten)]
+    pub clients: Clients,
+    pub events: BTreeSet<String>,
+    pub kinds: BTreeSet<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Allow {
+    pub mode: Mode,
+    #[serde(flatten)]
+    pub clients: Clients,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Moderation {
+    pub bans: Bans,
+    pub allow: Allow,
+}
+
+impl Moderation {
+    /// Whether a client may use the relay at all, or write if `write`.
+    pub fn check_client(
+        &self,
+        pubkey: Option<&str>,
+        ip: Option<IpAddr>,
+        write: bool,
+    ) -> Result<(), String> {
+        if let Some(pk) = pubkey.filter(|pk| self.bans.clients.contains(Some(pk), None)) {
+            return Err(format!("public key {} is banned", pk));
+        }
+        if let Some(ip) = ip.filter(|ip| self.bans.clients.contains(None, Some(*ip))) {
+            return Err(format!("address {} is banned", ip));
+        }
+        if write && self.allow.mode == Mode::Allowlist {
+            let clients = &self.allow.clients;
+            if !pubkey.is_some_and(|pk| clients.contains(Some(pk), None)) {
+                return Err("public key is not on the allow list".to_string());
+            }
+            if ip.is_some_and(|ip| !clients.ips.is_empty() && !clients.contains(None, Some(ip))) {
+                return Err("address is not on the allow list".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `e` may be stored, judged by its id, kind and author.
+    pub fn check_event(&self, e: &Event) -> Result<(), String> {
+        if self.bans.events.contains(&e.id) {
+            return Err(format!("event {} is banned", e.id));
+        }
+        if self.bans.kinds.contains(&e.kind) {
+            return Err(format!("kind {} is banned", e.kind));
+        }
+        self.check_client(Some(&e.public_key), None, true)
+    }
+}
+
+/// Keeps the moderation lists across restarts.
+pub trait ModerationRepo {
+    fn lists(&self) -> &Moderation;
+    /// Replaces the stored lists.
+    fn save(&mut self, lists: Moderation) -> Result<(), RepoError>;
+}
+
+#[derive(Clone, Default)]
+pub struct ModerationRepoInMemory {
+    lists: Moderation,
+}
+
+impl ModerationRepoInMemory {
+    pub fn new() -> ModerationRepoInMemory {
+        ModerationRepoInMemory::default()
+    }
+}
+
+impl ModerationRepo for ModerationRepoInMemory {
+    fn lists(&self) -> &Moderation {
+        &self.lists
+    }
+    fn save(&mut self, lists: Moderation) -> Result<(), RepoError> {
+        self.lists = lists;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::event;
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.1.0.0/15".parse().unwrap();
+        assert!(range.contains("10.0.255.1".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert_eq!(single.to_string(), "2001:db8::1/128");
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_checks() {
+        let mut lists = Moderation::default();
+        lists.bans.clients.pubkeys.insert("mallory".to_string());
+        lists
+            .bans
+            .clients
+            .ips
+            .insert("192.168.0.0/16".parse().unwrap());
+        lists.bans.kinds.insert(4);
+        let ip = "192.168.1.1".parse().ok();
+        assert!(lists.check_client(Some("alice"), None, true).is_ok());
+        assert!(lists.check_client(Some("mallory"), None, false).is_err());
+        assert!(lists.check_client(None, ip, false).is_err());
+        assert!(lists.check_event(&event("a", "alice", 4, 0)).is_err());
+
+        lists.allow.mode = Mode::Allowlist;
+        lists.allow.clients.pubkeys.insert("alice".to_string());
+        assert!(lists.check_client(Some("bob"), None, false).is_ok());
+        assert!(lists.check_client(Some("bob"), None, true).is_err());
+        assert!(lists.check_event(&event("a", "alice", 1, 0)).is_ok());
+        lists
+            .allow
+            .clients
+            .ips
+            .insert("10.0.0.0/8".parse().unwrap());
+        assert!(lists
+            .check_client(Some("alice"), "172.16.0.1".parse().ok(), true)
+            .is_err());
+        assert!(lists
+            .check_client(Some("alice"), "10.0.0.1".parse().ok(), true)
+            .is_ok());
+
+        // a banned key may not post in another form of the same key
+        let x = "b".repeat(64);
+        let mut bans: Bans = serde_json::from_value(serde_json::json!({
+            "pubkeys": [format!("03{}", x)]
+        }))
+        .unwrap();
+        assert_eq!(bans.clients.pubkeys, BTreeSet::from([x.clone()]));
+        lists.allow.mode = Mode::Denylist;
+        lists.bans.clients.pubkeys.append(&mut bans.clients.pubkeys);
+        for pk in [x.clone(), format!("02{}", x), format!("03{}", x)] {
+            assert!(lists.check_client(Some(&pk), None, false).is_err());
+            assert!(lists.check_event(&event("b", &pk, 1, 0)).is_err());
+        }
+        lists.allow.mode = Mode::Allowlist;
+        let y = "a".repeat(64);
+        lists.allow.clients.pubkeys.insert(y.clone());
+        let ip = "10.0.0.1".parse().ok();
+        assert!(lists
+            .check_client(Some(&format!("02{}", y)), ip, true)
+            .is_ok());
+
+        let json = serde_json::to_string(&lists).unwrap();
+        assert!(json.contains("\"192.168.0.0/16\""));
+        assert_eq!(serde_json::from_str::<Moderation>(&json).unwrap(), lists);
+    }
+}
@@ -1,110 +1,370 @@
+use crate::search::{self, SearchIndex};
 use dyn_clone::DynClone;
-use entity::{Event,User,Session};
-use std::collections::HashMap;
+use entity::reaction::{self, Stats};
+use entity::{Count, Event, Filter, Session, User};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
 use tracing::event;
 dyn_clone::clone_trait_object!(UserRepo);
+
+#[derive(Debug)]
+pub enum RepoError {
+    Storage(String),
+    /// An event with this id is already stored.
+    Duplicate(String),
+    /// A newer event already occupies the replaceable slot of this event.
+    Outdated(String),
+}
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::Storage(msg) => write!(f, "storage error: {}", msg),
+            RepoError::Duplicate(id) => write!(f, "event {} already exists", id),
+            RepoError::Outdated(id) => write!(f, "event {} is superseded by a newer one", id),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {}
+
 pub trait EventRepo {
-    fn add(&mut self, e: Event);
-    fn read(&mut self, id: &String) -> Option<Event>;
+    /// Stores `e`. Ids are unique, and for replaceable and addressable kinds
+    /// only the event that supersedes all others with its address is kept.
+    fn add(&mut self, e: Event) -> Result<(), RepoError>;
+    /// The event with `id`, unless it does not exist or has expired. Reads
+    /// skip expired events but leave them stored until
+    /// [`EventRepo::delete_expired`], which reports their removal.
+    fn read(&mut self, id: &str) -> Option<Event>;
     fn read_all(&mut self) -> Vec<Event>;
-    fn delete(&mut self,id:&String);
+    /// Events matching `filter`, newest first, at most `filter.limit` of them.
+    fn query(&mut self, filter: &Filter) -> Vec<Event>;
+    /// Like [`EventRepo::query`], but a backend that fails to read says so
+    /// rather than finding nothing.
+    fn try_query(&mut self, filter: &Filter) -> Result<Vec<Event>, RepoError> {
+        Ok(self.query(filter))
+    }
+    /// How many distinct events match any of `filters`, ignoring their
+    /// limits. Backends counting from their indexes may include events that
+    /// expired since the last [`EventRepo::delete_expired`], and may return
+    /// an approximate count for very large sets.
+    fn count(&mut self, filters: &[Filter]) -> Count;
+    /// The distinct values of tag `name` among the stored events, in
+    /// ascending order. Like [`EventRepo::count`], backends reading their
+    /// indexes may include values of events that expired.
+    fn tag_values(&mut self, name: &str) -> Vec<String>;
+    fn delete(&mut self, id: &str) -> Result<(), RepoError>;
+    /// Removes all expired events and returns their ids.
+    fn delete_expired(&mut self) -> Result<Vec<String>, RepoError>;
+    /// Reactions, reposts and replies counted for event `id` among the
+    /// stored events. Counters are updated as events are added and removed.
+    fn stats(&mut self, id: &str) -> Stats;
+}
+
+/// Orders events newest first; ties are broken by descending id so that
+/// every backend returns the same sequence.
+pub fn sort_newest_first(events: &mut [Event]) {
+    events.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| b.id.cmp(&a.id))
+    });
+}
+
+/// A cursor over the events matching a filter, newest first, a page of
+/// queries at a time, so that walking a large store never holds all of it.
+/// A search filter is ranked rather than ordered and comes as one page.
+pub struct Pages {
+    window: Filter,
+    /// Events of the oldest second returned, which the next page starts with.
+    seen: HashSet<String>,
+    /// Events still to return under the filter's own limit.
+    remaining: usize,
+    done: bool,
+}
+
+impl Pages {
+    pub fn new(filter: &Filter, page: usize) -> Pages {
+        let remaining = filter.limit.unwrap_or(usize::MAX);
+        let window = match filter.search {
+            Some(_) => filter.clone(),
+            None => Filter {
+                limit: Some(page.max(1)),
+                ..filter.clone()
+            },
+        };
+        Pages {
+            window,
+            seen: HashSet::new(),
+            remaining,
+            done: false,
+        }
+    }
+
+    /// The next page of events from `repo`, or `None` once all were returned.
+    pub fn next(&mut self, repo: &mut dyn EventRepo) -> Option<Vec<Event>> {
+        while !self.done && self.remaining > 0 {
+            let events = repo.query(&self.window);
+            if self.window.search.is_some() {
+                self.done = true;
+                return Some(events).filter(|events| !events.is_empty());
+            }
+            let full = events.len() == self.window.limit.unwrap_or(usize::MAX);
+            let Some(oldest) = events.last().map(|e| e.created_at) else {
+                self.done = true;
+                return None;
+            };
+            let fresh: Vec<Event> = events
+                .iter()
+                .filter(|e| !self.seen.contains(&e.id))
+                .take(self.remaining)
+                .cloned()
+                .collect();
+            if !full {
+                self.done = true;
+            } else if !fresh.is_empty() {
+                self.seen = events
+                    .iter()
+                    .filter(|e| e.created_at == oldest)
+                    .map(|e| e.id.clone())
+                    .collect();
+                self.window.until = Some(oldest);
+            } else {
+                // a single second holds more than a page
+                self.window.limit = self.window.limit.map(|l| l * 2);
+            }
+            if !fresh.is_empty() {
+                self.remaining -= fresh.len();
+                return Some(fresh);
+            }
+        }
+        None
+    }
 }
+
 #[derive(Clone)]
 pub struct EventRepoInMemory {
     events: HashMap<String, Event>,
+    // address of replaceable and addressable events -> id
+    addresses: HashMap<String, String>,
+    search: SearchIndex,
+    stats: HashMap<String, Stats>,
 }
 
 impl EventRepo for EventRepoInMemory {
-    fn add(&mut self, u: Event) {
+    fn add(&mut self, u: Event) -> Result<(), RepoError> {
         let n = u.get_id();
+        if self.events.contains_key(&n) {
+            return Err(RepoError::Duplicate(n));
+        }
+        if let Some(address) = u.address() {
+            if let Some(current) = self.addresses.get(&address).cloned() {
+                match self.events.get(&current) {
+                    Some(old) if !u.supersedes(old) => return Err(RepoError::Outdated(n)),
+                    _ => self.delete(&current)?,
+                }
+            }
+            self.addresses.insert(address, n.clone());
+        }
+        self.search.insert(&u);
+        if let Some((target, engagement)) = reaction::engagement(&u) {
+            self.stats.entry(target).or_default().add(&engagement);
+        }
         self.events.insert(n, u);
+        Ok(())
     }
-    fn read(&mut self, id: &String) -> Option<Event> {
-        match self.events.get(id) {
-            Some(e) => {
-                let oe = e.to_owned();
-                if oe.expired(){
-                    let msg = format!("deleting {}",oe.id);
-                    event!(tracing::Level::INFO,msg);
-                    self.delete(&oe.id);
+    fn read(&mut self, id: &str) -> Option<Event> {
+        self.events.get(id).filter(|e| !e.expired()).cloned()
+    }
+    fn read_all(&mut self) -> Vec<Event> {
+        self.events
+            .values()
+            .filter(|e| !e.expired())
+            .cloned()
+            .collect()
+    }
+    fn query(&mut self, filter: &Filter) -> Vec<Event> {
+        let terms = filter.search_terms();
+        if !terms.is_empty() {
+            let ranked = self.search.search(&terms, self.events.len() as u64);
+            let mut res = Vec::new();
+            for (id, score) in ranked {
+                match self.read(&id) {
+                    Some(e) if filter.matches(&e) => res.push((score, e)),
+                    _ => {}
                 }
-                return Some(oe)
-            },
-            None => return None,
-
+            }
+            search::sort_by_rank(&mut res);
+            let limit = filter.limit.unwrap_or(usize::MAX);
+            return res.into_iter().take(limit).map(|(_, e)| e).collect();
+        }
+        let mut res: Vec<Event> = self
+            .read_all()
+            .into_iter()
+            .filter(|e| filter.matches(e))
+            .collect();
+        sort_newest_first(&mut res);
+        if let Some(limit) = filter.limit {
+            res.truncate(limit);
         }
+        res
     }
-    fn read_all(&mut self) -> Vec<Event> {
-        let mut res: Vec<Event> = Vec::new();
-        let mut obsoletes: Vec<String> = Vec::new();
-        for (_, v) in &self.events {
-            if !v.expired(){
-                res.push(v.clone());
-            } else {
-                obsoletes.push(v.id.clone());
+    fn count(&mut self, filters: &[Filter]) -> Count {
+        let count = self
+            .events
+            .values()
+            .filter(|e| !e.expired() && filters.iter().any(|f| f.matches(e)))
+            .count();
+        Count {
+            count: count as u64,
+            approximate: false,
+        }
+    }
+    fn tag_values(&mut self, name: &str) -> Vec<String> {
+        let values: BTreeSet<&str> = self
+            .events
+            .values()
+            .filter(|e| !e.expired())
+            .flat_map(|e| e.tag_values(name))
+            .collect();
+        values.into_iter().map(|v| v.to_string()).collect()
+    }
+    fn delete(&mut self, id: &str) -> Result<(), RepoError> {
+        let Some(e) = self.events.remove(id) else {
+            return Ok(());
+        };
+        self.search.remove(&e);
+        if let Some((target, engagement)) = reaction::engagement(&e) {
+            if let Some(stats) = self.stats.get_mut(&target) {
+                stats.remove(&engagement);
+                if stats.is_empty() {
+                    self.stats.remove(&target);
+                }
             }
         }
-        for  v in obsoletes {
-            let msg = format!("deleting {}",v);
-            event!(tracing::Level::INFO,msg);
-            self.delete(&v);
+        if let Some(address) = e.address() {
+            if self
+                .addresses
+                .get(&address)
+                .is_some_and(|current| current == id)
+            {
+                self.addresses.remove(&address);
+            }
+        }
+        Ok(())
+    }
+    fn delete_expired(&mut self) -> Result<Vec<String>, RepoError> {
+        let expired: Vec<String> = self
+            .events
+            .values()
+            .filter(|e| e.expired())
+            .map(|e| e.get_id())
+            .collect();
+        for id in &expired {
+            self.delete(id)?;
         }
-        return res;
+        Ok(expired)
+    }
+    fn stats(&mut self, id: &str) -> Stats {
+        self.stats.get(id).cloned().unwrap_or_default()
     }
-    fn delete(&mut self,id: &String) {
-        self.events.remove(id);
+}
+impl Default for EventRepoInMemory {
+    fn default() -> Self {
+        Self::new()
     }
 }
+
 impl EventRepoInMemory {
     pub fn new() -> EventRepoInMemory {
-        return EventRepoInMemory {
+        EventRepoInMemory {
             events: HashMap::new(),
-        };
+            addresses: HashMap::new(),
+            search: SearchIndex::new(),
+            stats: HashMap::new(),
+        }
     }
 }
+/// Users are keyed by the x-only form of their public key, so either form
+/// of a key finds the same entry and marker.
 pub trait UserRepo: DynClone {
     fn add_user(&mut self, u: User);
-    fn read_user(&self, public_key: &String) -> Option<&User>;
+    fn read_user(&self, public_key: &str) -> Option<&User>;
+    /// The user registered under `name`, ignoring case.
+    fn find_user_by_name(&self, name: &str) -> Option<&User>;
     fn read_all_users(&self) -> Vec<User>;
+    /// When `public_key` last read their notifications, 0 if never. Kept
+    /// apart from the user entry, so it works for unregistered keys too.
+    fn last_seen(&self, public_key: &str) -> u64;
+    /// Moves the marker forward to `at`; it never moves back.
+    fn mark_seen(&mut self, public_key: &str, at: u64);
     //fn Clone(&self) -> dyn UserRepo;
 }
 #[derive(Clone)]
 pub struct UserRepoInMemory {
     users: HashMap<String, User>,
+    last_seen: HashMap<String, u64>,
 }
 
 impl UserRepo for UserRepoInMemory {
     fn add_user(&mut self, u: User) {
         println!("adding user {}", &u.name);
-        let n = u.public_key.clone();
+        let n = crypto::x_only(&u.public_key).to_string();
         self.users.insert(n, u);
     }
-    fn read_user(&self, public_key: &String) -> Option<&User> {
-        return self.users.get(public_key);
+    fn read_user(&self, public_key: &str) -> Option<&User> {
+        self.users.get(crypto::x_only(public_key))
+    }
+    fn find_user_by_name(&self, name: &str) -> Option<&User> {
+        self.users
+            .values()
+            .find(|u| u.name.eq_ignore_ascii_case(name))
     }
     fn read_all_users(&self) -> Vec<User> {
         let mut res: Vec<User> = Vec::new();
-        for (_, v) in &self.users {
+        for v in self.users.values() {
             res.push(v.clone());
         }
-        return res;
+        res
+    }
+    fn last_seen(&self, public_key: &str) -> u64 {
+        self.last_seen
+            .get(crypto::x_only(public_key))
+            .copied()
+            .unwrap_or(0)
+    }
+    fn mark_seen(&mut self, public_key: &str, at: u64) {
+        let key = crypto::x_only(public_key).to_string();
+        let seen = self.last_seen.entry(key).or_default();
+        *seen = (*seen).max(at);
     }
 }
+impl Default for UserRepoInMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UserRepoInMemory {
     pub fn new() -> UserRepoInMemory {
-        return UserRepoInMemory {
+        UserRepoInMemory {
             users: HashMap::new(),
-        };
+            last_seen: HashMap::new(),
+        }
+    }
+    /// A repository holding `users` and `last_seen` markers keyed by x-only
+    /// public key, e.g. as loaded from storage.
+    pub fn with(users: HashMap<String, User>, last_seen: HashMap<String, u64>) -> UserRepoInMemory {
+        UserRepoInMemory { users, last_seen }
     }
 }
 
-
-
 pub trait SessionRepo {
     fn add(&mut self, e: Session);
-    fn read(&mut self, id: &String) -> Option<Session>;
+    /// The session with `id`, unless it does not exist or has expired.
+    fn read(&mut self, id: &str) -> Option<Session>;
     fn read_all(&mut self) -> Vec<Session>;
-    fn delete(&mut self,id:&String);
+    fn delete(&mut self, id: &str);
 }
 #[derive(Clone)]
 pub struct SessionRepoInMemory {
@@ -116,7 +376,7 @@ impl SessionRepo for SessionRepoInMemory {
         let n = u.get_id();
         self.sessions.insert(n, u);
     }
-    fn read(&mut self, id: &String) -> Option<Session> {
+    fn read(&mut self, id: &str) -> Option<Session> {
         match self.sessions.get(id) {
             Some(e) => {
                 let oe = e.to_owned();
@@ -124,18 +384,18 @@ impl SessionRepo for SessionRepoInMemory {
                     let msg = format!("deleting {}",oe.id);
                     event!(tracing::Level::INFO,msg);
                     self.delete(&oe.id);
+                    return None;
                 }
-                return Some(oe)
-            },
-            None => return None,
-
+                Some(oe)
+            }
+            None => None,
         }
     }
     fn read_all(&mut self) -> Vec<Session> {
         let mut res: Vec<Session> = Vec::new();
         let mut obsoletes: Vec<String> = Vec::new();
-        for (_, v) in &self.sessions {
-            if !v.expired(){
+        for v in self.sessions.values() {
+            if !v.expired() {
                 res.push(v.clone());
             } else {
                 obsoletes.push(v.id.clone());
@@ -146,18 +406,89 @@ impl SessionRepo for SessionRepoInMemory {
             event!(tracing::Level::INFO,msg);
             self.delete(&v);
         }
-        return res;
+        res
     }
-    fn delete(&mut self,id: &String) {
+    fn delete(&mut self, id: &str) {
         self.sessions.remove(id);
     }
 }
 
+impl Default for SessionRepoInMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl SessionRepoInMemory{
+impl SessionRepoInMemory {
     pub fn new() -> SessionRepoInMemory {
-        return SessionRepoInMemory {
+        SessionRepoInMemory {
             sessions: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance;
+
+    #[test]
+    fn test_event_repo_in_memory() {
+        conformance::event_repo(EventRepoInMemory::new);
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut repo = EventRepoInMemory::new();
+        // three events in the same second fill more than a page
+        for (id, kind, created_at) in [
+            ("a", 1, 1),
+            ("b", 1, 2),
+            ("c", 1, 2),
+            ("d", 1, 2),
+            ("e", 7, 3),
+        ] {
+            repo.add(conformance::event(id, "alice", kind, created_at))
+                .unwrap();
+        }
+        let walk = |repo: &mut EventRepoInMemory, filter: &Filter| {
+            let mut pages = Pages::new(filter, 2);
+            let mut ids = Vec::new();
+            while let Some(page) = pages.next(repo) {
+                assert!(!page.is_empty());
+                ids.extend(page.into_iter().map(|e| e.id));
+            }
+            ids
         };
+        assert_eq!(
+            walk(&mut repo, &Filter::new()),
+            vec!["e", "d", "c", "b", "a"]
+        );
+        let kind = Filter {
+            kinds: Some(vec![1]),
+            limit: Some(3),
+            ..Filter::new()
+        };
+        assert_eq!(walk(&mut repo, &kind), vec!["d", "c", "b"]);
+    }
+
+    #[test]
+    fn test_user_repo_in_memory() {
+        conformance::user_repo(UserRepoInMemory::new);
+    }
+
+    #[test]
+    fn test_session_repo_in_memory() {
+        conformance::session_repo(SessionRepoInMemory::new);
+    }
+
+    #[test]
+    fn test_moderation_repo_in_memory() {
+        conformance::moderation_repo(crate::moderation::ModerationRepoInMemory::new);
+    }
+
+    #[test]
+    fn test_circle_repo_in_memory() {
+        conformance::circle_repo(crate::circle::CircleRepoInMemory::new);
     }
 }
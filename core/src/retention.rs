@@ -0,0 +1,362 @@
+//! Bounds on what the relay keeps, applied on top of per-event expiry.
+//!
+//! A policy is applied in four passes: expired events are removed first,
+//! then events older than the maximum age of their kind, then the oldest
+//! events of authors above their quota, and finally the oldest events
+//! overall until the store fits its size limit. Events of pinned authors are
+//! only ever removed by their own expiry.
+//!
+//! [`RetentionPolicy::apply`] walks the events too old for their kind and
+//! the whole store for the quotas a page at a time, so the relay never holds
+//! all events at once; a policy without limits only removes expired events.
+
+use crate::repository::{EventRepo, Pages, RepoError};
+use entity::{Event, Filter};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::SystemTime;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    /// Maximum age in seconds per kind; kinds without entry never age out.
+    pub max_age: BTreeMap<u32, u64>,
+    /// Maximum number of events kept per author.
+    pub max_events_per_author: Option<usize>,
+    /// Maximum size of all events together, measured as serialized JSON.
+    pub max_total_bytes: Option<u64>,
+    /// Authors whose events are never evicted.
+    pub pinned_authors: BTreeSet<String>,
+}
+
+/// Ids removed by a retention run, grouped by the reason for the removal.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RetentionReport {
+    pub expired: Vec<String>,
+    pub too_old: Vec<String>,
+    pub over_author_quota: Vec<String>,
+    pub over_store_quota: Vec<String>,
+}
+
+impl RetentionReport {
+    pub fn total(&self) -> usize {
+        self.expired.len()
+            + self.too_old.len()
+            + self.over_author_quota.len()
+            + self.over_store_quota.len()
+    }
+}
+
+impl RetentionPolicy {
+    /// Removes expired events and everything the policy evicts from `repo`,
+    /// with the same outcome as [`RetentionPolicy::plan`] over all events.
+    /// The store is walked newest first a page at a time rather than read at
+    /// once, and not at all unless the policy sets a limit.
+    pub fn apply(&self, repo: &mut dyn EventRepo) -> Result<RetentionReport, RepoError> {
+        let mut report = RetentionReport {
+            expired: repo.delete_expired()?,
+            ..RetentionReport::default()
+        };
+        if self.is_unbounded() {
+            return Ok(report);
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+        for (kind, max) in &self.max_age {
+            let Some(until) = now.checked_sub(max + 1) else {
+                continue;
+            };
+            let filter = Filter {
+                kinds: Some(vec![*kind]),
+                until: Some(until),
+                ..Filter::new()
+            };
+            let start = report.too_old.len();
+            newest_first(repo, &filter, PAGE, |e| {
+                if !self.pinned(e) {
+                    report.too_old.push(e.get_id());
+                }
+            });
+            for id in &report.too_old[start..] {
+                repo.delete(id)?;
+            }
+        }
+
+        if self.max_events_per_author.is_none() && self.max_total_bytes.is_none() {
+            return Ok(report);
+        }
+        // newest first, an author is over quota once their count reaches it
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let (mut total, mut pinned) = (0, 0);
+        newest_first(repo, &Filter::new(), PAGE, |e| {
+            let count = counts.entry(e.public_key.clone()).or_insert(0);
+            if self.max_events_per_author.is_some_and(|max| *count >= max) && !self.pinned(e) {
+                report.over_author_quota.push(e.get_id());
+                return;
+            }
+            *count += 1;
+            total += size(e);
+            if self.pinned(e) {
+                pinned += size(e);
+            }
+        });
+        for id in &report.over_author_quota {
+            repo.delete(id)?;
+        }
+        // Oldest first, events are evicted while the store is too large,
+        // so one is evicted if the pinned events, it and the unpinned events
+        // newer than it exceed the limit.
+        if let Some(max) = self.max_total_bytes.filter(|max| total > *max) {
+            let mut newer = 0;
+            newest_first(repo, &Filter::new(), PAGE, |e| {
+                if self.pinned(e) {
+                    return;
+                }
+                if pinned + size(e) + newer > max {
+                    report.over_store_quota.push(e.get_id());
+                }
+                newer += size(e);
+            });
+            for id in &report.over_store_quota {
+                repo.delete(id)?;
+            }
+            // reported oldest first, as by plan
+            report.over_store_quota.reverse();
+        }
+        report.over_author_quota.reverse();
+        Ok(report)
+    }
+
+    /// Whether the policy only leaves expiry to the repository.
+    fn is_unbounded(&self) -> bool {
+        self.max_age.is_empty()
+            && self.max_events_per_author.is_none()
+            && self.max_total_bytes.is_none()
+    }
+
+    /// Decides which of `events` to evict at time `now` without touching any
+    /// repository. Expiry is left to the repository.
+    pub fn plan(&self, events: &[Event], now: u64) -> RetentionReport {
+        let mut report = RetentionReport::default();
+        let mut kept: Vec<&Event> = Vec::new();
+        for e in events {
+            let too_old = self
+                .max_age
+                .get(&e.kind)
+                .is_some_and(|max| now.saturating_sub(e.created_at) > *max);
+            if too_old && !self.pinned(e) {
+                report.too_old.push(e.get_id());
+            } else {
+                kept.push(e);
+            }
+        }
+        // oldest first, so evictions below always take from the front
+        kept.sort_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)));
+
+        if let Some(max) = self.max_events_per_author {
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            for e in &kept {
+                *counts.entry(e.public_key.as_str()).or_insert(0) += 1;
+            }
+            let mut evicted = HashSet::new();
+            for e in &kept {
+                let count = counts.get_mut(e.public_key.as_str()).expect("counted");
+                if *count > max && !self.pinned(e) {
+                    *count -= 1;
+                    evicted.insert(e.id.as_str());
+                    report.over_author_quota.push(e.get_id());
+                }
+            }
+            kept.retain(|e| !evicted.contains(e.id.as_str()));
+        }
+
+        if let Some(max) = self.max_total_bytes {
+            let mut total: u64 = kept.iter().map(|e| size(e)).sum();
+            for e in &kept {
+                if total <= max {
+                    break;
+                }
+                if !self.pinned(e) {
+                    total -= size(e);
+                    report.over_store_quota.push(e.get_id());
+                }
+            }
+        }
+        report
+    }
+
+    /// Whether `e` is by a pinned author, with either key given in
+    /// compressed or x-only form.
+    fn pinned(&self, e: &Event) -> bool {
+        let author = crypto::x_only(&e.public_key);
+        self.pinned_authors
+            .iter()
+            .any(|pk| crypto::x_only(pk) == author)
+    }
+}
+
+/// Events read per query while walking the store.
+const PAGE: usize = 500;
+
+/// Calls `visit` for every event of `repo` matching `filter`, newest
+/// first, reading `page` events at a time.
+fn newest_first(
+    repo: &mut dyn EventRepo,
+    filter: &Filter,
+    page: usize,
+    mut visit: impl FnMut(&Event),
+) {
+    let mut pages = Pages::new(filter, page);
+    while let Some(events) = pages.next(repo) {
+        events.iter().for_each(&mut visit);
+    }
+}
+
+fn size(e: &Event) -> u64 {
+    serde_json::to_vec(e).map(|b| b.len() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::event;
+    use crate::repository::EventRepoInMemory;
+
+    #[test]
+    fn test_plan() {
+        let events = vec![
+            event("a1", "alice", 1, 10),
+            event("a2", "alice", 1, 20),
+            event("a3", "alice", 1, 30),
+            event("b1", "bob", 7, 5),
+            event("p1", "pinned", 7, 1),
+            event("p2", "pinned", 1, 2),
+        ];
+        let mut policy = RetentionPolicy {
+            max_age: BTreeMap::from([(7, 50)]),
+            max_events_per_author: Some(2),
+            ..RetentionPolicy::default()
+        };
+        policy.pinned_authors.insert("pinned".to_string());
+        let report = policy.plan(&events, 100);
+        assert_eq!(report.too_old, vec!["b1"]);
+        assert_eq!(report.over_author_quota, vec!["a1"]);
+        assert!(report.over_store_quota.is_empty());
+
+        // room for the two pinned events and alice's newest one
+        let policy = RetentionPolicy {
+            max_total_bytes: Some(size(&events[2]) + size(&events[4]) + size(&events[5])),
+            pinned_authors: policy.pinned_authors,
+            ..RetentionPolicy::default()
+        };
+        let report = policy.plan(&events, 100);
+        assert_eq!(report.over_store_quota, vec!["b1", "a1", "a2"]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut repo = EventRepoInMemory::new();
+        let mut expired = event("x", "alice", 1, 10);
+        expired.expires_at = 1;
+        repo.add(expired).unwrap();
+        repo.add(event("a", "alice", 1, 20)).unwrap();
+        repo.add(event("b", "alice", 1, 30)).unwrap();
+        let policy = RetentionPolicy {
+            max_events_per_author: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let report = policy.apply(&mut repo).unwrap();
+        assert_eq!(report.expired, vec!["x"]);
+        assert_eq!(report.over_author_quota, vec!["a"]);
+        assert_eq!(report.total(), 2);
+        assert_eq!(repo.read_all().len(), 1);
+        assert!(repo.read("b").is_some());
+    }
+
+    #[test]
+    fn test_newest_first() {
+        let mut repo = EventRepoInMemory::new();
+        // five events in the same second fill more than a page
+        for (id, created_at) in [
+            ("a", 1),
+            ("b", 2),
+            ("c", 2),
+            ("d", 2),
+            ("e", 2),
+            ("f", 2),
+            ("g", 3),
+        ] {
+            repo.add(event(id, "alice", 1, created_at)).unwrap();
+        }
+        let mut ids = Vec::new();
+        newest_first(&mut repo, &Filter::new(), 2, |e| ids.push(e.get_id()));
+        assert_eq!(ids, vec!["g", "f", "e", "d", "c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_apply_agrees_with_plan() {
+        let mut repo = EventRepoInMemory::new();
+        for i in 0..1200u64 {
+            let author = ["alice", "bob", "carol", "pinned"][i as usize % 4];
+            let mut e = event(
+                &format!("e{:04}", i),
+                author,
+                [1, 7, 9][i as usize % 3],
+                i / 7,
+            );
+            e.content = "x".repeat(i as usize % 13);
+            repo.add(e).unwrap();
+        }
+        let mut policy = RetentionPolicy {
+            max_age: BTreeMap::from([(7, 60)]),
+            max_events_per_author: Some(150),
+            max_total_bytes: Some(60_000),
+            ..RetentionPolicy::default()
+        };
+        policy.pinned_authors.insert("pinned".to_string());
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let mut planned = policy.plan(&repo.read_all(), now);
+        let mut applied = policy.apply(&mut repo).unwrap();
+        assert!(!planned.over_author_quota.is_empty());
+        assert!(!planned.over_store_quota.is_empty());
+        planned.too_old.sort();
+        applied.too_old.sort();
+        assert_eq!(applied, planned);
+        assert_eq!(repo.read_all().len(), 1200 - planned.total());
+    }
+
+    #[test]
+    fn test_pinned_key_forms() {
+        let x_only = "ab".repeat(32);
+        let events = vec![
+            event("a", &format!("02{}", x_only), 7, 1),
+            event("b", &x_only, 7, 2),
+        ];
+        let mut policy = RetentionPolicy {
+            max_age: BTreeMap::from([(7, 10)]),
+            ..RetentionPolicy::default()
+        };
+        policy.pinned_authors.insert(x_only.clone());
+        assert!(policy.plan(&events, 100).too_old.is_empty());
+        policy.pinned_authors = BTreeSet::from([format!("03{}", x_only)]);
+        assert!(policy.plan(&events, 100).too_old.is_empty());
+    }
+
+    #[test]
+    fn test_apply_without_limits() {
+        let mut repo = EventRepoInMemory::new();
+        let mut expired = event("x", "alice", 1, 10);
+        expired.expires_at = 1;
+        repo.add(expired).unwrap();
+        repo.add(event("a", "alice", 1, 20)).unwrap();
+        let report = RetentionPolicy::default().apply(&mut repo).unwrap();
+        assert_eq!(report.expired, vec!["x"]);
+        assert_eq!(report.total(), 1);
+        assert!(repo.read("a").is_some());
+    }
+}
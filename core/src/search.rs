@@ -0,0 +1,109 @@
+//! Inverted index over event content and the ranking shared by all backends.
+//!
+//! Content is split with [`entity::tokenize`]. A search matches events that
+//! contain every term; they are scored with tf-idf, i.e. the sum over terms
+//! of `tf * ln(1 + total / df)`.
+
+use entity::{tokenize, Event};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// How often each term occurs in `content`.
+pub fn term_frequencies(content: &str) -> HashMap<String, u32> {
+    let mut tf = HashMap::new();
+    for term in tokenize(content) {
+        *tf.entry(term).or_insert(0) += 1;
+    }
+    tf
+}
+
+/// Ranks the ids that occur in every posting list. `postings` holds one
+/// `id -> tf` map per query term and `total` is the number of indexed events.
+pub fn rank(postings: &[HashMap<String, u32>], total: u64) -> Vec<(String, f64)> {
+    let Some((first, rest)) = postings.split_first() else {
+        return Vec::new();
+    };
+    let idf = |p: &HashMap<String, u32>| (1.0 + total as f64 / p.len().max(1) as f64).ln();
+    first
+        .keys()
+        .filter(|id| rest.iter().all(|p| p.contains_key(*id)))
+        .map(|id| {
+            let score = postings.iter().map(|p| p[id] as f64 * idf(p)).sum();
+            (id.clone(), score)
+        })
+        .collect()
+}
+
+/// Orders scored events best first, newest first among equal scores.
+pub fn sort_by_rank(events: &mut [(f64, Event)]) {
+    events.sort_by(|(sa, a), (sb, b)| {
+        sb.partial_cmp(sa)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.created_at.cmp(&a.created_at))
+            .then_with(|| b.id.cmp(&a.id))
+    });
+}
+
+/// In-memory postings: term -> event id -> term frequency.
+#[derive(Clone, Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, HashMap<String, u32>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> SearchIndex {
+        SearchIndex::default()
+    }
+    pub fn insert(&mut self, e: &Event) {
+        for (term, tf) in term_frequencies(&e.content) {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(e.id.clone(), tf);
+        }
+    }
+    pub fn remove(&mut self, e: &Event) {
+        for term in term_frequencies(&e.content).keys() {
+            if let Some(ids) = self.postings.get_mut(term) {
+                ids.remove(&e.id);
+                if ids.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+    /// Ids of events containing all `terms` with their scores.
+    pub fn search(&self, terms: &[String], total: u64) -> Vec<(String, f64)> {
+        let postings: Vec<HashMap<String, u32>> = terms
+            .iter()
+            .map(|t| self.postings.get(t).cloned().unwrap_or_default())
+            .collect();
+        rank(&postings, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_prefers_frequent_terms() {
+        let mut index = SearchIndex::new();
+        let mut a = Event::new("pk".to_string(), "rust rust rust and nostr".to_string(), 0);
+        a.id = "a".to_string();
+        let mut b = Event::new("pk".to_string(), "Rust and Nostr".to_string(), 0);
+        b.id = "b".to_string();
+        let mut c = Event::new("pk".to_string(), "just nostr".to_string(), 0);
+        c.id = "c".to_string();
+        for e in [&a, &b, &c] {
+            index.insert(e);
+        }
+        let mut ranked = index.search(&["rust".to_string(), "nostr".to_string()], 3);
+        ranked.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+        let ids: Vec<&str> = ranked.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        index.remove(&a);
+        assert_eq!(index.search(&["rust".to_string()], 2).len(), 1);
+    }
+}
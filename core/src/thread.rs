@@ -0,0 +1,163 @@
+//! Reconstructs the conversation around an event from the store.
+//!
+//! Ancestors are followed through each event's parent, descendants are
+//! found level by level through the `e` tags of text notes. Every event is
+//! taken at most once, so reference cycles end the walk instead of looping,
+//! and `max_depth` bounds both directions.
+
+use crate::repository::EventRepo;
+use entity::thread::{self, Node, Thread};
+use entity::{kind, Event, Filter};
+use std::collections::{HashMap, HashSet};
+
+/// The thread around event `id`, `None` if it is not stored. At most
+/// `max_events` replies are included.
+pub fn thread(
+    repo: &mut dyn EventRepo,
+    id: &str,
+    max_depth: usize,
+    max_events: usize,
+) -> Option<Thread> {
+    let event = repo.read(id)?;
+    let mut seen = HashSet::from([event.id.clone()]);
+    let mut truncated = false;
+
+    let mut ancestors = Vec::new();
+    let mut current = event.clone();
+    while let Some(parent_id) = thread::parent(&current) {
+        if ancestors.len() == max_depth {
+            truncated = true;
+            break;
+        }
+        if !seen.insert(parent_id.clone()) {
+            break;
+        }
+        let Some(parent) = repo.read(&parent_id) else {
+            break;
+        };
+        ancestors.push(parent.clone());
+        current = parent;
+    }
+    // the root stays in view even when the chain to it is broken
+    if let Some(root_id) = thread::root(&event) {
+        if !seen.contains(&root_id) {
+            if let Some(root) = repo.read(&root_id) {
+                seen.insert(root_id);
+                ancestors.push(root);
+            }
+        }
+    }
+    ancestors.reverse();
+
+    let mut children: HashMap<String, Vec<Event>> = HashMap::new();
+    let mut frontier = HashSet::from([event.id.clone()]);
+    let mut count = 0;
+    for depth in 0..=max_depth {
+        if frontier.is_empty() {
+            break;
+        }
+        let filter = Filter {
+            kinds: Some(vec![kind::TEXT_NOTE]),
+            ..Filter::new().with_tag("e", frontier.iter().cloned().collect())
+        };
+        let replies: Vec<(String, Event)> = repo
+            .query(&filter)
+            .into_iter()
+            .filter(|e| !seen.contains(&e.id))
+            .filter_map(|e| thread::parent(&e).map(|p| (p, e)))
+            .filter(|(p, _)| frontier.contains(p))
+            .collect();
+        if depth == max_depth {
+            truncated |= !replies.is_empty();
+            break;
+        }
+        frontier.clear();
+        for (parent, e) in replies {
+            if count == max_events {
+                truncated = true;
+                break;
+            }
+            count += 1;
+            seen.insert(e.id.clone());
+            frontier.insert(e.id.clone());
+            children.entry(parent).or_default().push(e);
+        }
+    }
+    Some(Thread {
+        ancestors,
+        tree: node(event, &mut children),
+        truncated,
+    })
+}
+
+fn node(event: Event, children: &mut HashMap<String, Vec<Event>>) -> Node {
+    let mut replies = children.remove(&event.id).unwrap_or_default();
+    replies.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+    Node {
+        replies: replies.into_iter().map(|e| node(e, children)).collect(),
+        event,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::event;
+    use crate::repository::EventRepoInMemory;
+
+    fn reply(id: &str, to: &Event, created_at: u64) -> Event {
+        let mut e = thread::reply("bob".to_string(), String::new(), to);
+        e.id = id.to_string();
+        e.created_at = created_at;
+        e
+    }
+
+    fn ids(nodes: &[Node]) -> Vec<&str> {
+        nodes.iter().map(|n| n.event.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_thread() {
+        let mut repo = EventRepoInMemory::new();
+        let root = event("r", "alice", kind::TEXT_NOTE, 1);
+        let a = reply("a", &root, 2);
+        let b = reply("b", &root, 3);
+        let aa = reply("aa", &a, 4);
+        let aaa = reply("aaa", &aa, 5);
+        for e in [&root, &a, &b, &aa, &aaa] {
+            repo.add(e.clone()).unwrap();
+        }
+
+        let t = thread(&mut repo, "r", 10, 100).unwrap();
+        assert!(t.ancestors.is_empty());
+        assert_eq!(ids(&t.tree.replies), vec!["a", "b"]);
+        assert_eq!(ids(&t.tree.replies[0].replies), vec!["aa"]);
+        assert!(!t.truncated);
+
+        let t = thread(&mut repo, "aa", 10, 100).unwrap();
+        let ancestors: Vec<_> = t.ancestors.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ancestors, vec!["r", "a"]);
+        assert_eq!(ids(&t.tree.replies), vec!["aaa"]);
+
+        let t = thread(&mut repo, "r", 1, 100).unwrap();
+        assert!(t.truncated);
+        assert!(t.tree.replies.iter().all(|n| n.replies.is_empty()));
+        assert!(thread(&mut repo, "r", 10, 2).unwrap().truncated);
+        assert!(thread(&mut repo, "missing", 10, 100).is_none());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut repo = EventRepoInMemory::new();
+        // x and y claim to reply to each other
+        let mut x = event("x", "alice", kind::TEXT_NOTE, 1);
+        let mut y = event("y", "bob", kind::TEXT_NOTE, 2);
+        x.tags = vec![vec!["e".to_string(), "y".to_string()]];
+        y.tags = vec![vec!["e".to_string(), "x".to_string()]];
+        repo.add(x).unwrap();
+        repo.add(y).unwrap();
+        let t = thread(&mut repo, "x", 10, 100).unwrap();
+        assert_eq!(t.ancestors.len(), 1);
+        assert!(t.tree.replies.is_empty());
+    }
+}
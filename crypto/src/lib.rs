@@ -1,21 +1,50 @@
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use base64::Engine;
+use secp256k1::ecdh::{shared_secret_point, SharedSecret};
 use secp256k1::hashes::sha256::Hash;
 use secp256k1::rand::rngs::OsRng;
-use secp256k1::ecdh::SharedSecret;
-use secp256k1::{Message, Secp256k1, SecretKey, XOnlyPublicKey, Parity};
+use secp256k1::rand::RngCore;
+use secp256k1::schnorr::Signature;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey};
 use sha256::digest;
 use std::str::FromStr;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CryptoError {
+    InvalidPublicKey(String),
+    InvalidPrivateKey,
+    /// The payload is malformed or was not encrypted for this key pair.
+    InvalidCiphertext,
+}
+
+impl std::fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CryptoError::InvalidPublicKey(key) => write!(f, "invalid public key {}", key),
+            CryptoError::InvalidPrivateKey => write!(f, "invalid private key"),
+            CryptoError::InvalidCiphertext => write!(f, "cannot decrypt payload"),
+        }
+    }
+}
+
+impl std::error::Error for CryptoError {}
+
 pub fn hash(s: String) -> String {
-    return digest(s);
+    digest(s)
+}
+
+pub fn hash_bytes(b: &[u8]) -> String {
+    digest(b)
 }
 
 pub fn create_key_pair() -> (String, String) {
     let secp = Secp256k1::new();
     let (secret_key, public_key) = secp.generate_keypair(&mut OsRng);
-    return (
+    (
         secret_key.display_secret().to_string(),
         public_key.to_string(),
-    );
+    )
 }
 
 pub fn sign_message(s: String, private_key: String) -> String {
@@ -26,41 +55,142 @@ pub fn sign_message(s: String, private_key: String) -> String {
     let key_pair = secret_key.keypair(&secp);
 
     let sig = secp.sign_schnorr(&message, &key_pair);
-    return sig.to_string();
+    sig.to_string()
 }
 
 pub fn get_public_key(private_key: String) -> String {
     let secp = Secp256k1::new();
     let secret_key = SecretKey::from_str(&private_key).expect("32 bytes, within curve order");
-    return secret_key.public_key(&secp).to_string();
+    secret_key.public_key(&secp).to_string()
 }
 
-pub fn verify_message(message: &String, signature: &String, public_key: &String) -> bool {
-    let mut pub_key = public_key.clone();
-    pub_key.remove(0);
-    pub_key.remove(0);
+pub fn verify_message(message: &str, signature: &str, public_key: &str) -> bool {
+    // compressed key without its parity prefix
+    let pub_key = match public_key.get(..2) {
+        Some("02" | "03") if public_key.len() == 66 => &public_key[2..],
+        _ => return false,
+    };
+    let message = Message::from_hashed_data::<Hash>(message.as_bytes());
+    verify_schnorr(&message, signature, pub_key)
+}
 
+/// Signs an already hashed message, given as hex, the way standard nostr
+/// events sign their id.
+pub fn sign_hash(hash: &str, private_key: String) -> String {
     let secp = Secp256k1::new();
-    let message = Message::from_hashed_data::<Hash>(message.as_bytes());
-    let xonly = XOnlyPublicKey::from_str(&pub_key).expect("Bad public key");
-    let decoded_signature = hex::decode(signature).expect("Decoding failed");
-    let sig = secp256k1::schnorr::Signature::from_slice(&decoded_signature).expect("TODO");
-    let result = secp.verify_schnorr(&sig, &message, &xonly);
-    return result.is_ok();
-}
-
-pub fn generate_shared_secret(public_key:&String, private_key: String) -> String {
-    let mut pub_key = public_key.clone();
-    pub_key.remove(0);
-    pub_key.remove(0);
-    let xonly = XOnlyPublicKey::from_str(&pub_key).expect("Bad public key");
+    let digest = hex::decode(hash).expect("Decoding failed");
+    let message = Message::from_slice(&digest).expect("32 byte hash");
     let decoded = hex::decode(private_key).expect("Decoding failed");
     let secret_key = SecretKey::from_slice(&decoded).expect("32 bytes, within curve order");
-    let sec1 = SharedSecret::new(&xonly.public_key(Parity::Odd), &secret_key);
-    return sec1.display_secret().to_string()
+    let sig = secp.sign_schnorr(&message, &secret_key.keypair(&secp));
+    sig.to_string()
+}
+
+/// Verifies a signature over an already hashed message. `public_key` is the
+/// 32 byte x-only key in hex, as used by standard nostr events.
+pub fn verify_hash(hash: &str, signature: &str, public_key: &str) -> bool {
+    let message = match hex::decode(hash).map(|d| Message::from_slice(&d)) {
+        Ok(Ok(message)) => message,
+        _ => return false,
+    };
+    verify_schnorr(&message, signature, public_key)
+}
+
+/// The 32 byte x-only form of a public key, which standard nostr software
+/// expects; compressed keys lose their parity prefix.
+pub fn x_only(public_key: &str) -> &str {
+    match public_key.len() {
+        66 => &public_key[2..],
+        _ => public_key,
+    }
+}
+
+fn verify_schnorr(message: &Message, signature: &str, xonly_key: &str) -> bool {
+    let Ok(xonly) = XOnlyPublicKey::from_str(xonly_key) else {
+        return false;
+    };
+    let sig = match hex::decode(signature).map(|d| Signature::from_slice(&d)) {
+        Ok(Ok(sig)) => sig,
+        _ => return false,
+    };
+    let secp = Secp256k1::verification_only();
+    secp.verify_schnorr(&sig, message, &xonly).is_ok()
 }
 
+pub fn generate_shared_secret(
+    public_key: &str,
+    private_key: String,
+) -> Result<String, CryptoError> {
+    let pub_key = PublicKey::from_str(public_key)
+        .map_err(|_| CryptoError::InvalidPublicKey(public_key.to_string()))?;
+    let decoded = hex::decode(private_key).map_err(|_| CryptoError::InvalidPrivateKey)?;
+    let secret_key = SecretKey::from_slice(&decoded).map_err(|_| CryptoError::InvalidPrivateKey)?;
+    let sec1 = SharedSecret::new(&pub_key, &secret_key);
+    Ok(sec1.display_secret().to_string())
+}
+
+type Aes256CbcEnc = cbc::Encryptor<aes::Aes256>;
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
 
+/// The key `private_key` and the owner of `public_key` share for encrypted
+/// messages (NIP-04): the x coordinate of their ECDH point. Both key forms
+/// are accepted; either parity gives the same x coordinate.
+fn conversation_key(public_key: &str, private_key: &str) -> Result<[u8; 32], CryptoError> {
+    let compressed = match public_key.len() {
+        64 => format!("02{}", public_key),
+        _ => public_key.to_string(),
+    };
+    let pub_key = PublicKey::from_str(&compressed)
+        .map_err(|_| CryptoError::InvalidPublicKey(public_key.to_string()))?;
+    let decoded = hex::decode(private_key).map_err(|_| CryptoError::InvalidPrivateKey)?;
+    let secret_key = SecretKey::from_slice(&decoded).map_err(|_| CryptoError::InvalidPrivateKey)?;
+    let point = shared_secret_point(&pub_key, &secret_key);
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&point[..32]);
+    Ok(key)
+}
+
+/// Encrypts `plaintext` from the owner of `private_key` for `public_key` as
+/// NIP-04 does: AES-256-CBC, sent as `<base64 ciphertext>?iv=<base64 iv>`.
+/// Encrypting for one's own public key keeps private data private.
+pub fn encrypt(
+    plaintext: &str,
+    public_key: &str,
+    private_key: String,
+) -> Result<String, CryptoError> {
+    let key = conversation_key(public_key, &private_key)?;
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+    let ciphertext = Aes256CbcEnc::new(&key.into(), &iv.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext.as_bytes());
+    let b64 = base64::engine::general_purpose::STANDARD;
+    Ok(format!("{}?iv={}", b64.encode(ciphertext), b64.encode(iv)))
+}
+
+/// Decrypts what [`encrypt`] produced, with the private key of either side.
+pub fn decrypt(
+    payload: &str,
+    public_key: &str,
+    private_key: String,
+) -> Result<String, CryptoError> {
+    let key = conversation_key(public_key, &private_key)?;
+    let b64 = base64::engine::general_purpose::STANDARD;
+    let (ciphertext, iv) = payload
+        .split_once("?iv=")
+        .ok_or(CryptoError::InvalidCiphertext)?;
+    let ciphertext = b64
+        .decode(ciphertext)
+        .map_err(|_| CryptoError::InvalidCiphertext)?;
+    let iv: [u8; 16] = b64
+        .decode(iv)
+        .ok()
+        .and_then(|iv| iv.try_into().ok())
+        .ok_or(CryptoError::InvalidCiphertext)?;
+    let plaintext = Aes256CbcDec::new(&key.into(), &iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(&ciphertext)
+        .map_err(|_| CryptoError::InvalidCiphertext)?;
+    String::from_utf8(plaintext).map_err(|_| CryptoError::InvalidCiphertext)
+}
 
 #[cfg(test)]
 mod tests {
@@ -71,15 +201,72 @@ mod tests {
          let private_key = "88acc91b32ff8678417e1c4f1dc9904865d9f2732d8111ff98fd4978809e58ec".to_string();
          let public_key = "036ccd001880d8938eed044baf4b8a0a7a081fea6b9c60f336aa8cf09f5b8ffa23".to_string();
         let msg = "testString to test".to_string();
-        let sig = sign_message(msg.clone(),private_key.clone());
-        assert_eq!(verify_message(&msg,&sig,&public_key),true);
+        let sig = sign_message(msg.clone(), private_key.clone());
+        assert!(verify_message(&msg, &sig, &public_key));
+    }
+    #[test]
+    fn test_verify_message_rejects_garbage() {
+        let msg = "testString to test".to_string();
+        assert!(!verify_message(&msg, "zz", ""));
+        assert!(!verify_message(&msg, "00", "036ccd"));
+    }
+    #[test]
+    fn test_verify_message_checks_prefix() {
+        let (sk, pk) = create_key_pair();
+        let msg = "testString to test".to_string();
+        let sig = sign_message(msg.clone(), sk);
+        let other_parity = match &pk[..2] {
+            "02" => "03",
+            _ => "02",
+        };
+        assert!(verify_message(&msg, &sig, &pk));
+        assert!(verify_message(
+            &msg,
+            &sig,
+            &format!("{}{}", other_parity, x_only(&pk))
+        ));
+        for prefix in ["zz", "04", "00"] {
+            let key = format!("{}{}", prefix, x_only(&pk));
+            assert!(!verify_message(&msg, &sig, &key), "prefix {}", prefix);
+        }
+        assert!(!verify_message(&msg, &sig, x_only(&pk)));
+    }
+    #[test]
+    fn test_verify_hash() {
+        let (sk, pk) = create_key_pair();
+        let digest = hash("message".to_string());
+        let sig = sign_hash(&digest, sk);
+        assert!(verify_hash(&digest, &sig, &pk[2..]));
+        assert!(!verify_hash(&digest, &sig, &pk));
+        assert!(!verify_hash(&hash("other".to_string()), &sig, &pk[2..]));
     }
     #[test]
     fn test_shared_secret() {
-        let(sk1,pk1) = create_key_pair();
-        let(sk2,pk2) = create_key_pair();
-        let ss1 = generate_shared_secret(&pk1, sk2);
-        let ss2 = generate_shared_secret(&pk2, sk1);
-        assert_eq!(ss1,ss2);
-   }
+        let (sk1, pk1) = create_key_pair();
+        let (sk2, pk2) = create_key_pair();
+        let ss1 = generate_shared_secret(&pk1, sk2.clone()).unwrap();
+        let ss2 = generate_shared_secret(&pk2, sk1).unwrap();
+        assert_eq!(ss1, ss2);
+        assert_eq!(
+            generate_shared_secret("nope", sk2),
+            Err(CryptoError::InvalidPublicKey("nope".to_string()))
+        );
+    }
+    #[test]
+    fn test_encrypt() {
+        let (sk1, pk1) = create_key_pair();
+        let (sk2, pk2) = create_key_pair();
+        let payload = encrypt("hello bob", &pk2, sk1.clone()).unwrap();
+        assert_eq!(decrypt(&payload, &pk1, sk2).unwrap(), "hello bob");
+        assert_eq!(
+            decrypt(&payload, x_only(&pk2), sk1.clone()).unwrap(),
+            "hello bob"
+        );
+        let (sk3, _) = create_key_pair();
+        assert!(decrypt(&payload, &pk1, sk3).is_err());
+        assert_eq!(
+            decrypt("garbage", &pk2, sk1),
+            Err(CryptoError::InvalidCiphertext)
+        );
+    }
 }
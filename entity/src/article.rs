@@ -0,0 +1,154 @@
+//! Long-form articles (NIP-23): addressable events of kind
+//! [`kind::LONG_FORM`] with Markdown content. The `d` tag names the article,
+//! so publishing again under the same `d` replaces the previous version,
+//! while `published_at` keeps the time the first version went out.
+
+use crate::{kind, Event};
+use chrono::NaiveDate;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Article {
+    pub d: String,
+    pub title: String,
+    pub summary: Option<String>,
+    pub image: Option<String>,
+    pub published_at: Option<u64>,
+    pub hashtags: Vec<String>,
+    /// Markdown.
+    pub content: String,
+}
+
+impl Article {
+    pub fn from_event(e: &Event) -> Result<Article, String> {
+        if e.kind != kind::LONG_FORM {
+            return Err(format!("expected an article, got kind {}", e.kind));
+        }
+        let first = |name| e.tag_values(name).next().map(str::to_string);
+        Ok(Article {
+            d: first("d").unwrap_or_default(),
+            title: first("title").unwrap_or_default(),
+            summary: first("summary"),
+            image: first("image"),
+            published_at: first("published_at").and_then(|v| v.parse().ok()),
+            hashtags: e.tag_values("t").map(str::to_string).collect(),
+            content: e.content.clone(),
+        })
+    }
+
+    /// Reads Markdown that may start with front matter between `---` lines:
+    /// `key: value` pairs for `d` (or `slug`), `title`, `summary`, `image`,
+    /// `published_at` (unix time or `YYYY-MM-DD`) and `tags`, comma
+    /// separated and optionally in brackets.
+    pub fn from_markdown(text: &str) -> Result<Article, String> {
+        let mut article = Article::default();
+        let Some(rest) = text.strip_prefix("---\n").or(text.strip_prefix("---\r\n")) else {
+            article.content = text.to_string();
+            return Ok(article);
+        };
+        let mut lines = rest.split_inclusive('\n');
+        let mut closed = false;
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line == "---" {
+                closed = true;
+                break;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                return Err(format!("invalid front matter line: {}", line));
+            };
+            let value = unquote(value.trim());
+            match key.trim() {
+                "d" | "slug" => article.d = value.to_string(),
+                "title" => article.title = value.to_string(),
+                "summary" => article.summary = Some(value.to_string()),
+                "image" => article.image = Some(value.to_string()),
+                "published_at" => article.published_at = Some(parse_time(value)?),
+                "tags" => {
+                    article.hashtags = value
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .split(',')
+                        .map(|t| unquote(t.trim()).trim_start_matches('#').to_lowercase())
+                        .filter(|t| !t.is_empty())
+                        .collect()
+                }
+                other => return Err(format!("unknown front matter key {}", other)),
+            }
+        }
+        if !closed {
+            return Err("front matter is not closed by ---".to_string());
+        }
+        article.content = lines.collect::<String>().trim_start().to_string();
+        Ok(article)
+    }
+
+    /// The unsigned event publishing this version of the article.
+    pub fn to_event(&self, public_key: String) -> Event {
+        let tag = |name: &str, value: &str| vec![name.to_string(), value.to_string()];
+        let mut e = Event::new(public_key, self.content.clone(), 0);
+        e.kind = kind::LONG_FORM;
+        e.tags = vec![tag("d", &self.d), tag("title", &self.title)];
+        if let Some(summary) = &self.summary {
+            e.tags.push(tag("summary", summary));
+        }
+        if let Some(image) = &self.image {
+            e.tags.push(tag("image", image));
+        }
+        if let Some(published_at) = self.published_at {
+            e.tags.push(tag("published_at", &published_at.to_string()));
+        }
+        e.tags.extend(self.hashtags.iter().map(|t| tag("t", t)));
+        e
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(v) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return v;
+        }
+    }
+    value
+}
+
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(seconds) = value.parse() {
+        return Ok(seconds);
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .and_then(|t| u64::try_from(t.and_utc().timestamp()).ok())
+        .ok_or_else(|| format!("invalid published_at {}", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_markdown() {
+        let text = "---\ntitle: \"Design: storage\"\nslug: storage\n\
+                    published_at: 2024-01-02\ntags: [Rust, '#redb']\n---\n\n# Storage\n\nText.\n";
+        let article = Article::from_markdown(text).unwrap();
+        assert_eq!(article.title, "Design: storage");
+        assert_eq!(article.d, "storage");
+        assert_eq!(article.published_at, Some(1704153600));
+        assert_eq!(article.hashtags, vec!["rust", "redb"]);
+        assert_eq!(article.content, "# Storage\n\nText.\n");
+
+        let e = article.to_event("alice".to_string());
+        assert_eq!(e.address().unwrap(), "30023:alice:storage");
+        assert_eq!(Article::from_event(&e).unwrap(), article);
+
+        assert_eq!(Article::from_markdown("# Hi").unwrap().content, "# Hi");
+        assert!(Article::from_markdown("---\ntitle: x\n").is_err());
+        assert!(Article::from_markdown("---\nauthor: x\n---\n").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Circles: named sets of members owned by a user. An event addressed to a
+//! circle carries a `["circle", <id>]` tag and is only shown to the owner
+//! and the members; the id is `<owner>:<name>`, with the owner's x-only key.
+//!
+//! The relay only restricts who can read such events. Authors who do not
+//! want to trust it with the content encrypt it with a circle key, a key
+//! pair the owner generates and hands to every member in an encrypted
+//! direct message ([`key_message`]). Encrypted events name the public part
+//! of the key they used as a third value of the `circle` tag, so members
+//! can tell which key to use after the owner replaced it.
+
+use crate::{kind, Event, KeyPair};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Circle {
+    /// x-only public key.
+    pub owner: String,
+    pub name: String,
+    /// x-only public keys.
+    #[serde(default)]
+    pub members: BTreeSet<String>,
+}
+
+impl Circle {
+    /// An empty circle; names are up to 64 letters, digits, `-` or `_`.
+    pub fn new(owner: &str, name: &str) -> Result<Circle, String> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if name.is_empty() || name.len() > 64 || !name.chars().all(valid) {
+            return Err(format!(
+                "invalid circle name {}: use up to 64 letters, digits, - or _",
+                name
+            ));
+        }
+        Ok(Circle {
+            owner: crypto::x_only(owner).to_string(),
+            name: name.to_string(),
+            members: BTreeSet::new(),
+        })
+    }
+
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.owner, self.name)
+    }
+
+    pub fn is_owner(&self, public_key: &str) -> bool {
+        crypto::x_only(public_key) == self.owner
+    }
+
+    /// Whether `public_key` may read the events addressed to the circle.
+    pub fn includes(&self, public_key: &str) -> bool {
+        self.is_owner(public_key) || self.members.contains(crypto::x_only(public_key))
+    }
+
+    /// Adds the members in either key form; false if all were members.
+    pub fn add<'a>(&mut self, members: impl IntoIterator<Item = &'a String>) -> bool {
+        let before = self.members.len();
+        self.members
+            .extend(members.into_iter().map(|pk| crypto::x_only(pk).to_string()));
+        self.members.len() != before
+    }
+
+    /// Removes the members in either key form; false if none was one.
+    pub fn remove<'a>(&mut self, members: impl IntoIterator<Item = &'a String>) -> bool {
+        let before = self.members.len();
+        for pk in members {
+            self.members.remove(crypto::x_only(pk));
+        }
+        self.members.len() != before
+    }
+}
+
+/// The id of the circle `e` is addressed to, if any.
+pub fn audience(e: &Event) -> Option<&str> {
+    e.tag_values("circle").next()
+}
+
+/// Addresses `e` to the circle `id`, encrypting its content with `key` if
+/// given.
+pub fn address(e: &mut Event, id: &str, key: Option<&KeyPair>) -> Result<(), String> {
+    let mut tag = vec!["circle".to_string(), id.to_string()];
+    if let Some(key) = key {
+        e.content = crypto::encrypt(&e.content, &key.public_key(), key.private_key())
+            .map_err(|e| e.to_string())?;
+        tag.push(key.public_key());
+    }
+    e.tags.push(tag);
+    Ok(())
+}
+
+/// The public part of the circle key the content of `e` is encrypted with.
+pub fn encrypted_with(e: &Event) -> Option<&str> {
+    e.tags
+        .iter()
+        .find(|t| t.len() > 2 && t[0] == "circle")
+        .map(|t| t[2].as_str())
+}
+
+/// The content of `e`, decrypted with whichever of `keys` it was encrypted
+/// with; unencrypted content is returned as is.
+pub fn open(e: &Event, keys: &[KeyPair]) -> Result<String, String> {
+    let Some(public_key) = encrypted_with(e) else {
+        return Ok(e.content.clone());
+    };
+    let key = keys
+        .iter()
+        .find(|k| k.public_key() == public_key)
+        .ok_or_else(|| format!("no circle key {}", public_key))?;
+    crypto::decrypt(&e.content, public_key, key.private_key()).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyMessage {
+    circle: String,
+    private_key: String,
+}
+
+/// An unsigned direct message from the owner handing `key` of circle `id`
+/// to `member`. It is addressed to the circle itself, so it is only shown
+/// to members.
+pub fn key_message(
+    owner: &KeyPair,
+    member: &str,
+    id: &str,
+    key: &KeyPair,
+) -> Result<Event, String> {
+    let message = KeyMessage {
+        circle: id.to_string(),
+        private_key: key.private_key(),
+    };
+    let json = serde_json::to_string(&message).expect("key messages serialize");
+    let content = crypto::encrypt(&json, member, owner.private_key()).map_err(|e| e.to_string())?;
+    let mut e = Event::new(owner.public_key(), content, 0);
+    e.kind = kind::ENCRYPTED_DM;
+    e.tags = vec![
+        vec!["p".to_string(), member.to_string()],
+        vec!["circle".to_string(), id.to_string()],
+    ];
+    Ok(e)
+}
+
+/// The circle id and key in a message made by [`key_message`], read by
+/// the member it was sent to or by the owner.
+pub fn read_key_message(e: &Event, reader: &KeyPair) -> Result<(String, KeyPair), String> {
+    let sender = match crypto::x_only(&e.public_key) == crypto::x_only(&reader.public_key()) {
+        // the owner reads what they sent with the receiver's key
+        true => e.tag_values("p").next().unwrap_or_default(),
+        false => &e.public_key,
+    };
+    let json =
+        crypto::decrypt(&e.content, sender, reader.private_key()).map_err(|e| e.to_string())?;
+    let message: KeyMessage =
+        serde_json::from_str(&json).map_err(|e| format!("invalid key message: {}", e))?;
+    if audience(e) != Some(message.circle.as_str()) {
+        return Err("key message names another circle".to_string());
+    }
+    Ok((
+        message.circle,
+        KeyPair::from_private_key(message.private_key)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership() {
+        let owner = format!("02{}", "a".repeat(64));
+        let mut circle = Circle::new(&owner, "team").unwrap();
+        assert_eq!(circle.id(), format!("{}:team", "a".repeat(64)));
+        assert!(circle.includes(&owner));
+        assert!(!circle.includes(&"b".repeat(64)));
+        assert!(circle.add(&[format!("03{}", "b".repeat(64))]));
+        assert!(!circle.add(&["b".repeat(64)]));
+        assert!(circle.includes(&"b".repeat(64)));
+        assert!(circle.remove(&["b".repeat(64)]));
+        assert!(circle.members.is_empty());
+        assert!(Circle::new(&owner, "a:b").is_err());
+        assert!(Circle::new(&owner, "").is_err());
+    }
+
+    #[test]
+    fn test_keys() {
+        let owner = KeyPair::generate();
+        let member = KeyPair::generate();
+        let key = KeyPair::generate();
+        let id = Circle::new(&owner.public_key(), "team").unwrap().id();
+
+        let mut e = Event::new(owner.public_key(), "secret".to_string(), 0);
+        address(&mut e, &id, Some(&key)).unwrap();
+        assert_eq!(audience(&e), Some(id.as_str()));
+        assert_ne!(e.content, "secret");
+
+        let message = key_message(&owner, &member.public_key(), &id, &key).unwrap();
+        let (circle, received) = read_key_message(&message, &member).unwrap();
+        assert_eq!(circle, id);
+        assert_eq!(open(&e, &[received]).unwrap(), "secret");
+        assert!(open(&e, &[KeyPair::generate()]).is_err());
+        assert!(read_key_message(&message, &KeyPair::generate()).is_err());
+
+        let own_copy = key_message(&owner, &owner.public_key(), &id, &key).unwrap();
+        assert!(read_key_message(&own_copy, &owner).is_ok());
+    }
+}
@@ -0,0 +1,133 @@
+//! Follow lists (NIP-02): a replaceable event of kind [`kind::CONTACTS`]
+//! whose `p` tags name the followed public keys, each optionally with a
+//! relay to find them on and a petname.
+
+use crate::{kind, Event};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contact {
+    pub public_key: String,
+    pub relay: Option<String>,
+    pub petname: Option<String>,
+}
+
+impl Contact {
+    pub fn new(public_key: String) -> Contact {
+        Contact {
+            public_key,
+            relay: None,
+            petname: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContactList {
+    pub contacts: Vec<Contact>,
+    /// Kept as is; older clients store their relays here.
+    pub content: String,
+}
+
+impl ContactList {
+    pub fn from_event(e: &Event) -> Result<ContactList, String> {
+        if e.kind != kind::CONTACTS {
+            return Err(format!("expected a contact list, got kind {}", e.kind));
+        }
+        let non_empty = |v: Option<&String>| v.filter(|v| !v.is_empty()).cloned();
+        let contacts = e
+            .tags
+            .iter()
+            .filter(|t| t.len() >= 2 && t[0] == "p")
+            .map(|t| Contact {
+                public_key: t[1].clone(),
+                relay: non_empty(t.get(2)),
+                petname: non_empty(t.get(3)),
+            })
+            .collect();
+        Ok(ContactList {
+            contacts,
+            content: e.content.clone(),
+        })
+    }
+
+    pub fn follows(&self, public_key: &str) -> bool {
+        self.contacts.iter().any(|c| c.public_key == public_key)
+    }
+
+    /// Adds `contact`, or replaces the entry with the same public key.
+    pub fn follow(&mut self, contact: Contact) {
+        match self
+            .contacts
+            .iter_mut()
+            .find(|c| c.public_key == contact.public_key)
+        {
+            Some(existing) => *existing = contact,
+            None => self.contacts.push(contact),
+        }
+    }
+
+    /// Removes `public_key`; false if it was not followed.
+    pub fn unfollow(&mut self, public_key: &str) -> bool {
+        let before = self.contacts.len();
+        self.contacts.retain(|c| c.public_key != public_key);
+        self.contacts.len() != before
+    }
+
+    /// The unsigned event publishing this list for `public_key`.
+    pub fn to_event(&self, public_key: String) -> Event {
+        let mut e = Event::new(public_key, self.content.clone(), 0);
+        e.kind = kind::CONTACTS;
+        e.tags = self
+            .contacts
+            .iter()
+            .map(|c| {
+                let mut tag = vec!["p".to_string(), c.public_key.clone()];
+                if c.relay.is_some() || c.petname.is_some() {
+                    tag.push(c.relay.clone().unwrap_or_default());
+                }
+                if let Some(petname) = &c.petname {
+                    tag.push(petname.clone());
+                }
+                tag
+            })
+            .collect();
+        e
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut list = ContactList::default();
+        list.follow(Contact::new("alice".to_string()));
+        list.follow(Contact {
+            public_key: "bob".to_string(),
+            relay: None,
+            petname: Some("bobby".to_string()),
+        });
+        list.follow(Contact {
+            public_key: "alice".to_string(),
+            relay: Some("wss://relay.example.com".to_string()),
+            petname: None,
+        });
+        let e = list.to_event("me".to_string());
+        assert_eq!(
+            e.tags,
+            vec![
+                vec!["p", "alice", "wss://relay.example.com"],
+                vec!["p", "bob", "", "bobby"],
+            ]
+        );
+        assert_eq!(ContactList::from_event(&e).unwrap(), list);
+
+        assert!(list.unfollow("alice"));
+        assert!(!list.unfollow("alice"));
+        assert!(list.follows("bob") && !list.follows("alice"));
+        let mut note = e;
+        note.kind = kind::TEXT_NOTE;
+        assert!(ContactList::from_event(&note).is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::Event;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Selects events by id, author, kind, tag and time range.
+///
+/// Empty or missing fields match everything. Tag conditions are keyed
+/// `#<name>` as on the wire, e.g. `{"#e": ["<id>"]}`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Filter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authors: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kinds: Option<Vec<u32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Full-text query; every term has to occur in the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, Vec<String>>,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+    /// Tag conditions as `(name, values)` with the leading `#` removed.
+    pub fn tag_conditions(&self) -> impl Iterator<Item = (&str, &Vec<String>)> {
+        self.tags
+            .iter()
+            .filter_map(|(k, v)| k.strip_prefix('#').map(|name| (name, v)))
+    }
+    /// The distinct terms of `search`, empty if there is no search.
+    pub fn search_terms(&self) -> Vec<String> {
+        let mut terms = self.search.as_deref().map(tokenize).unwrap_or_default();
+        terms.sort();
+        terms.dedup();
+        terms
+    }
+    pub fn with_tag(mut self, name: &str, values: Vec<String>) -> Filter {
+        self.tags.insert(format!("#{}", name), values);
+        self
+    }
+    pub fn matches(&self, e: &Event) -> bool {
+        if let Some(ids) = &self.ids {
+            if !ids.contains(&e.id) {
+                return false;
+            }
+        }
+        if let Some(authors) = &self.authors {
+            if !authors.contains(&e.public_key) {
+                return false;
+            }
+        }
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&e.kind) {
+                return false;
+            }
+        }
+        if self.since.is_some_and(|since| e.created_at < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| e.created_at > until) {
+            return false;
+        }
+        let terms = self.search_terms();
+        if !terms.is_empty() {
+            let words = tokenize(&e.content);
+            if !terms.iter().all(|t| words.contains(t)) {
+                return false;
+            }
+        }
+        self.tag_conditions()
+            .all(|(name, values)| e.tag_values(name).any(|v| values.iter().any(|x| x == v)))
+    }
+}
+
+/// The number of events matching a set of filters (NIP-45).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Count {
+    pub count: u64,
+    /// Set when `count` is an estimate, as it may be for very large sets.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+/// Splits text into lowercase alphanumeric words for full-text search.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(kind: u32, created_at: u64, tags: Vec<Vec<String>>) -> Event {
+        let mut e = Event::new("pk".to_string(), "hello".to_string(), 0);
+        e.id = format!("{}-{}", kind, created_at);
+        e.kind = kind;
+        e.created_at = created_at;
+        e.tags = tags;
+        e
+    }
+
+    #[test]
+    fn test_matches() {
+        let e = event(1, 100, vec![vec!["t".to_string(), "rust".to_string()]]);
+        assert!(Filter::new().matches(&e));
+        let f = Filter {
+            kinds: Some(vec![1]),
+            since: Some(50),
+            until: Some(100),
+            ..Filter::default()
+        };
+        assert!(f.matches(&e));
+        assert!(!Filter {
+            since: Some(101),
+            ..Filter::default()
+        }
+        .matches(&e));
+        assert!(Filter::new()
+            .with_tag("t", vec!["rust".to_string()])
+            .matches(&e));
+        assert!(!Filter::new()
+            .with_tag("t", vec!["go".to_string()])
+            .matches(&e));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut e = event(1, 100, vec![]);
+        e.content = "Rust, Nostr and relays!".to_string();
+        assert_eq!(tokenize(&e.content), vec!["rust", "nostr", "and", "relays"]);
+        let search = |q: &str| Filter {
+            search: Some(q.to_string()),
+            ..Filter::default()
+        };
+        assert!(search("nostr RUST").matches(&e));
+        assert!(!search("nostr go").matches(&e));
+        assert!(search("  ").matches(&e));
+    }
+
+    #[test]
+    fn test_deserialize_tag_keys() {
+        let f: Filter = serde_json::from_str(r##"{"kinds":[1],"#e":["abc"]}"##).unwrap();
+        assert_eq!(f.kinds, Some(vec![1]));
+        assert_eq!(
+            f.tag_conditions().collect::<Vec<_>>(),
+            vec![("e", &vec!["abc".to_string()])]
+        );
+    }
+}
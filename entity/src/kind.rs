@@ -0,0 +1,47 @@
+//! Well-known event kinds and the ranges that decide how they are stored.
+
+pub const METADATA: u32 = 0;
+pub const TEXT_NOTE: u32 = 1;
+pub const CONTACTS: u32 = 3;
+/// A direct message, encrypted for the receiver (NIP-04).
+pub const ENCRYPTED_DM: u32 = 4;
+/// Asks for earlier events of the same author to be deleted (NIP-09).
+pub const DELETION: u32 = 5;
+/// A repost of a text note (NIP-18).
+pub const REPOST: u32 = 6;
+/// A reaction such as a like (NIP-25).
+pub const REACTION: u32 = 7;
+/// A repost of an event of any other kind (NIP-18).
+pub const GENERIC_REPOST: u32 = 16;
+/// Authors, hashtags, words and threads its owner mutes (NIP-51).
+pub const MUTE_LIST: u32 = 10000;
+/// Events its owner pins to their profile (NIP-51).
+pub const PIN_LIST: u32 = 10001;
+/// Events, articles, hashtags and URLs its owner bookmarked (NIP-51).
+pub const BOOKMARKS: u32 = 10003;
+/// Hashtags and interest sets its owner follows (NIP-51).
+pub const INTERESTS: u32 = 10015;
+/// A relay monitor announcing itself (NIP-66).
+pub const RELAY_MONITOR: u32 = 10166;
+/// A named set of people, e.g. to read a feed of (NIP-51).
+pub const FOLLOW_SET: u32 = 30000;
+/// A named set of bookmarks (NIP-51).
+pub const BOOKMARK_SET: u32 = 30003;
+/// A named set of hashtags (NIP-51).
+pub const INTEREST_SET: u32 = 30015;
+/// A long-form article in Markdown (NIP-23).
+pub const LONG_FORM: u32 = 30023;
+/// A relay monitor's report on a relay (NIP-66).
+pub const RELAY_DISCOVERY: u32 = 30166;
+/// Authorizes a single HTTP request (NIP-98).
+pub const HTTP_AUTH: u32 = 27235;
+
+/// Only the latest event per author and kind is kept.
+pub fn is_replaceable(kind: u32) -> bool {
+    kind == METADATA || kind == CONTACTS || (10_000..20_000).contains(&kind)
+}
+
+/// Only the latest event per author, kind and `d` tag is kept.
+pub fn is_addressable(kind: u32) -> bool {
+    (30_000..40_000).contains(&kind)
+}
@@ -4,16 +4,31 @@ use ulid::Ulid;
 use chrono::prelude::*;
 use chrono::Duration;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+pub mod article;
+pub mod circle;
+pub mod contacts;
+mod filter;
+pub mod kind;
+pub mod lists;
+pub mod mutes;
+pub mod nip05;
+pub mod reaction;
+pub mod thread;
+pub use filter::{tokenize, Count, Filter};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Event {
     pub id: String,
+    #[serde(alias = "pubkey")]
     pub public_key: String,
     pub created_at: u64,
     pub kind: u32,
-    pub tags: String,
+    #[serde(default)]
+    pub tags: Vec<Vec<String>>,
     pub content: String,
     pub sig: String,
     // circle
+    #[serde(default)]
     pub expires_at: u64,
 }
 
@@ -23,32 +38,43 @@ impl Event {
             .duration_since(SystemTime::UNIX_EPOCH)
             .expect("Time went backwards")
             .as_secs();
-        let fmt_str = format!(
-            "[0,{},{},{},{},{}]",
-            self.public_key, self.created_at, self.kind, self.tags, self.content
-        );
+        let fmt_str = self.signing_string();
         let sig_str = fmt_str.clone();
         self.id = crypto::hash(fmt_str);
         self.sig = crypto::sign_message(sig_str, private_key);
     }
+    /// Checks that the id matches the content and that the signature is
+    /// valid. Events carrying a 32 byte x-only key, as produced by other
+    /// nostr software, are checked against the standard JSON serialization.
     pub fn verify(&self) -> bool {
-        let fmt_str = format!(
-            "[0,{},{},{},{},{}]",
-            self.public_key, self.created_at, self.kind, self.tags, self.content
-        );
-        return crypto::verify_message(&fmt_str, &self.sig.clone(), &self.public_key.clone());
+        if self.public_key.len() == 64 {
+            let serialized = serde_json::json!([
+                0,
+                self.public_key,
+                self.created_at,
+                self.kind,
+                self.tags,
+                self.content
+            ])
+            .to_string();
+            return crypto::hash(serialized) == self.id
+                && crypto::verify_hash(&self.id, &self.sig, &self.public_key);
+        }
+        let fmt_str = self.signing_string();
+        crypto::hash(fmt_str.clone()) == self.id
+            && crypto::verify_message(&fmt_str, &self.sig, &self.public_key)
     }
     pub fn new(public_key: String, content: String, expires_at: u64) -> Event {
-        return Event {
+        Event {
             id: "".to_string(),
             public_key,
             created_at: 0,
-            kind: 0,
-            tags: "".to_string(),
+            kind: kind::TEXT_NOTE,
+            tags: Vec::new(),
             content,
             sig: "".to_string(),
             expires_at,
-        };
+        }
     }
     pub fn get_id(&self) -> String {
         self.id.clone()
@@ -60,22 +86,208 @@ impl Event {
             expect("msg").
             as_secs() >=self.expires_at
         }
-        return false
+        false
+    }
+    /// An unsigned request authorization for `method` on the absolute `url`,
+    /// bound to `body` if given (NIP-98).
+    pub fn http_auth(public_key: String, url: &str, method: &str, body: Option<&[u8]>) -> Event {
+        let mut e = Event::new(public_key, String::new(), 0);
+        e.kind = kind::HTTP_AUTH;
+        // the nonce keeps identical requests within a second apart, which
+        // would otherwise be taken for replays
+        e.tags = vec![
+            vec!["u".to_string(), url.to_string()],
+            vec!["method".to_string(), method.to_uppercase()],
+            vec!["nonce".to_string(), Ulid::new().to_string()],
+        ];
+        if let Some(body) = body {
+            e.tags
+                .push(vec!["payload".to_string(), crypto::hash_bytes(body)]);
+        }
+        e
+    }
+    /// Proof of work as the number of leading zero bits of the id (NIP-13).
+    pub fn difficulty(&self) -> u32 {
+        let mut bits = 0;
+        for c in self.id.chars() {
+            match c.to_digit(16) {
+                Some(0) => bits += 4,
+                Some(d) => return bits + d.leading_zeros() - 28,
+                None => return bits,
+            }
+        }
+        bits
+    }
+    /// Values of all tags named `name`, e.g. the referenced ids of `e` tags.
+    pub fn tag_values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.tags
+            .iter()
+            .filter(move |t| t.len() > 1 && t[0] == name)
+            .map(|t| t[1].as_str())
+    }
+    /// Identifies the slot a replaceable or addressable event occupies:
+    /// `kind:public_key` or `kind:public_key:d`. Newer events with the same
+    /// address supersede older ones; regular events have no address.
+    pub fn address(&self) -> Option<String> {
+        if kind::is_replaceable(self.kind) {
+            Some(format!("{}:{}", self.kind, self.public_key))
+        } else if kind::is_addressable(self.kind) {
+            let d = self.tag_values("d").next().unwrap_or("");
+            Some(format!("{}:{}:{}", self.kind, self.public_key, d))
+        } else {
+            None
+        }
+    }
+    /// Whether `self` wins over `other` for the same address: the newer
+    /// event, or the lower id when both were created in the same second.
+    pub fn supersedes(&self, other: &Event) -> bool {
+        self.created_at > other.created_at
+            || (self.created_at == other.created_at && self.id < other.id)
+    }
+    fn signing_string(&self) -> String {
+        let tags = serde_json::to_string(&self.tags).expect("tags serialize");
+        format!(
+            "[0,{},{},{},{},{}]",
+            self.public_key, self.created_at, self.kind, tags, self.content
+        )
+    }
+}
+
+/// What a user may do on the relay. Records from before roles existed are
+/// members.
+#[derive(
+    Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Admin,
+    #[default]
+    Member,
+    ReadOnly,
+    /// Publishes on its own, but leaves its user entry to the admins.
+    Bot,
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "admin" => Ok(Role::Admin),
+            "member" => Ok(Role::Member),
+            "read-only" => Ok(Role::ReadOnly),
+            "bot" => Ok(Role::Bot),
+            _ => Err("expected admin, member, read-only or bot".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Admin => "admin",
+            Role::Member => "member",
+            Role::ReadOnly => "read-only",
+            Role::Bot => "bot",
+        };
+        f.write_str(name)
     }
 }
 
+/// A registered public key, derived from its latest profile event.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
     pub name: String,
     pub public_key: String,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub about: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub website: Option<String>,
+    /// The internet identifier the user claims, see [`nip05`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nip05: Option<String>,
+    /// `created_at` of the profile event the entry was derived from.
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+/// The content of a profile event; other fields are ignored.
+#[derive(Deserialize)]
+struct Metadata {
+    name: Option<String>,
+    display_name: Option<String>,
+    about: Option<String>,
+    picture: Option<String>,
+    website: Option<String>,
+    nip05: Option<String>,
 }
 
 impl User {
     pub fn new(name: String, public_key: String) -> User {
-        return User { name, public_key };
+        User {
+            name,
+            public_key,
+            role: Role::default(),
+            display_name: None,
+            about: None,
+            picture: None,
+            website: None,
+            nip05: None,
+            updated_at: 0,
+        }
+    }
+    /// The entry described by a signed profile event (kind 0), which proves
+    /// that the registrant owns the key. The name must be 1 to 32 of
+    /// `a-z0-9._-`, as in the local part of a NIP-05 identifier.
+    pub fn from_profile(e: &Event) -> Result<User, String> {
+        if e.kind != kind::METADATA {
+            return Err(format!("expected a profile event, got kind {}", e.kind));
+        }
+        if !e.verify() {
+            return Err(format!("invalid id or signature for event {}", e.id));
+        }
+        let metadata: Metadata = serde_json::from_str(&e.content)
+            .map_err(|err| format!("invalid profile metadata: {}", err))?;
+        let name = metadata.name.ok_or("profile metadata has no name")?;
+        if !is_valid_name(&name) {
+            return Err(format!(
+                "invalid name {:?}: use 1 to 32 of a-z, 0-9, '.', '_' and '-'",
+                name
+            ));
+        }
+        for url in [&metadata.picture, &metadata.website].into_iter().flatten() {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(format!("{} is not an http(s) URL", url));
+            }
+        }
+        if let Some(id) = &metadata.nip05 {
+            id.parse::<nip05::Identifier>()?;
+        }
+        Ok(User {
+            name,
+            public_key: e.public_key.clone(),
+            role: Role::default(),
+            display_name: metadata.display_name,
+            about: metadata.about,
+            picture: metadata.picture,
+            website: metadata.website,
+            nip05: metadata.nip05,
+            updated_at: e.created_at,
+        })
     }
 }
 
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '.' | '_' | '-'))
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Session {
@@ -84,10 +296,17 @@ pub struct Session {
     public_key:String,
     key_pair: KeyPair,
     expires_at: u64,
+    /// Whether requests of this session apply the owner's mute list.
+    #[serde(default)]
+    apply_mutes: bool,
 }
 
-impl  Session {
-    pub fn new(public_key: String,expires_at:u64) -> Session {
+impl Session {
+    pub fn new(public_key: String, expires_at: u64) -> Session {
+        Session::try_new(public_key, expires_at).expect("valid public key")
+    }
+    /// Like [`Session::new`], but fails on a malformed public key.
+    pub fn try_new(public_key: String, expires_at: u64) -> Result<Session, crypto::CryptoError> {
         let ulid = Ulid::new();
         let expires = if expires_at == 0 {
              (Local::now() + Duration::hours(1)).timestamp().try_into().unwrap()
@@ -95,8 +314,22 @@ impl  Session {
             expires_at
         };
         let key_pair = KeyPair::generate();
-        let shared_secret = crypto::generate_shared_secret(&public_key, key_pair.private_key());
-        return Session { id: ulid.to_string(), shared_secret, key_pair, public_key, expires_at: expires }
+        let shared_secret = crypto::generate_shared_secret(&public_key, key_pair.private_key())?;
+        Ok(Session {
+            id: ulid.to_string(),
+            shared_secret,
+            key_pair,
+            public_key,
+            expires_at: expires,
+            apply_mutes: false,
+        })
+    }
+    pub fn with_apply_mutes(mut self, apply_mutes: bool) -> Session {
+        self.apply_mutes = apply_mutes;
+        self
+    }
+    pub fn apply_mutes(&self) -> bool {
+        self.apply_mutes
     }
     pub fn expired(&self) -> bool {
         if self.expires_at != 0 {
@@ -105,11 +338,14 @@ impl  Session {
             expect("msg").
             as_secs() >=self.expires_at
         }
-        return false
+        false
     }
     pub fn get_id(&self) -> String {
         self.id.clone()
     }
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
 }
 
 
@@ -123,12 +359,119 @@ pub struct KeyPair {
 impl KeyPair {
     pub fn generate() -> KeyPair {
         let (private_key, public_key) = crypto::create_key_pair();
-        return KeyPair { public_key, private_key }
+        KeyPair {
+            public_key,
+            private_key,
+        }
+    }
+    /// The key pair of a hex encoded private key.
+    pub fn from_private_key(private_key: String) -> Result<KeyPair, String> {
+        if private_key.len() != 64 || !private_key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err("invalid private key".to_string());
+        }
+        Ok(KeyPair {
+            public_key: crypto::get_public_key(private_key.clone()),
+            private_key,
+        })
     }
     pub fn public_key(&self) -> String {
-        return self.public_key.clone()
+        self.public_key.clone()
     }
     pub fn private_key(&self) -> String {
-        return self.private_key.clone()
+        self.private_key.clone()
     }
-}
\ No newline at end of file
+}
+
+/// Body of every error response of the HTTP API.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorBody {
+    /// Stable, machine-readable, e.g. `not_found`.
+    pub code: String,
+    pub message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let kp = KeyPair::generate();
+        let mut e = Event::new(kp.public_key(), "hello".to_string(), 0);
+        e.tags = vec![vec!["t".to_string(), "test".to_string()]];
+        e.sign(kp.private_key());
+        assert!(e.verify());
+        let mut forged = e.clone();
+        forged.content = "bye".to_string();
+        assert!(!forged.verify());
+        forged.id = "00".to_string();
+        assert!(!forged.verify());
+    }
+
+    #[test]
+    fn test_profile() {
+        let kp = KeyPair::generate();
+        let profile = |content: &str| {
+            let mut e = Event::new(kp.public_key(), content.to_string(), 0);
+            e.kind = kind::METADATA;
+            e.sign(kp.private_key());
+            e
+        };
+        let e = profile(
+            r#"{"name":"alice","display_name":"Alice","website":"https://a.example","lud16":"x"}"#,
+        );
+        let user = User::from_profile(&e).unwrap();
+        assert_eq!(user.name, "alice");
+        assert_eq!(user.public_key, kp.public_key());
+        assert_eq!(user.display_name.as_deref(), Some("Alice"));
+        assert_eq!(user.updated_at, e.created_at);
+
+        assert!(User::from_profile(&profile(r#"{"name":"Alice Smith"}"#)).is_err());
+        assert!(User::from_profile(&profile(r#"{"about":"no name"}"#)).is_err());
+        assert!(User::from_profile(&profile(r#"{"name":"a","picture":"javascript:x"}"#)).is_err());
+        assert!(User::from_profile(&profile("not json")).is_err());
+        assert!(User::from_profile(&profile(r#"{"name":"a","nip05":"a@"}"#)).is_err());
+        let mut forged = e.clone();
+        forged.public_key = KeyPair::generate().public_key();
+        assert!(User::from_profile(&forged).is_err());
+        let mut note = e;
+        note.kind = kind::TEXT_NOTE;
+        assert!(User::from_profile(&note).is_err());
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let mut e = Event::new("pk".to_string(), String::new(), 0);
+        e.id = "000f".to_string();
+        assert_eq!(e.difficulty(), 12);
+        e.id = "002a".to_string();
+        assert_eq!(e.difficulty(), 10);
+        e.id = "8".to_string();
+        assert_eq!(e.difficulty(), 0);
+    }
+
+    #[test]
+    fn test_verify_standard_event() {
+        let kp = KeyPair::generate();
+        let pubkey = kp.public_key()[2..].to_string();
+        let serialized =
+            serde_json::json!([0, pubkey, 1700000000, 1, [["t", "nostr"]], "gm \"world\"\n"]);
+        let id = crypto::hash(serialized.to_string());
+        let sig = crypto::sign_hash(&id, kp.private_key());
+        let line = serde_json::json!({
+            "id": id,
+            "pubkey": pubkey,
+            "created_at": 1700000000,
+            "kind": 1,
+            "tags": [["t", "nostr"]],
+            "content": "gm \"world\"\n",
+            "sig": sig,
+        });
+        let e: Event = serde_json::from_value(line).unwrap();
+        assert_eq!(e.expires_at, 0);
+        assert!(e.verify());
+        let mut forged = e.clone();
+        forged.created_at += 1;
+        assert!(!forged.verify());
+    }
+}
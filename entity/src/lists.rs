@@ -0,0 +1,307 @@
+//! Generic lists (NIP-51): pinned notes, bookmarks and interests are
+//! replaceable, so a user has one of each; bookmark sets, interest sets and
+//! people sets are addressable, named by their `d` tag, so a user can keep
+//! several. Like a [`MuteList`](crate::mutes::MuteList), a list has public
+//! items in its tags and private ones encrypted into its content.
+
+use crate::{kind, Event};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Whether `kind` is one of the lists modelled here.
+pub fn is_list(kind: u32) -> bool {
+    matches!(
+        kind,
+        kind::PIN_LIST
+            | kind::BOOKMARKS
+            | kind::INTERESTS
+            | kind::FOLLOW_SET
+            | kind::BOOKMARK_SET
+            | kind::INTEREST_SET
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub enum Item {
+    Event(String),
+    /// `kind:public_key:d` of an addressable event, e.g. an article.
+    Address(String),
+    Person(String),
+    Hashtag(String),
+    Url(String),
+}
+
+impl Item {
+    fn from_tag(tag: &[String]) -> Option<Item> {
+        let value = tag.get(1)?.clone();
+        match tag[0].as_str() {
+            "e" => Some(Item::Event(value)),
+            "a" => Some(Item::Address(value)),
+            "p" => Some(Item::Person(value)),
+            "t" => Some(Item::Hashtag(value.to_lowercase())),
+            "r" => Some(Item::Url(value)),
+            _ => None,
+        }
+    }
+
+    /// Whether lists of `kind` may hold this item.
+    pub fn fits(&self, kind: u32) -> bool {
+        match kind {
+            kind::PIN_LIST => matches!(self, Item::Event(_)),
+            kind::BOOKMARKS | kind::BOOKMARK_SET => !matches!(self, Item::Person(_)),
+            kind::INTERESTS => matches!(self, Item::Hashtag(_) | Item::Address(_)),
+            kind::INTEREST_SET => matches!(self, Item::Hashtag(_)),
+            kind::FOLLOW_SET => matches!(self, Item::Person(_)),
+            _ => false,
+        }
+    }
+}
+
+impl TryFrom<Vec<String>> for Item {
+    type Error = String;
+    fn try_from(tag: Vec<String>) -> Result<Item, String> {
+        match tag.first() {
+            Some(_) => Item::from_tag(&tag).ok_or_else(|| format!("invalid list item {:?}", tag)),
+            None => Err("empty list item".to_string()),
+        }
+    }
+}
+
+impl From<Item> for Vec<String> {
+    fn from(item: Item) -> Vec<String> {
+        let (name, value) = match item {
+            Item::Event(v) => ("e", v),
+            Item::Address(v) => ("a", v),
+            Item::Person(v) => ("p", v),
+            Item::Hashtag(v) => ("t", v),
+            Item::Url(v) => ("r", v),
+        };
+        vec![name.to_string(), value]
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Event(v) => write!(f, "event {}", v),
+            Item::Address(v) => write!(f, "address {}", v),
+            Item::Person(v) => write!(f, "person {}", v),
+            Item::Hashtag(v) => write!(f, "hashtag #{}", v),
+            Item::Url(v) => write!(f, "url {}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct List {
+    pub kind: u32,
+    /// The name of a set; lists of replaceable kinds have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+    pub public: Vec<Item>,
+    /// Empty until [`List::decrypt`] read them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub private: Vec<Item>,
+    /// The encrypted private items while they are not decrypted.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content: String,
+    /// Tags that are not items, such as `title`, kept as they are.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other: Vec<Vec<String>>,
+}
+
+impl List {
+    /// An empty list of `kind`; sets need a name, other lists must not
+    /// have one.
+    pub fn new(kind: u32, d: Option<String>) -> Result<List, String> {
+        if !is_list(kind) {
+            return Err(format!("kind {} is not a list", kind));
+        }
+        match (kind::is_addressable(kind), &d) {
+            (true, None) => return Err(format!("lists of kind {} need a name", kind)),
+            (false, Some(_)) => return Err(format!("lists of kind {} have no name", kind)),
+            _ => {}
+        }
+        Ok(List {
+            kind,
+            d,
+            public: Vec::new(),
+            private: Vec::new(),
+            content: String::new(),
+            other: Vec::new(),
+        })
+    }
+
+    pub fn from_event(e: &Event) -> Result<List, String> {
+        if !is_list(e.kind) {
+            return Err(format!("expected a list, got kind {}", e.kind));
+        }
+        let d = match kind::is_addressable(e.kind) {
+            true => Some(e.tag_values("d").next().unwrap_or("").to_string()),
+            false => None,
+        };
+        let mut list = List::new(e.kind, d)?;
+        for tag in e.tags.iter().filter(|t| !t.is_empty() && t[0] != "d") {
+            match Item::from_tag(tag) {
+                Some(item) => list.public.push(item),
+                None => list.other.push(tag.clone()),
+            }
+        }
+        list.content = e.content.clone();
+        Ok(list)
+    }
+
+    /// The value of the `title` tag sets may carry.
+    pub fn title(&self) -> Option<&str> {
+        self.other
+            .iter()
+            .find(|t| t.len() > 1 && t[0] == "title")
+            .map(|t| t[1].as_str())
+    }
+
+    /// Reads the private items with the owner's keys.
+    pub fn decrypt(&mut self, public_key: &str, private_key: String) -> Result<(), String> {
+        if self.content.is_empty() {
+            return Ok(());
+        }
+        let tags = decrypt_tags(&self.content, public_key, private_key)?;
+        self.private = tags.iter().filter_map(|t| Item::from_tag(t)).collect();
+        self.content.clear();
+        Ok(())
+    }
+
+    pub fn contains(&self, item: &Item) -> bool {
+        self.public.contains(item) || self.private.contains(item)
+    }
+
+    /// Adds `item` publicly or privately; false if it is already listed.
+    pub fn add(&mut self, item: Item, private: bool) -> Result<bool, String> {
+        if !item.fits(self.kind) {
+            return Err(format!("lists of kind {} cannot hold {}", self.kind, item));
+        }
+        if self.contains(&item) {
+            return Ok(false);
+        }
+        match private {
+            true => self.private.push(item),
+            false => self.public.push(item),
+        }
+        Ok(true)
+    }
+
+    /// Removes `item` wherever it is listed; false if it was not.
+    pub fn remove(&mut self, item: &Item) -> bool {
+        let before = self.public.len() + self.private.len();
+        self.public.retain(|i| i != item);
+        self.private.retain(|i| i != item);
+        self.public.len() + self.private.len() != before
+    }
+
+    /// The unsigned event publishing this list, encrypting the private
+    /// items for the owner. Private items still encrypted are kept.
+    pub fn to_event(&self, public_key: String, private_key: String) -> Result<Event, String> {
+        let private: Vec<Vec<String>> = self.private.iter().cloned().map(Vec::from).collect();
+        let content = seal(&private, &self.content, &public_key, private_key)?;
+        let mut e = Event::new(public_key, content, 0);
+        e.kind = self.kind;
+        e.tags = self
+            .d
+            .iter()
+            .map(|d| vec!["d".to_string(), d.clone()])
+            .chain(self.other.iter().cloned())
+            .chain(self.public.iter().cloned().map(Vec::from))
+            .collect();
+        Ok(e)
+    }
+}
+
+/// A list as the relay stores it, with the event it was read from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Published {
+    #[serde(flatten)]
+    pub list: List,
+    pub event: Event,
+}
+
+/// The content carrying `private` tags: the encrypted JSON array of them,
+/// or `sealed` when there are none because they were never decrypted.
+pub(crate) fn seal(
+    private: &[Vec<String>],
+    sealed: &str,
+    public_key: &str,
+    private_key: String,
+) -> Result<String, String> {
+    match (private.is_empty(), sealed.is_empty()) {
+        (true, _) => Ok(sealed.to_string()),
+        (false, true) => {
+            let json = serde_json::to_string(private).expect("tags serialize");
+            crypto::encrypt(&json, public_key, private_key).map_err(|e| e.to_string())
+        }
+        (false, false) => Err("decrypt the private entries before changing them".to_string()),
+    }
+}
+
+/// The private tags encrypted into `content` by [`seal`].
+pub(crate) fn decrypt_tags(
+    content: &str,
+    public_key: &str,
+    private_key: String,
+) -> Result<Vec<Vec<String>>, String> {
+    let json = crypto::decrypt(content, public_key, private_key).map_err(|e| e.to_string())?;
+    serde_json::from_str(&json).map_err(|e| format!("invalid private entries: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+
+    #[test]
+    fn test_new() {
+        assert!(List::new(kind::BOOKMARKS, None).is_ok());
+        assert!(List::new(kind::BOOKMARKS, Some("x".to_string())).is_err());
+        assert!(List::new(kind::FOLLOW_SET, None).is_err());
+        assert!(List::new(kind::TEXT_NOTE, None).is_err());
+
+        let mut pins = List::new(kind::PIN_LIST, None).unwrap();
+        assert!(pins.add(Item::Hashtag("rust".to_string()), false).is_err());
+        assert_eq!(pins.add(Item::Event("n".to_string()), false), Ok(true));
+        assert_eq!(pins.add(Item::Event("n".to_string()), true), Ok(false));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let kp = KeyPair::generate();
+        let mut set = List::new(kind::FOLLOW_SET, Some("friends".to_string())).unwrap();
+        set.other
+            .push(vec!["title".to_string(), "Friends".to_string()]);
+        set.add(Item::Person("bob".to_string()), false).unwrap();
+        set.add(Item::Person("carol".to_string()), true).unwrap();
+        let e = set.to_event(kp.public_key(), kp.private_key()).unwrap();
+        assert_eq!(
+            e.tags,
+            vec![
+                vec!["d", "friends"],
+                vec!["title", "Friends"],
+                vec!["p", "bob"]
+            ]
+        );
+        assert!(!e.content.contains("carol"));
+
+        let mut read = List::from_event(&e).unwrap();
+        assert_eq!(read.title(), Some("Friends"));
+        assert!(read.add(Item::Person("dave".to_string()), true).is_ok());
+        assert!(read.to_event(kp.public_key(), kp.private_key()).is_err());
+
+        let mut read = List::from_event(&e).unwrap();
+        read.decrypt(&kp.public_key(), kp.private_key()).unwrap();
+        assert_eq!(read, set);
+        assert!(read.remove(&Item::Person("carol".to_string())));
+        assert!(!read.remove(&Item::Person("carol".to_string())));
+
+        let json = serde_json::to_value(&read).unwrap();
+        assert_eq!(json["public"], serde_json::json!([["p", "bob"]]));
+        assert_eq!(serde_json::from_value::<List>(json).unwrap(), read);
+    }
+}
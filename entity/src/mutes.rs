@@ -0,0 +1,199 @@
+//! Mute lists (NIP-51): a replaceable event of kind [`kind::MUTE_LIST`]
+//! naming the authors (`p`), hashtags (`t`), words (`word`) and threads
+//! (`e`) its owner does not want to see. Public entries are tags; private
+//! ones are the same tags as a JSON array, encrypted by the owner for
+//! themselves (NIP-04) into the content, so only the owner can apply them.
+
+use crate::{kind, lists, Event};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mute {
+    Author(String),
+    Hashtag(String),
+    /// Matched case-insensitively anywhere in the content.
+    Word(String),
+    /// The event with this id and everything referencing it.
+    Thread(String),
+}
+
+impl Mute {
+    fn from_tag(tag: &[String]) -> Option<Mute> {
+        let value = tag.get(1)?.clone();
+        match tag[0].as_str() {
+            "p" => Some(Mute::Author(value)),
+            "t" => Some(Mute::Hashtag(value.to_lowercase())),
+            "word" => Some(Mute::Word(value.to_lowercase())),
+            "e" => Some(Mute::Thread(value)),
+            _ => None,
+        }
+    }
+    fn to_tag(&self) -> Vec<String> {
+        let (name, value) = match self {
+            Mute::Author(v) => ("p", v),
+            Mute::Hashtag(v) => ("t", v),
+            Mute::Word(v) => ("word", v),
+            Mute::Thread(v) => ("e", v),
+        };
+        vec![name.to_string(), value.clone()]
+    }
+    pub fn matches(&self, e: &Event) -> bool {
+        match self {
+            Mute::Author(pk) => {
+                crypto::x_only(pk).eq_ignore_ascii_case(crypto::x_only(&e.public_key))
+            }
+            Mute::Hashtag(t) => e.tag_values("t").any(|v| v.eq_ignore_ascii_case(t)),
+            Mute::Word(w) => e.content.to_lowercase().contains(&w.to_lowercase()),
+            Mute::Thread(id) => e.id == *id || e.tag_values("e").any(|v| v == id),
+        }
+    }
+}
+
+impl fmt::Display for Mute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mute::Author(v) => write!(f, "author {}", v),
+            Mute::Hashtag(v) => write!(f, "hashtag #{}", v),
+            Mute::Word(v) => write!(f, "word {}", v),
+            Mute::Thread(v) => write!(f, "thread {}", v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MuteList {
+    pub public: Vec<Mute>,
+    /// Empty until [`MuteList::decrypt`] read them.
+    pub private: Vec<Mute>,
+    /// The encrypted private entries while they are not decrypted.
+    pub content: String,
+}
+
+impl MuteList {
+    pub fn from_event(e: &Event) -> Result<MuteList, String> {
+        if e.kind != kind::MUTE_LIST {
+            return Err(format!("expected a mute list, got kind {}", e.kind));
+        }
+        Ok(MuteList {
+            public: parse_tags(&e.tags),
+            private: Vec::new(),
+            content: e.content.clone(),
+        })
+    }
+
+    /// Reads the private entries with the owner's keys.
+    pub fn decrypt(&mut self, public_key: &str, private_key: String) -> Result<(), String> {
+        if self.content.is_empty() {
+            return Ok(());
+        }
+        let tags = lists::decrypt_tags(&self.content, public_key, private_key)?;
+        self.private = parse_tags(&tags);
+        self.content.clear();
+        Ok(())
+    }
+
+    pub fn contains(&self, mute: &Mute) -> bool {
+        self.public.contains(mute) || self.private.contains(mute)
+    }
+
+    /// Adds `mute` publicly or privately; false if it is already listed.
+    pub fn add(&mut self, mute: Mute, private: bool) -> bool {
+        if self.contains(&mute) {
+            return false;
+        }
+        match private {
+            true => self.private.push(mute),
+            false => self.public.push(mute),
+        }
+        true
+    }
+
+    /// Removes `mute` wherever it is listed; false if it was not.
+    pub fn remove(&mut self, mute: &Mute) -> bool {
+        let before = self.public.len() + self.private.len();
+        self.public.retain(|m| m != mute);
+        self.private.retain(|m| m != mute);
+        self.public.len() + self.private.len() != before
+    }
+
+    /// Whether any known entry hides `e`.
+    pub fn mutes(&self, e: &Event) -> bool {
+        self.public
+            .iter()
+            .chain(&self.private)
+            .any(|m| m.matches(e))
+    }
+
+    /// The unsigned event publishing this list, encrypting the private
+    /// entries for the owner. Private entries still encrypted are kept.
+    pub fn to_event(&self, public_key: String, private_key: String) -> Result<Event, String> {
+        let private: Vec<Vec<String>> = self.private.iter().map(Mute::to_tag).collect();
+        let content = lists::seal(&private, &self.content, &public_key, private_key)?;
+        let mut e = Event::new(public_key, content, 0);
+        e.kind = kind::MUTE_LIST;
+        e.tags = self.public.iter().map(Mute::to_tag).collect();
+        Ok(e)
+    }
+}
+
+fn parse_tags(tags: &[Vec<String>]) -> Vec<Mute> {
+    tags.iter().filter_map(|t| Mute::from_tag(t)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyPair;
+
+    fn note(author: &str, content: &str, tags: Vec<Vec<String>>) -> Event {
+        let mut e = Event::new(author.to_string(), content.to_string(), 0);
+        e.id = "n".to_string();
+        e.tags = tags;
+        e
+    }
+
+    #[test]
+    fn test_mutes() {
+        let tag = |name: &str, value: &str| vec![name.to_string(), value.to_string()];
+        let compressed = format!("02{}", "ab".repeat(32));
+        assert!(Mute::Author("ab".repeat(32)).matches(&note(&compressed, "", vec![])));
+        assert!(Mute::Hashtag("rust".to_string()).matches(&note("a", "", vec![tag("t", "Rust")])));
+        assert!(Mute::Word("spoiler".to_string()).matches(&note("a", "Big SPOILER ahead", vec![])));
+        assert!(Mute::Thread("r".to_string()).matches(&note("a", "", vec![tag("e", "r")])));
+        assert!(!Mute::Thread("r".to_string()).matches(&note("a", "", vec![])));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let kp = KeyPair::generate();
+        let mut list = MuteList::default();
+        assert!(list.add(Mute::Hashtag("politics".to_string()), false));
+        assert!(list.add(Mute::Author("mallory".to_string()), true));
+        assert!(!list.add(Mute::Author("mallory".to_string()), false));
+        let e = list.to_event(kp.public_key(), kp.private_key()).unwrap();
+        assert_eq!(e.tags, vec![vec!["t", "politics"]]);
+        assert!(!e.content.contains("mallory"));
+
+        let mut read = MuteList::from_event(&e).unwrap();
+        assert!(read.private.is_empty());
+        // private entries survive an update without the key
+        read.add(Mute::Word("crypto".to_string()), false);
+        let update = read.to_event(kp.public_key(), kp.private_key()).unwrap();
+        assert_eq!(update.content, e.content);
+        assert!(read.add(Mute::Word("nft".to_string()), true));
+        assert!(read.to_event(kp.public_key(), kp.private_key()).is_err());
+
+        let mut read = MuteList::from_event(&update).unwrap();
+        read.decrypt(&kp.public_key(), kp.private_key()).unwrap();
+        assert_eq!(read.private, vec![Mute::Author("mallory".to_string())]);
+        assert!(read.mutes(&note("mallory", "hi", vec![])));
+        assert!(read.remove(&Mute::Author("mallory".to_string())));
+        assert!(!read.remove(&Mute::Author("mallory".to_string())));
+        assert_eq!(
+            read.to_event(kp.public_key(), kp.private_key())
+                .unwrap()
+                .content,
+            ""
+        );
+    }
+}
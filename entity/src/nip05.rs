@@ -0,0 +1,121 @@
+//! Internet identifiers (NIP-05): `name@domain` refers to the public key
+//! that the domain's `/.well-known/nostr.json` lists under `name`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identifier {
+    pub name: String,
+    pub domain: String,
+}
+
+impl Identifier {
+    /// Where the domain serves its document. Local hosts are asked over
+    /// plain HTTP, so that a relay on the same machine can be checked.
+    pub fn url(&self) -> String {
+        let host = self.domain.rsplit_once(':').map_or(&*self.domain, |h| h.0);
+        let local = host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+        let scheme = if local { "http" } else { "https" };
+        format!(
+            "{}://{}/.well-known/nostr.json?name={}",
+            scheme, self.domain, self.name
+        )
+    }
+}
+
+impl FromStr for Identifier {
+    type Err = String;
+    /// Parses `name@domain`; a bare domain stands for `_@domain`.
+    fn from_str(s: &str) -> Result<Identifier, String> {
+        let (name, domain) = s.split_once('@').unwrap_or(("_", s));
+        let name = name.to_lowercase();
+        if !crate::is_valid_name(&name) {
+            return Err(format!("invalid name in identifier {}", s));
+        }
+        if domain.is_empty() || domain.contains(['/', '@', '?', '#']) {
+            return Err(format!("invalid domain in identifier {}", s));
+        }
+        Ok(Identifier {
+            name,
+            domain: domain.to_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.domain)
+    }
+}
+
+/// The `/.well-known/nostr.json` document, with x-only public keys.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct Document {
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+    /// Relays to find each public key on.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub relays: BTreeMap<String, Vec<String>>,
+}
+
+impl Document {
+    /// Checks that the document names `public_key`, in either form, as `id`.
+    pub fn verify(&self, id: &Identifier, public_key: &str) -> Result<(), String> {
+        match self.names.get(&id.name) {
+            Some(pk) if pk.eq_ignore_ascii_case(crypto::x_only(public_key)) => Ok(()),
+            Some(pk) => Err(format!("{} belongs to {}", id, pk)),
+            None => Err(format!("{} is not known to {}", id.name, id.domain)),
+        }
+    }
+
+    pub fn relays(&self, public_key: &str) -> &[String] {
+        self.relays
+            .get(crypto::x_only(public_key))
+            .map_or(&[], |r| r.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifier() {
+        let id: Identifier = "Alice@Relay.example.com".parse().unwrap();
+        assert_eq!(id.to_string(), "alice@relay.example.com");
+        assert_eq!(
+            id.url(),
+            "https://relay.example.com/.well-known/nostr.json?name=alice"
+        );
+        let local: Identifier = "bob@127.0.0.1:3000".parse().unwrap();
+        assert_eq!(
+            local.url(),
+            "http://127.0.0.1:3000/.well-known/nostr.json?name=bob"
+        );
+        assert_eq!("example.com".parse::<Identifier>().unwrap().name, "_");
+        assert!("a b@example.com".parse::<Identifier>().is_err());
+        assert!("alice@".parse::<Identifier>().is_err());
+        assert!("alice@example.com/x".parse::<Identifier>().is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let compressed = format!("02{}", "ab".repeat(32));
+        let document: Document = serde_json::from_str(&format!(
+            r#"{{"names":{{"alice":"{}"}},"relays":{{"{}":["wss://relay.example.com"]}}}}"#,
+            "ab".repeat(32),
+            "ab".repeat(32)
+        ))
+        .unwrap();
+        let alice: Identifier = "alice@example.com".parse().unwrap();
+        assert!(document.verify(&alice, &compressed).is_ok());
+        assert!(document.verify(&alice, &"cd".repeat(32)).is_err());
+        let bob: Identifier = "bob@example.com".parse().unwrap();
+        assert!(document.verify(&bob, &compressed).is_err());
+        assert_eq!(document.relays(&compressed), ["wss://relay.example.com"]);
+    }
+}
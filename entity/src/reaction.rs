@@ -0,0 +1,151 @@
+//! Reactions (NIP-25), reposts (NIP-18) and the engagement they add up to.
+//!
+//! Both point at their target with `e` tags; when there are several, the
+//! last one is the target. A reaction's content is `+` (a like, also used
+//! for empty content), `-` or an emoji. A text note counts as a reply to
+//! the event it directly answers, see [`thread::parent`].
+
+use crate::{kind, thread, Event};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engagement {
+    /// The normalized reaction content.
+    Reaction(String),
+    Repost,
+    Reply,
+}
+
+/// The event `e` engages with and how, if it is a reaction, a repost or a
+/// reply.
+pub fn engagement(e: &Event) -> Option<(String, Engagement)> {
+    let last_ref = || e.tag_values("e").last().map(str::to_string);
+    match e.kind {
+        kind::REACTION => {
+            let content = match e.content.trim() {
+                "" => "+".to_string(),
+                c => c.to_string(),
+            };
+            last_ref().map(|id| (id, Engagement::Reaction(content)))
+        }
+        kind::REPOST | kind::GENERIC_REPOST => last_ref().map(|id| (id, Engagement::Repost)),
+        kind::TEXT_NOTE => thread::parent(e).map(|id| (id, Engagement::Reply)),
+        _ => None,
+    }
+}
+
+/// An unsigned reaction by `public_key` with `content` to `to`.
+pub fn reaction(public_key: String, content: String, to: &Event) -> Event {
+    let mut e = Event::new(public_key, content, 0);
+    e.kind = kind::REACTION;
+    e.tags = target_tags(to);
+    e
+}
+
+/// An unsigned repost of `to` by `public_key`. Text notes are reposted with
+/// kind 6 and everything else with the generic kind 16; the content carries
+/// the reposted event.
+pub fn repost(public_key: String, to: &Event) -> Event {
+    let content = serde_json::to_string(to).expect("events serialize");
+    let mut e = Event::new(public_key, content, 0);
+    e.tags = target_tags(to);
+    if to.kind == kind::TEXT_NOTE {
+        e.kind = kind::REPOST;
+    } else {
+        e.kind = kind::GENERIC_REPOST;
+        e.tags.push(vec!["k".to_string(), to.kind.to_string()]);
+    }
+    e
+}
+
+fn target_tags(to: &Event) -> Vec<Vec<String>> {
+    vec![
+        vec!["e".to_string(), to.id.clone()],
+        vec!["p".to_string(), to.public_key.clone()],
+    ]
+}
+
+/// Counters of the engagement with one event.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Reactions by content.
+    #[serde(default)]
+    pub reactions: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub reposts: u64,
+    #[serde(default)]
+    pub replies: u64,
+}
+
+impl Stats {
+    pub fn add(&mut self, engagement: &Engagement) {
+        match engagement {
+            Engagement::Reaction(content) => {
+                *self.reactions.entry(content.clone()).or_default() += 1
+            }
+            Engagement::Repost => self.reposts += 1,
+            Engagement::Reply => self.replies += 1,
+        }
+    }
+    /// Takes back what [`Stats::add`] counted; counters never drop below
+    /// zero.
+    pub fn remove(&mut self, engagement: &Engagement) {
+        match engagement {
+            Engagement::Reaction(content) => {
+                if let Some(n) = self.reactions.get_mut(content) {
+                    *n -= 1;
+                    if *n == 0 {
+                        self.reactions.remove(content);
+                    }
+                }
+            }
+            Engagement::Repost => self.reposts = self.reposts.saturating_sub(1),
+            Engagement::Reply => self.replies = self.replies.saturating_sub(1),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self == &Stats::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engagement() {
+        let mut note = Event::new("alice".to_string(), "hi".to_string(), 0);
+        note.id = "n".to_string();
+        let like = reaction("bob".to_string(), String::new(), &note);
+        let boost = repost("bob".to_string(), &note);
+        let answer = thread::reply("bob".to_string(), "yo".to_string(), &note);
+        assert_eq!(boost.kind, kind::REPOST);
+
+        let mut stats = Stats::default();
+        for e in [&like, &boost, &answer] {
+            let (target, engagement) = engagement(e).unwrap();
+            assert_eq!(target, "n");
+            stats.add(&engagement);
+        }
+        stats.add(&Engagement::Reaction("🤙".to_string()));
+        assert_eq!(stats.reactions["+"], 1);
+        assert_eq!((stats.reposts, stats.replies), (1, 1));
+
+        stats.remove(&Engagement::Reaction("+".to_string()));
+        stats.remove(&Engagement::Reaction("+".to_string()));
+        stats.remove(&Engagement::Reaction("🤙".to_string()));
+        stats.remove(&Engagement::Repost);
+        stats.remove(&Engagement::Repost);
+        stats.remove(&Engagement::Reply);
+        assert!(stats.is_empty());
+
+        let mut profile = note.clone();
+        profile.kind = kind::METADATA;
+        assert_eq!(
+            repost("bob".to_string(), &profile).kind,
+            kind::GENERIC_REPOST
+        );
+        assert!(engagement(&note).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//! Replies and threads (NIP-10).
+//!
+//! A reply references other events with `e` tags marked `root` (the event
+//! that started the thread), `reply` (the event answered directly) or
+//! `mention`. A direct reply to the root only carries the `root` tag.
+//! Unmarked `e` tags from older clients are read positionally: the first is
+//! the root and the last the event replied to.
+
+use crate::{kind, Event};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    Root,
+    Reply,
+    Mention,
+}
+
+/// An `e` tag: `["e", <id>, <relay>, <marker>]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventRef {
+    pub id: String,
+    pub relay: Option<String>,
+    pub marker: Option<Marker>,
+}
+
+pub fn refs(e: &Event) -> Vec<EventRef> {
+    e.tags
+        .iter()
+        .filter(|t| t.len() > 1 && t[0] == "e")
+        .map(|t| EventRef {
+            id: t[1].clone(),
+            relay: t.get(2).filter(|r| !r.is_empty()).cloned(),
+            marker: match t.get(3).map(String::as_str) {
+                Some("root") => Some(Marker::Root),
+                Some("reply") => Some(Marker::Reply),
+                Some("mention") => Some(Marker::Mention),
+                _ => None,
+            },
+        })
+        .collect()
+}
+
+/// The id of the event that started the thread `e` belongs to.
+pub fn root(e: &Event) -> Option<String> {
+    let refs = refs(e);
+    if refs.iter().any(|r| r.marker.is_some()) {
+        return refs
+            .into_iter()
+            .find(|r| r.marker == Some(Marker::Root))
+            .map(|r| r.id);
+    }
+    refs.into_iter().next().map(|r| r.id)
+}
+
+/// The id of the event `e` directly replies to.
+pub fn parent(e: &Event) -> Option<String> {
+    let refs = refs(e);
+    if refs.iter().any(|r| r.marker.is_some()) {
+        let marked = |m| refs.iter().find(|r| r.marker == Some(m));
+        return marked(Marker::Reply)
+            .or_else(|| marked(Marker::Root))
+            .map(|r| r.id.clone());
+    }
+    refs.last().map(|r| r.id.clone())
+}
+
+/// An unsigned text note by `public_key` replying to `to`. It keeps the
+/// thread's root and notifies the author of `to` and everyone `to` named.
+pub fn reply(public_key: String, content: String, to: &Event) -> Event {
+    let mut e = Event::new(public_key, content, 0);
+    e.kind = kind::TEXT_NOTE;
+    let tag = |id: &str, marker: &str| {
+        vec![
+            "e".to_string(),
+            id.to_string(),
+            String::new(),
+            marker.to_string(),
+        ]
+    };
+    e.tags = match root(to) {
+        Some(root) => vec![tag(&root, "root"), tag(&to.id, "reply")],
+        None => vec![tag(&to.id, "root")],
+    };
+    let mut notified: Vec<&str> = Vec::new();
+    for pk in std::iter::once(to.public_key.as_str()).chain(to.tag_values("p")) {
+        if !notified.contains(&pk) && pk != e.public_key {
+            notified.push(pk);
+        }
+    }
+    for pk in notified {
+        e.tags.push(vec!["p".to_string(), pk.to_string()]);
+    }
+    e
+}
+
+/// An event with the replies to it, oldest first.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Node {
+    pub event: Event,
+    #[serde(default)]
+    pub replies: Vec<Node>,
+}
+
+/// The conversation around an event.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Thread {
+    /// The events above the requested one, root first. Events missing from
+    /// the store leave gaps.
+    pub ancestors: Vec<Event>,
+    /// The requested event and everything below it.
+    pub tree: Node,
+    /// Whether the depth or size limit cut off replies or ancestors.
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, author: &str) -> Event {
+        let mut e = Event::new(author.to_string(), id.to_string(), 0);
+        e.id = id.to_string();
+        e
+    }
+
+    #[test]
+    fn test_reply() {
+        let root_note = note("r", "alice");
+        let first = reply("bob".to_string(), "hi".to_string(), &root_note);
+        assert_eq!(root(&first).as_deref(), Some("r"));
+        assert_eq!(parent(&first).as_deref(), Some("r"));
+        assert_eq!(first.tag_values("p").collect::<Vec<_>>(), vec!["alice"]);
+
+        let mut first = first;
+        first.id = "f".to_string();
+        let second = reply("alice".to_string(), "yo".to_string(), &first);
+        assert_eq!(root(&second).as_deref(), Some("r"));
+        assert_eq!(parent(&second).as_deref(), Some("f"));
+        assert_eq!(second.tag_values("p").collect::<Vec<_>>(), vec!["bob"]);
+    }
+
+    #[test]
+    fn test_positional_and_mentions() {
+        let mut e = note("x", "alice");
+        e.tags = vec![
+            vec!["e".to_string(), "r".to_string()],
+            vec!["e".to_string(), "p".to_string()],
+        ];
+        assert_eq!(root(&e).as_deref(), Some("r"));
+        assert_eq!(parent(&e).as_deref(), Some("p"));
+
+        e.tags = vec![vec![
+            "e".to_string(),
+            "m".to_string(),
+            String::new(),
+            "mention".to_string(),
+        ]];
+        assert_eq!(root(&e), None);
+        assert_eq!(parent(&e), None);
+        assert_eq!(refs(&e)[0].marker, Some(Marker::Mention));
+    }
+}
@@ -1,3 +1,165 @@
-fn main() {
-    api::main();
+use api::Config;
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use entity::Filter;
+use rustr_core::archive::{self, Store};
+use rustr_core::kv::EventRepoKv;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the relay (default)
+    Serve(ServeArgs),
+    /// Write the events and users of a store as JSONL
+    Export {
+        /// Event store file
+        #[arg(long)]
+        db: PathBuf,
+        /// Output file, stdout if omitted
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+        /// Only export events matching this filter, e.g. '{"kinds":[1]}'
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Load a JSONL dump, from rustr or another relay, into a store
+    Import {
+        /// Event store file
+        #[arg(long)]
+        db: PathBuf,
+        /// Dump to import, stdin if omitted
+        file: Option<PathBuf>,
+        /// Keep the roles of imported users instead of the default role
+        #[arg(long)]
+        keep_roles: bool,
+    },
+}
+
+/// Flags override the configuration file and `RUSTR_*` variables.
+#[derive(ClapArgs, Default)]
+struct ServeArgs {
+    /// Configuration file, also taken from RUSTR_CONFIG
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Address to listen on, e.g. 127.0.0.1:3000
+    #[arg(long)]
+    bind: Option<String>,
+    /// memory or redb
+    #[arg(long)]
+    storage_backend: Option<String>,
+    /// Database file of the redb backend
+    #[arg(long)]
+    storage_path: Option<String>,
+    /// Session lifetime in seconds
+    #[arg(long)]
+    session_ttl: Option<String>,
+    /// text or json
+    #[arg(long)]
+    log_format: Option<String>,
+    /// trace, debug, info, warn or error
+    #[arg(long)]
+    log_level: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let result = match args.command {
+        None => serve(ServeArgs::default()),
+        Some(Command::Serve(serve_args)) => serve(serve_args),
+        Some(Command::Export { db, out, filter }) => export(db, out, filter),
+        Some(Command::Import {
+            db,
+            file,
+            keep_roles,
+        }) => import(db, file, keep_roles),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn serve(args: ServeArgs) -> Result<(), String> {
+    let path = args
+        .config
+        .or_else(|| std::env::var_os("RUSTR_CONFIG").map(PathBuf::from));
+    let mut config = Config::load(path.as_deref(), std::env::vars()).map_err(|e| e.to_string())?;
+    let flags = [
+        ("--bind", "bind", args.bind),
+        ("--storage-backend", "storage.backend", args.storage_backend),
+        ("--storage-path", "storage.path", args.storage_path),
+        ("--session-ttl", "session.ttl", args.session_ttl),
+        ("--log-format", "log.format", args.log_format),
+        ("--log-level", "log.level", args.log_level),
+    ];
+    for (flag, key, value) in flags {
+        if let Some(value) = value {
+            config.set(flag, key, &value).map_err(|e| e.to_string())?;
+        }
+    }
+    config.validate().map_err(|e| e.to_string())?;
+    api::main(config)
+}
+
+fn export(db: PathBuf, out: Option<PathBuf>, filter: Option<String>) -> Result<(), String> {
+    let filter: Option<Filter> = filter
+        .map(|f| serde_json::from_str(&f))
+        .transpose()
+        .map_err(|e| format!("invalid filter: {}", e))?;
+    let mut events = EventRepoKv::open(db).map_err(|e| e.to_string())?;
+    let mut users = events.users().map_err(|e| e.to_string())?;
+    let store = Store {
+        events: &mut events,
+        users: Some(&mut users),
+    };
+    let report = match out {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            archive::export(store, filter.as_ref(), BufWriter::new(file))
+        }
+        None => archive::export(store, filter.as_ref(), io::stdout().lock()),
+    }
+    .map_err(|e| e.to_string())?;
+    eprintln!(
+        "exported {} events and {} users",
+        report.events, report.users
+    );
+    Ok(())
+}
+
+fn import(db: PathBuf, file: Option<PathBuf>, keep_roles: bool) -> Result<(), String> {
+    let mut events = EventRepoKv::open(db).map_err(|e| e.to_string())?;
+    let mut users = events.users().map_err(|e| e.to_string())?;
+    let store = Store {
+        events: &mut events,
+        users: Some(&mut users),
+    };
+    let report = match file {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            archive::import(store, BufReader::new(file), keep_roles)
+        }
+        None => archive::import(store, io::stdin().lock(), keep_roles),
+    }
+    .map_err(|e| e.to_string())?;
+    for error in &report.errors {
+        eprintln!("{}", error);
+    }
+    eprintln!(
+        "accepted {}, duplicate {}, rejected {}",
+        report.accepted, report.duplicate, report.rejected
+    );
+    Ok(())
 }